@@ -0,0 +1,8 @@
+CREATE TABLE
+    local_credentials (
+        user_id INTEGER NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+        password_hash VARCHAR NOT NULL,
+        updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+SELECT diesel_manage_updated_at('local_credentials');
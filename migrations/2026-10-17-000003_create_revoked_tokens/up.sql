@@ -0,0 +1,10 @@
+CREATE TABLE
+    revoked_tokens (
+        jti VARCHAR(36) NOT NULL PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        expires_at TIMESTAMP NOT NULL,
+        revoked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+-- Expired revocations are purged periodically
+CREATE INDEX idx_revoked_tokens_expires_at ON revoked_tokens(expires_at);
@@ -0,0 +1,20 @@
+CREATE TABLE
+    roles (
+        id INTEGER NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+        name VARCHAR(50) NOT NULL UNIQUE,
+        description TEXT
+    );
+
+CREATE TABLE
+    user_roles (
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        role_id INTEGER NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+        assigned_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (user_id, role_id)
+    );
+
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'システム管理者'),
+    ('manager', 'マネージャー'),
+    ('user', '一般ユーザー'),
+    ('readonly', '閲覧のみ');
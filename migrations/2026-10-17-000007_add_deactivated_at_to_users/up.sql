@@ -0,0 +1,2 @@
+-- Set by the directory sync when the user is no longer in the directory
+ALTER TABLE users ADD COLUMN deactivated_at TIMESTAMP;
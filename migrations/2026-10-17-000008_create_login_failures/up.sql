@@ -0,0 +1,13 @@
+-- Failed logins per username, also for usernames that do not exist
+CREATE TABLE
+    login_failures (
+        username VARCHAR(255) NOT NULL PRIMARY KEY,
+        failure_count INTEGER NOT NULL DEFAULT 0,
+        first_failed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        last_failed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        locked_until TIMESTAMP,
+        lockout_count INTEGER NOT NULL DEFAULT 0
+    );
+
+-- Stale entries are purged periodically
+CREATE INDEX idx_login_failures_last_failed_at ON login_failures(last_failed_at);
@@ -0,0 +1,16 @@
+-- Long-lived tokens for machine clients, sent as bearer tokens like access tokens
+CREATE TABLE
+    api_tokens (
+        id INTEGER NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        name VARCHAR(100) NOT NULL,
+        token_hash VARCHAR(64) NOT NULL UNIQUE,
+        token_prefix VARCHAR(12) NOT NULL,
+        scopes TEXT[] NOT NULL,
+        expires_at TIMESTAMP NOT NULL,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        last_used_at TIMESTAMP,
+        revoked_at TIMESTAMP
+    );
+
+CREATE INDEX idx_api_tokens_user_id ON api_tokens(user_id);
@@ -0,0 +1,11 @@
+-- OIDC logins between the redirect to the provider and the callback
+CREATE TABLE
+    oidc_logins (
+        state VARCHAR(64) NOT NULL PRIMARY KEY,
+        code_verifier VARCHAR(128) NOT NULL,
+        nonce VARCHAR(64) NOT NULL,
+        expires_at TIMESTAMP NOT NULL
+    );
+
+-- Expired logins are purged whenever a login starts
+CREATE INDEX idx_oidc_logins_expires_at ON oidc_logins(expires_at);
@@ -0,0 +1,32 @@
+-- TOTP second factor of a user; enabled once the first code has been confirmed
+CREATE TABLE
+    user_mfa (
+        user_id INTEGER NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+        secret VARCHAR(64) NOT NULL,
+        enabled_at TIMESTAMP,
+        last_used_step BIGINT,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+-- Single-use codes replacing a lost authenticator
+CREATE TABLE
+    mfa_recovery_codes (
+        id INTEGER NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        code_hash VARCHAR(64) NOT NULL,
+        used_at TIMESTAMP
+    );
+
+CREATE INDEX idx_mfa_recovery_codes_user_id ON mfa_recovery_codes(user_id);
+
+-- Logins that passed the password check and wait for the second factor
+CREATE TABLE
+    mfa_challenges (
+        token_hash VARCHAR(64) NOT NULL PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        expires_at TIMESTAMP NOT NULL
+    );
+
+-- Expired challenges are purged whenever a challenge is created
+CREATE INDEX idx_mfa_challenges_expires_at ON mfa_challenges(expires_at);
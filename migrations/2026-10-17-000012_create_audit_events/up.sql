@@ -0,0 +1,16 @@
+-- Audit trail of what was done on behalf of users, e.g. by an impersonating admin
+CREATE TABLE
+    audit_events (
+        id BIGINT NOT NULL GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+        action VARCHAR(50) NOT NULL,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        impersonator_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+        token_id VARCHAR(36),
+        http_method VARCHAR(10),
+        http_path TEXT,
+        http_status INTEGER,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+CREATE INDEX idx_audit_events_user_id ON audit_events(user_id);
+CREATE INDEX idx_audit_events_impersonator_id ON audit_events(impersonator_id);
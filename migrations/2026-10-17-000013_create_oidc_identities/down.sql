@@ -0,0 +1,3 @@
+ALTER TABLE oidc_logins DROP COLUMN link_user_id;
+DROP TABLE oidc_identities;
+ALTER TABLE users DROP COLUMN source;
@@ -0,0 +1,17 @@
+-- How the user was provisioned; the directory sync only deactivates users of the directory
+ALTER TABLE users ADD COLUMN source VARCHAR(20) NOT NULL DEFAULT 'directory';
+
+-- OIDC identities, by issuer and subject, and the user they log in as
+CREATE TABLE
+    oidc_identities (
+        issuer VARCHAR(255) NOT NULL,
+        subject VARCHAR(255) NOT NULL,
+        user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (issuer, subject)
+    );
+
+CREATE INDEX idx_oidc_identities_user_id ON oidc_identities(user_id);
+
+-- Set for logins started by a logged-in user to link their identity
+ALTER TABLE oidc_logins ADD COLUMN link_user_id INTEGER REFERENCES users(id) ON DELETE CASCADE;
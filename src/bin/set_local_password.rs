@@ -0,0 +1,23 @@
+use rust_api::{create_connection_pool, models::users::usecases::*, services::auth::backend::local::hash_password};
+
+// Usage: set_local_password <login_id> <password>
+// Creates the user if needed and stores a password for AUTH_BACKEND=local
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = std::env::args().collect::<Vec<String>>();
+    let [_, login_id, password] = args.as_slice() else {
+        return Err("Usage: set_local_password <login_id> <password>".into());
+    };
+
+    let pool = create_connection_pool();
+    let mut conn = pool.get()?;
+
+    let user = match search_user(&mut conn, login_id)?.into_iter().next() {
+        Some(user) => user,
+        None => insert_new_user(&mut conn, login_id.clone(), None, None, None, None, None)
+            .map_err(|e| format!("Failed to create user: {}", e))?,
+    };
+
+    set_local_password_hash(&mut conn, user.id, &hash_password(password)?)?;
+    println!("Password set for {} (id: {})", user.login_id, user.id);
+    Ok(())
+}
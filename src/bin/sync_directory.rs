@@ -0,0 +1,16 @@
+use rust_api::{config::get_config, create_connection_pool, services::auth::backend::ldap::LdapBackend};
+
+// Usage: sync_directory
+// Syncs the users table with the LDAP directory once, like the LDAP_SYNC_INTERVAL_SECS job
+#[actix_web::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = get_config()?;
+    let backend = LdapBackend::from_config(&config)?;
+
+    let report = backend.sync_directory(create_connection_pool(), config.get_ldap_sync_page_size()).await?;
+    println!(
+        "Synced {} directory entries: {} created, {} updated, {} reactivated, {} deactivated, {} failed",
+        report.seen, report.created, report.updated, report.reactivated, report.deactivated, report.failed
+    );
+    Ok(())
+}
@@ -5,6 +5,7 @@ use serde::Deserialize;
 pub struct Config {
     pub database_url: String,
     pub test_database_url: String,
+    #[serde(default)]
     pub jwt_secret: String,
     pub ldap_uri: String,
     pub ldap_filter: String,
@@ -12,6 +13,152 @@ pub struct Config {
     pub ldap_user_dn: String,
     pub client_host: Option<String>,
     
+    // LDAP search-then-bind configuration
+    #[serde(default)]
+    pub ldap_bind_mode: Option<String>,
+    #[serde(default)]
+    pub ldap_bind_dn: Option<String>,
+    #[serde(default)]
+    pub ldap_bind_password: Option<String>,
+    #[serde(default)]
+    pub ldap_search_base: Option<String>,
+    
+    // LDAP transport security configuration
+    #[serde(default)]
+    pub ldap_tls_mode: Option<String>,
+    #[serde(default)]
+    pub ldap_allow_cleartext: Option<bool>,
+    #[serde(default)]
+    pub ldap_ca_file: Option<String>,
+    #[serde(default)]
+    pub ldap_client_cert_file: Option<String>,
+    #[serde(default)]
+    pub ldap_client_key_file: Option<String>,
+    #[serde(default)]
+    pub ldap_connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub ldap_operation_timeout_secs: Option<u64>,
+    
+    // LDAP connection pool configuration
+    #[serde(default)]
+    pub ldap_pool_max_size: Option<usize>,
+    #[serde(default)]
+    pub ldap_pool_acquire_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub ldap_pool_idle_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub ldap_pool_health_check_secs: Option<u64>,
+    
+    // LDAP group-based authorization configuration
+    #[serde(default)]
+    pub ldap_deny_groups: Option<String>,
+    #[serde(default)]
+    pub ldap_role_mapping: Option<String>,
+    #[serde(default)]
+    pub ldap_group_source: Option<String>,
+    #[serde(default)]
+    pub ldap_group_search_base: Option<String>,
+    #[serde(default)]
+    pub ldap_group_member_attribute: Option<String>,
+    
+    // LDAP directory sync configuration
+    #[serde(default)]
+    pub ldap_sync_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub ldap_sync_page_size: Option<i32>,
+    
+    // Authentication backend configuration
+    #[serde(default)]
+    pub auth_backend: Option<String>,
+    
+    // OpenID Connect login configuration
+    #[serde(default)]
+    pub oidc_issuer_url: Option<String>,
+    #[serde(default)]
+    pub oidc_client_id: Option<String>,
+    #[serde(default)]
+    pub oidc_client_secret: Option<String>,
+    #[serde(default)]
+    pub oidc_redirect_uri: Option<String>,
+    #[serde(default)]
+    pub oidc_scopes: Option<String>,
+    #[serde(default)]
+    pub oidc_username_claim: Option<String>,
+    #[serde(default)]
+    pub oidc_employee_number_claim: Option<String>,
+    #[serde(default)]
+    pub oidc_groups_claim: Option<String>,
+    #[serde(default)]
+    pub oidc_role_mapping: Option<String>,
+    #[serde(default)]
+    pub oidc_login_ttl_secs: Option<i64>,
+    
+    // Token signing configuration
+    #[serde(default)]
+    pub jwt_algorithm: Option<String>,
+    #[serde(default)]
+    pub jwt_private_key_file: Option<String>,
+    #[serde(default)]
+    pub jwt_public_key_file: Option<String>,
+    #[serde(default)]
+    pub jwt_keyring_file: Option<String>,
+    #[serde(default)]
+    pub jwt_keyring_reload_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub jwt_issuer: Option<String>,
+    #[serde(default)]
+    pub jwt_audience: Option<String>,
+    #[serde(default)]
+    pub jwt_leeway_secs: Option<u64>,
+    
+    // Token lifetime configuration
+    #[serde(default)]
+    pub access_token_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub refresh_token_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub impersonation_token_ttl_secs: Option<i64>,
+    
+    // Token revocation configuration
+    #[serde(default)]
+    pub revocation_cache_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub revocation_purge_interval_secs: Option<u64>,
+    
+    // Current user cache configuration
+    #[serde(default)]
+    pub user_cache_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub user_cache_max_entries: Option<usize>,
+    
+    // Per-account login lockout configuration
+    #[serde(default)]
+    pub login_lockout_threshold: Option<i32>,
+    #[serde(default)]
+    pub login_lockout_window_secs: Option<i64>,
+    #[serde(default)]
+    pub login_lockout_base_secs: Option<i64>,
+    #[serde(default)]
+    pub login_lockout_max_secs: Option<i64>,
+    
+    // API token configuration
+    #[serde(default)]
+    pub api_token_max_ttl_days: Option<i64>,
+    
+    // Two-factor authentication configuration
+    #[serde(default)]
+    pub mfa_required_roles: Option<String>,
+    #[serde(default)]
+    pub mfa_issuer: Option<String>,
+    #[serde(default)]
+    pub mfa_challenge_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub mfa_max_attempts: Option<i32>,
+    
+    // Role-based access control configuration
+    #[serde(default)]
+    pub rbac_default_role: Option<String>,
+    
     // OpenTelemetry configuration
     #[serde(default)]
     pub otel_enabled: Option<bool>,
@@ -28,6 +175,8 @@ pub struct Config {
     #[serde(default)]
     pub cookie_secure: Option<bool>,
     #[serde(default)]
+    pub session_auth_enabled: Option<bool>,
+    #[serde(default)]
     pub environment: Option<String>,
     
     // Rate limiting configuration
@@ -37,6 +186,18 @@ pub struct Config {
     pub rate_limit_requests: Option<usize>,
     #[serde(default)]
     pub rate_limit_period_secs: Option<u64>,
+    #[serde(default)]
+    pub rate_limit_backend_url: Option<String>,
+    #[serde(default)]
+    pub rate_limit_fail_open: Option<bool>,
+    #[serde(default)]
+    pub rate_limit_trust_proxy: Option<bool>,
+    #[serde(default)]
+    pub rate_limit_api_requests: Option<usize>,
+    #[serde(default)]
+    pub rate_limit_api_period_secs: Option<u64>,
+    #[serde(default)]
+    pub rate_limit_api_key_by: Option<String>,
 }
 
 pub fn get_config() -> Result<Config, String> {
@@ -47,6 +208,7 @@ pub fn get_config() -> Result<Config, String> {
     
     // Validate OpenTelemetry configuration
     config.validate_otel_config()?;
+    config.validate_jwt_claims_config()?;
     
     Ok(config)
 }
@@ -57,6 +219,295 @@ impl Config {
         self.otel_enabled.unwrap_or(false)
     }
     
+    /// Returns the authentication backend name ("ldap" or "local")
+    pub fn get_auth_backend(&self) -> String {
+        self.auth_backend
+            .as_ref()
+            .map(|backend| backend.to_lowercase())
+            .unwrap_or_else(|| "ldap".to_string())
+    }
+    
+    /// Returns how LDAP users are bound ("direct" or "search")
+    pub fn get_ldap_bind_mode(&self) -> String {
+        self.ldap_bind_mode
+            .as_ref()
+            .map(|mode| mode.to_lowercase())
+            .unwrap_or_else(|| "direct".to_string())
+    }
+    
+    /// Returns the base DN of the subtree searched for users in "search" bind mode
+    pub fn get_ldap_search_base(&self) -> String {
+        self.ldap_search_base
+            .clone()
+            .unwrap_or_else(|| self.ldap_user_dn.clone())
+    }
+    
+    /// Returns how LDAP connections are secured ("none", "starttls" or "ldaps").
+    ///
+    /// Defaults to "ldaps" for `ldaps://` URIs and "starttls" otherwise.
+    pub fn get_ldap_tls_mode(&self) -> String {
+        self.ldap_tls_mode
+            .as_ref()
+            .map(|mode| mode.to_lowercase())
+            .unwrap_or_else(|| {
+                if self.ldap_uri.to_lowercase().starts_with("ldaps://") {
+                    "ldaps".to_string()
+                } else {
+                    "starttls".to_string()
+                }
+            })
+    }
+    
+    /// Returns whether binds over an unencrypted LDAP connection are allowed
+    pub fn is_ldap_cleartext_allowed(&self) -> bool {
+        self.ldap_allow_cleartext.unwrap_or(false)
+    }
+    
+    /// Returns the LDAP connect timeout in seconds
+    pub fn get_ldap_connect_timeout_secs(&self) -> u64 {
+        self.ldap_connect_timeout_secs.unwrap_or(5)
+    }
+    
+    /// Returns the timeout of a single LDAP operation in seconds
+    pub fn get_ldap_operation_timeout_secs(&self) -> u64 {
+        self.ldap_operation_timeout_secs.unwrap_or(10)
+    }
+    
+    /// Returns the maximum number of pooled LDAP service account connections
+    pub fn get_ldap_pool_max_size(&self) -> usize {
+        self.ldap_pool_max_size.unwrap_or(10)
+    }
+    
+    /// Returns how long a login waits for a pooled LDAP connection, in seconds
+    pub fn get_ldap_pool_acquire_timeout_secs(&self) -> u64 {
+        self.ldap_pool_acquire_timeout_secs.unwrap_or(5)
+    }
+    
+    /// Returns after how many idle seconds a pooled LDAP connection is closed
+    pub fn get_ldap_pool_idle_timeout_secs(&self) -> u64 {
+        self.ldap_pool_idle_timeout_secs.unwrap_or(5 * 60)
+    }
+    
+    /// Returns after how many idle seconds a pooled LDAP connection is checked before reuse
+    pub fn get_ldap_pool_health_check_secs(&self) -> u64 {
+        self.ldap_pool_health_check_secs.unwrap_or(30)
+    }
+    
+    /// Returns the `;`-separated groups whose members may not log in.
+    ///
+    /// Defaults to "Partner", the group that was denied before this was configurable.
+    pub fn get_ldap_deny_groups(&self) -> String {
+        self.ldap_deny_groups
+            .clone()
+            .unwrap_or_else(|| "Partner".to_string())
+    }
+    
+    /// Returns the `;`-separated `group:role` entries granting roles to group members
+    pub fn get_ldap_role_mapping(&self) -> String {
+        self.ldap_role_mapping.clone().unwrap_or_default()
+    }
+    
+    /// Returns how a user's groups are looked up ("member_of" or "search")
+    pub fn get_ldap_group_source(&self) -> String {
+        self.ldap_group_source
+            .clone()
+            .unwrap_or_else(|| "member_of".to_string())
+    }
+    
+    /// Returns the base DN of the subtree searched for groups when LDAP_GROUP_SOURCE=search
+    pub fn get_ldap_group_search_base(&self) -> String {
+        self.ldap_group_search_base
+            .clone()
+            .unwrap_or_else(|| self.get_ldap_search_base())
+    }
+    
+    /// Returns the group attribute listing member DNs when LDAP_GROUP_SOURCE=search
+    pub fn get_ldap_group_member_attribute(&self) -> String {
+        self.ldap_group_member_attribute
+            .clone()
+            .unwrap_or_else(|| "member".to_string())
+    }
+    
+    /// Returns the interval between directory syncs in seconds, or None if the sync is disabled
+    pub fn get_ldap_sync_interval_secs(&self) -> Option<u64> {
+        self.ldap_sync_interval_secs.filter(|&secs| secs > 0)
+    }
+    
+    /// Returns the number of entries requested per page of the directory sync search
+    pub fn get_ldap_sync_page_size(&self) -> i32 {
+        self.ldap_sync_page_size.unwrap_or(500)
+    }
+    
+    /// Returns the OpenID Connect issuer, or None if OIDC login is disabled
+    pub fn get_oidc_issuer_url(&self) -> Option<String> {
+        self.oidc_issuer_url
+            .as_ref()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+    }
+    
+    /// Returns the space-separated scopes requested from the OIDC provider
+    pub fn get_oidc_scopes(&self) -> String {
+        self.oidc_scopes
+            .clone()
+            .unwrap_or_else(|| "openid profile email".to_string())
+    }
+    
+    /// Returns the ID token claim used as the login ID
+    pub fn get_oidc_username_claim(&self) -> String {
+        self.oidc_username_claim
+            .clone()
+            .unwrap_or_else(|| "preferred_username".to_string())
+    }
+    
+    /// Returns the ID token claim listing the user's groups
+    pub fn get_oidc_groups_claim(&self) -> String {
+        self.oidc_groups_claim
+            .clone()
+            .unwrap_or_else(|| "groups".to_string())
+    }
+    
+    /// Returns the `;`-separated `group:role` entries granting roles to members of OIDC groups
+    pub fn get_oidc_role_mapping(&self) -> String {
+        self.oidc_role_mapping.clone().unwrap_or_default()
+    }
+    
+    /// Returns how long a started OIDC login may take to come back to the callback, in seconds
+    pub fn get_oidc_login_ttl_secs(&self) -> i64 {
+        self.oidc_login_ttl_secs.unwrap_or(10 * 60)
+    }
+    
+    /// Returns the token signing algorithm (HS256, RS256, ES256 or EdDSA)
+    pub fn get_jwt_algorithm(&self) -> Result<jsonwebtoken::Algorithm, String> {
+        crate::jwt::parse_algorithm(self.jwt_algorithm.as_deref().unwrap_or("HS256"))
+            .map_err(|e| format!("Invalid JWT_ALGORITHM: {}", e))
+    }
+    
+    /// Returns the interval between reloads of `JWT_KEYRING_FILE` in seconds
+    pub fn get_jwt_keyring_reload_interval_secs(&self) -> u64 {
+        self.jwt_keyring_reload_interval_secs.unwrap_or(5 * 60)
+    }
+    
+    /// Returns the `iss` claim put into and required from access tokens
+    pub fn get_jwt_issuer(&self) -> String {
+        self.jwt_issuer
+            .clone()
+            .unwrap_or_else(|| self.default_jwt_claim())
+    }
+    
+    /// Returns the `aud` claim put into and required from access tokens
+    pub fn get_jwt_audience(&self) -> String {
+        self.jwt_audience
+            .clone()
+            .unwrap_or_else(|| self.default_jwt_claim())
+    }
+    
+    /// Default `iss` and `aud`, which include `ENVIRONMENT` so that environments reject each other's tokens
+    fn default_jwt_claim(&self) -> String {
+        match self.environment.as_deref().map(str::trim).filter(|env| !env.is_empty()) {
+            Some(env) => format!("rust-api-{}", env.to_lowercase()),
+            None => "rust-api".to_string(),
+        }
+    }
+    
+    /// Returns the allowed clock skew for `exp`, `nbf` and `iat` in seconds
+    pub fn get_jwt_leeway_secs(&self) -> u64 {
+        self.jwt_leeway_secs.unwrap_or(60)
+    }
+    
+    /// Returns the access token lifetime in seconds
+    pub fn get_access_token_ttl_secs(&self) -> i64 {
+        self.access_token_ttl_secs.unwrap_or(15 * 60)
+    }
+    
+    /// Returns the refresh token lifetime in seconds
+    pub fn get_refresh_token_ttl_secs(&self) -> i64 {
+        self.refresh_token_ttl_secs.unwrap_or(14 * 24 * 60 * 60)
+    }
+    
+    /// Returns the lifetime of an impersonation token in seconds; they cannot be refreshed
+    pub fn get_impersonation_token_ttl_secs(&self) -> i64 {
+        self.impersonation_token_ttl_secs.unwrap_or(10 * 60)
+    }
+    
+    /// Returns how long a "not revoked" answer is cached, in seconds
+    pub fn get_revocation_cache_ttl_secs(&self) -> i64 {
+        self.revocation_cache_ttl_secs.unwrap_or(10)
+    }
+    
+    /// Returns the interval between purges of expired revocations, in seconds
+    pub fn get_revocation_purge_interval_secs(&self) -> u64 {
+        self.revocation_purge_interval_secs.unwrap_or(60 * 60)
+    }
+    
+    /// Returns how long users resolved for requests are cached, in seconds; 0 disables the cache
+    pub fn get_user_cache_ttl_secs(&self) -> i64 {
+        self.user_cache_ttl_secs.unwrap_or(30)
+    }
+    
+    /// Returns how many users are cached at most; 0 disables the cache
+    pub fn get_user_cache_max_entries(&self) -> usize {
+        self.user_cache_max_entries.unwrap_or(10_000)
+    }
+    
+    /// Returns the number of failed logins within the window that locks an account; 0 disables lockout
+    pub fn get_login_lockout_threshold(&self) -> i32 {
+        self.login_lockout_threshold.unwrap_or(5)
+    }
+    
+    /// Returns how long failed logins of an account are remembered, in seconds
+    pub fn get_login_lockout_window_secs(&self) -> i64 {
+        self.login_lockout_window_secs.unwrap_or(15 * 60)
+    }
+    
+    /// Returns the duration of the first lockout in seconds; each further lockout doubles it
+    pub fn get_login_lockout_base_secs(&self) -> i64 {
+        self.login_lockout_base_secs.unwrap_or(60)
+    }
+    
+    /// Returns the upper bound of a lockout in seconds
+    pub fn get_login_lockout_max_secs(&self) -> i64 {
+        self.login_lockout_max_secs.unwrap_or(60 * 60)
+    }
+    
+    /// Returns the longest lifetime an API token may be created with, in days
+    pub fn get_api_token_max_ttl_days(&self) -> i64 {
+        self.api_token_max_ttl_days.unwrap_or(365)
+    }
+    
+    /// Returns the `;`-separated roles whose holders must log in with a second factor
+    pub fn get_mfa_required_roles(&self) -> String {
+        self.mfa_required_roles.clone().unwrap_or_default()
+    }
+    
+    /// Returns the issuer shown by authenticator apps
+    pub fn get_mfa_issuer(&self) -> String {
+        self.mfa_issuer
+            .clone()
+            .unwrap_or_else(|| "rust-api".to_string())
+    }
+    
+    /// Returns how long the challenge token of a two-step login is valid, in seconds
+    pub fn get_mfa_challenge_ttl_secs(&self) -> i64 {
+        self.mfa_challenge_ttl_secs.unwrap_or(5 * 60)
+    }
+    
+    /// Returns how many wrong codes a challenge token accepts before it is invalidated
+    pub fn get_mfa_max_attempts(&self) -> i32 {
+        self.mfa_max_attempts.unwrap_or(5)
+    }
+    
+    /// Returns the role every authenticated user holds in addition to their own.
+    ///
+    /// Defaults to "user"; an empty value grants nothing beyond the user's roles.
+    pub fn get_rbac_default_role(&self) -> Option<String> {
+        match self.rbac_default_role.as_deref() {
+            None => Some("user".to_string()),
+            Some("") => None,
+            Some(role) => Some(role.to_string()),
+        }
+    }
+    
     /// Returns the session secret key
     pub fn get_session_secret(&self) -> Vec<u8> {
         if let Some(secret) = &self.session_secret {
@@ -75,6 +526,11 @@ impl Config {
         })
     }
     
+    /// Returns whether logins keep the tokens in the session cookie for browser clients
+    pub fn is_session_auth_enabled(&self) -> bool {
+        self.session_auth_enabled.unwrap_or(false)
+    }
+    
     /// Returns whether the environment is production
     pub fn is_production(&self) -> bool {
         self.environment
@@ -88,16 +544,52 @@ impl Config {
         self.rate_limit_enabled.unwrap_or(true)
     }
     
-    /// Returns the maximum number of requests allowed per period
+    /// Returns the maximum number of login requests allowed per period and client IP
     pub fn get_rate_limit_requests(&self) -> usize {
         self.rate_limit_requests.unwrap_or(5)
     }
     
-    /// Returns the rate limit period in seconds
+    /// Returns the login rate limit period in seconds
     pub fn get_rate_limit_period_secs(&self) -> u64 {
         self.rate_limit_period_secs.unwrap_or(60)
     }
     
+    /// Returns where request counts are kept: a Redis URL, or "memory://" for this process only
+    pub fn get_rate_limit_backend_url(&self) -> String {
+        self.rate_limit_backend_url
+            .clone()
+            .unwrap_or_else(|| "memory://".to_string())
+    }
+    
+    /// Returns whether requests pass when the rate limit backend fails, instead of being rejected with 503
+    pub fn is_rate_limit_fail_open(&self) -> bool {
+        self.rate_limit_fail_open.unwrap_or(false)
+    }
+    
+    /// Returns whether the client IP is taken from `Forwarded` / `X-Forwarded-For` instead of the peer address.
+    ///
+    /// Disable when clients connect directly, since they can forge these headers.
+    pub fn is_rate_limit_trust_proxy(&self) -> bool {
+        self.rate_limit_trust_proxy.unwrap_or(true)
+    }
+    
+    /// Returns the maximum number of `/api` requests allowed per period and client; 0 disables the limit
+    pub fn get_rate_limit_api_requests(&self) -> usize {
+        self.rate_limit_api_requests.unwrap_or(300)
+    }
+    
+    /// Returns the `/api` rate limit period in seconds
+    pub fn get_rate_limit_api_period_secs(&self) -> u64 {
+        self.rate_limit_api_period_secs.unwrap_or(60)
+    }
+    
+    /// Returns what `/api` requests are counted by ("ip", "user" or "api_key")
+    pub fn get_rate_limit_api_key_by(&self) -> String {
+        self.rate_limit_api_key_by
+            .clone()
+            .unwrap_or_else(|| "user".to_string())
+    }
+    
     /// Returns the OpenTelemetry endpoint with default value
     pub fn get_otel_endpoint(&self) -> String {
         self.otel_endpoint
@@ -119,6 +611,15 @@ impl Config {
             .unwrap_or_else(|| "0.1.0".to_string())
     }
     
+    /// Validates the token claim configuration
+    /// Returns an error message if production does not set its own issuer and audience
+    pub fn validate_jwt_claims_config(&self) -> Result<(), String> {
+        if self.is_production() && (self.jwt_issuer.is_none() || self.jwt_audience.is_none()) {
+            return Err("JWT_ISSUER and JWT_AUDIENCE must be set when ENVIRONMENT is production".to_string());
+        }
+        Ok(())
+    }
+    
     /// Validates OpenTelemetry configuration
     /// Returns an error message if the configuration is invalid
     pub fn validate_otel_config(&self) -> Result<(), String> {
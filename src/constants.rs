@@ -6,17 +6,39 @@
 // API prefix for all authenticated endpoints
 pub const API_PREFIX: &str = "/api";
 
+// Prefix for token management endpoints that do not require a bearer token
+pub const AUTH_PREFIX: &str = "/auth";
+
 // API tags for OpenAPI documentation
 pub mod tags {
     pub const AUTH: &str = "auth";
     pub const USERS: &str = "users";
     pub const CUSTOMERS: &str = "customers";
+    pub const TOKENS: &str = "tokens";
+    pub const MFA: &str = "mfa";
+    pub const ADMIN: &str = "admin";
+}
+
+// Permissions required by API routes, granted to roles in `rbac`
+pub mod permissions {
+    pub const CUSTOMERS_READ: &str = "customers:read";
+    pub const CUSTOMERS_WRITE: &str = "customers:write";
+    pub const USERS_READ: &str = "users:read";
+    pub const USERS_UNLOCK: &str = "users:unlock";
+    pub const USERS_IMPERSONATE: &str = "users:impersonate";
+    pub const TOKENS_MANAGE: &str = "tokens:manage";
+    pub const TOKENS_INTROSPECT: &str = "tokens:introspect";
+    pub const MFA_MANAGE: &str = "mfa:manage";
+    pub const IDENTITIES_LINK: &str = "identities:link";
 }
 
 // API paths
 pub mod paths {
     pub const USERS: &str = "/users";
     pub const CUSTOMERS: &str = "/customers";
+    pub const TOKENS: &str = "/tokens";
+    pub const MFA: &str = "/mfa";
+    pub const ADMIN: &str = "/admin";
 }
 
 // API context paths for OpenAPI documentation
@@ -30,4 +52,16 @@ pub mod context_paths {
     pub fn customers() -> String {
         format!("{}{}", API_PREFIX, super::paths::CUSTOMERS)
     }
+    
+    pub fn tokens() -> String {
+        format!("{}{}", API_PREFIX, super::paths::TOKENS)
+    }
+    
+    pub fn mfa() -> String {
+        format!("{}{}", API_PREFIX, super::paths::MFA)
+    }
+    
+    pub fn admin() -> String {
+        format!("{}{}", API_PREFIX, super::paths::ADMIN)
+    }
 }
@@ -14,6 +14,8 @@ pub enum ServiceError {
     DatabaseError { message: String },
     #[display("Authentication Error: {message}")]
     AuthenticationError { message: String },
+    #[display("Forbidden: {message}")]
+    Forbidden { message: String },
 }
 
 impl error::ResponseError for ServiceError {
@@ -94,6 +96,27 @@ impl error::ResponseError for ServiceError {
                     }))
                 }
             }
+            ServiceError::Forbidden { message } => {
+                // Log authorization failure
+                tracing::warn!(
+                    error.type = "forbidden",
+                    error.message = %message,
+                    "Authorization error occurred"
+                );
+                
+                if is_production {
+                    HttpResponse::Forbidden().json(json!({
+                        "error": "Forbidden",
+                        "message": "You do not have permission to perform this action"
+                    }))
+                } else {
+                    HttpResponse::Forbidden().json(json!({
+                        "error": "Forbidden",
+                        "message": message,
+                        "note": "Detailed errors are hidden in production"
+                    }))
+                }
+            }
         }
     }
 }
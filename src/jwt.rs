@@ -0,0 +1,438 @@
+//! JWT encoding and decoding shared by the auth endpoints and middleware
+//!
+//! Tokens are signed with HS256 and `JWT_SECRET` unless `JWT_ALGORITHM`
+//! selects an asymmetric algorithm (RS256, ES256 or EdDSA), in which case the
+//! PEM keys from `JWT_PRIVATE_KEY_FILE` and `JWT_PUBLIC_KEY_FILE` are used.
+//!
+//! For key rotation `JWT_KEYRING_FILE` points to a JSON keyring instead:
+//!
+//! ```json
+//! {
+//!   "active": "2026-10",
+//!   "keys": [
+//!     { "kid": "2026-10", "algorithm": "EdDSA", "private_key_file": "/keys/2026-10.pem", "public_key_file": "/keys/2026-10.pub.pem" },
+//!     { "kid": "2026-07", "algorithm": "EdDSA", "public_key_file": "/keys/2026-07.pub.pem" }
+//!   ]
+//! }
+//! ```
+//!
+//! Only the active key signs; every key verifies tokens carrying its `kid`.
+//! The keyring is re-read periodically, so keys can be added and retired
+//! without a restart. Public keys are published at `/.well-known/jwks.json`.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType, ThumbprintHash};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use simple_asn1::{from_der, ASN1Block};
+use crate::{config::{self, Config}, middleware::{Actor, UserClaims}};
+
+lazy_static! {
+    static ref KEYRING: RwLock<Option<Arc<Keyring>>> = RwLock::new(None);
+}
+
+/// Parses a supported signing algorithm name
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, String> {
+    match name {
+        "HS256" => Ok(Algorithm::HS256),
+        "RS256" => Ok(Algorithm::RS256),
+        "ES256" => Ok(Algorithm::ES256),
+        "EdDSA" => Ok(Algorithm::EdDSA),
+        other => Err(format!("Unsupported JWT algorithm '{}'. Must be one of HS256, RS256, ES256, EdDSA", other)),
+    }
+}
+
+/// Contents of `JWT_KEYRING_FILE`
+#[derive(Debug, Deserialize)]
+struct KeyringFile {
+    active: String,
+    keys: Vec<KeyringFileEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyringFileEntry {
+    kid: String,
+    algorithm: String,
+    /// Hex encoded HMAC secret for HS256, in the same format as `JWT_SECRET`
+    secret: Option<String>,
+    private_key_file: Option<String>,
+    public_key_file: Option<String>,
+}
+
+/// A single signing or verification key
+struct KeyEntry {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    /// `None` for verify-only keys
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    /// Public key as published in the JWKS, `None` for HMAC
+    jwk: Option<Jwk>,
+}
+
+impl KeyEntry {
+    fn hmac(kid: Option<String>, secret: &[u8]) -> Self {
+        KeyEntry {
+            kid,
+            algorithm: Algorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// Loads an asymmetric key; without `private_pem` the key can only verify.
+    ///
+    /// Without `kid` the RFC 7638 thumbprint of the public key is used.
+    fn asymmetric(kid: Option<String>, algorithm: Algorithm, private_pem: Option<&[u8]>, public_pem: &[u8]) -> Result<Self, String> {
+        let decoding = match algorithm {
+            Algorithm::RS256 => DecodingKey::from_rsa_pem(public_pem),
+            Algorithm::ES256 => DecodingKey::from_ec_pem(public_pem),
+            Algorithm::EdDSA => DecodingKey::from_ed_pem(public_pem),
+            other => return Err(format!("Unsupported JWT algorithm: {:?}", other)),
+        }
+        .map_err(|e| format!("Invalid JWT public key: {}", e))?;
+
+        let encoding = private_pem
+            .map(|pem| match algorithm {
+                Algorithm::RS256 => EncodingKey::from_rsa_pem(pem),
+                Algorithm::ES256 => EncodingKey::from_ec_pem(pem),
+                _ => EncodingKey::from_ed_pem(pem),
+            })
+            .transpose()
+            .map_err(|e| format!("Invalid JWT private key: {}", e))?;
+
+        let mut jwk = public_jwk(algorithm, public_pem)?;
+        jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+        let kid = kid.unwrap_or_else(|| jwk.thumbprint(ThumbprintHash::SHA256));
+        jwk.common.key_id = Some(kid.clone());
+
+        Ok(KeyEntry { kid: Some(kid), algorithm, encoding, decoding, jwk: Some(jwk) })
+    }
+}
+
+/// The active signing key and every key that is still accepted for verification
+pub struct Keyring {
+    keys: Vec<KeyEntry>,
+    /// Index of the active key in `keys`
+    active: usize,
+}
+
+impl Keyring {
+    /// Loads `JWT_KEYRING_FILE`, or the single key selected by `JWT_ALGORITHM`
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        if let Some(path) = &config.jwt_keyring_file {
+            return Self::from_file(path);
+        }
+
+        let algorithm = config.get_jwt_algorithm()?;
+        let key = if algorithm == Algorithm::HS256 {
+            KeyEntry::hmac(None, &secret(config)?)
+        } else {
+            let private_pem = read_pem(config.jwt_private_key_file.as_deref(), "JWT_PRIVATE_KEY_FILE")?;
+            let public_pem = read_pem(config.jwt_public_key_file.as_deref(), "JWT_PUBLIC_KEY_FILE")?;
+            KeyEntry::asymmetric(None, algorithm, Some(&private_pem), &public_pem)?
+        };
+
+        Ok(Keyring { keys: vec![key], active: 0 })
+    }
+
+    fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read JWT_KEYRING_FILE '{}': {}", path, e))?;
+        let file: KeyringFile = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid JWT_KEYRING_FILE '{}': {}", path, e))?;
+
+        let mut keys = Vec::with_capacity(file.keys.len());
+        for entry in file.keys {
+            if keys.iter().any(|key: &KeyEntry| key.kid.as_deref() == Some(entry.kid.as_str())) {
+                return Err(format!("Duplicate kid '{}' in JWT_KEYRING_FILE", entry.kid));
+            }
+
+            let algorithm = parse_algorithm(&entry.algorithm)?;
+            let key = if algorithm == Algorithm::HS256 {
+                let secret = entry.secret.as_deref()
+                    .ok_or_else(|| format!("Key '{}' requires a secret", entry.kid))
+                    .and_then(parse_secret)?;
+                KeyEntry::hmac(Some(entry.kid), &secret)
+            } else {
+                let public_pem = read_pem(entry.public_key_file.as_deref(), "public_key_file")?;
+                let private_pem = entry.private_key_file.as_deref()
+                    .map(|path| read_pem(Some(path), "private_key_file"))
+                    .transpose()?;
+                KeyEntry::asymmetric(Some(entry.kid), algorithm, private_pem.as_deref(), &public_pem)?
+            };
+            keys.push(key);
+        }
+
+        let active = keys.iter()
+            .position(|key| key.kid.as_deref() == Some(file.active.as_str()))
+            .ok_or_else(|| format!("Active key '{}' is not in JWT_KEYRING_FILE", file.active))?;
+        if keys[active].encoding.is_none() {
+            return Err(format!("Active key '{}' has no private key", file.active));
+        }
+
+        Ok(Keyring { keys, active })
+    }
+
+    /// Returns the `kid` of the active signing key, if it has one
+    pub fn active_key_id(&self) -> Option<&str> {
+        self.keys[self.active].kid.as_deref()
+    }
+
+    /// Signs the claims with the active key
+    pub fn encode(&self, claims: &UserClaims) -> Result<String, String> {
+        let key = &self.keys[self.active];
+        let encoding = key.encoding.as_ref()
+            .ok_or_else(|| "Active JWT key cannot sign".to_string())?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = key.kid.clone();
+
+        encode(&header, claims, encoding)
+            .map_err(|e| format!("Token generation error: {}", e))
+    }
+
+    /// Checks the signature and the claims of a token against `policy`.
+    ///
+    /// The key is selected by the `kid` header. Tokens without one are tried
+    /// against every key of the same algorithm, active key first.
+    pub fn decode(&self, token: &str, policy: &ClaimsPolicy) -> Result<TokenData<UserClaims>, String> {
+        let header = decode_header(token)
+            .map_err(|e| format!("Token validation error: {}", e))?;
+
+        let candidates: Vec<&KeyEntry> = match &header.kid {
+            Some(kid) => self.keys.iter()
+                .filter(|key| key.kid.as_deref() == Some(kid.as_str()))
+                .collect(),
+            None => std::iter::once(&self.keys[self.active])
+                .chain(self.keys.iter().enumerate().filter(|(i, _)| *i != self.active).map(|(_, key)| key))
+                .filter(|key| key.algorithm == header.alg)
+                .collect(),
+        };
+
+        let mut last_error = format!("Token validation error: no key for kid {:?}", header.kid);
+        for key in candidates {
+            match decode::<UserClaims>(token, &key.decoding, &policy.validation(key.algorithm)) {
+                Ok(data) => return policy.check_issued_at(&data.claims).map(|_| data),
+                Err(e) => last_error = format!("Token validation error: {}", e),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Returns the public keys; HMAC secrets are never published
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect() }
+    }
+}
+
+/// Issuer, audience and clock leeway that access tokens must satisfy
+#[derive(Clone, Debug)]
+pub struct ClaimsPolicy {
+    pub issuer: String,
+    pub audience: String,
+    pub leeway_secs: u64,
+}
+
+impl ClaimsPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        ClaimsPolicy {
+            issuer: config.get_jwt_issuer(),
+            audience: config.get_jwt_audience(),
+            leeway_secs: config.get_jwt_leeway_secs(),
+        }
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+        validation.validate_nbf = true;
+        validation.leeway = self.leeway_secs;
+        validation
+    }
+
+    /// Rejects tokens claiming to be issued in the future; `jsonwebtoken` does not check `iat`
+    fn check_issued_at(&self, claims: &UserClaims) -> Result<(), String> {
+        if claims.iat > chrono::Utc::now().timestamp() + self.leeway_secs as i64 {
+            return Err("Token validation error: token issued in the future".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current keyring, loading it on first use
+pub fn keyring(config: &Config) -> Result<Arc<Keyring>, String> {
+    if let Some(keyring) = KEYRING.read().map_err(|e| e.to_string())?.as_ref() {
+        return Ok(keyring.clone());
+    }
+
+    reload_keyring(config)
+}
+
+/// Reloads the keyring from `config`, replacing the current one.
+///
+/// On error the current keyring stays in use.
+pub fn reload_keyring(config: &Config) -> Result<Arc<Keyring>, String> {
+    let keyring = Arc::new(Keyring::from_config(config)?);
+    *KEYRING.write().map_err(|e| e.to_string())? = Some(keyring.clone());
+    Ok(keyring)
+}
+
+/// Reloads the keyring every `period` on the current runtime
+pub fn spawn_reload_task(period: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(period);
+        // The first tick completes immediately and the keyring was just loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+
+            match config::get_config().and_then(|config| reload_keyring(&config)) {
+                Ok(keyring) => tracing::debug!(active_kid = ?keyring.active_key_id(), "JWT keyring reloaded"),
+                Err(e) => tracing::error!(error = %e, "Failed to reload JWT keyring, keeping the current keys"),
+            }
+        }
+    });
+}
+
+/// Parses `JWT_SECRET` ("18 A6 77 ...") into the raw HMAC key
+pub fn secret(config: &Config) -> Result<Vec<u8>, String> {
+    parse_secret(&config.jwt_secret)
+}
+
+fn parse_secret(secret: &str) -> Result<Vec<u8>, String> {
+    secret.split(" ")
+        .map(|hex_str| u8::from_str_radix(hex_str, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| format!("JWT secret configuration error: {}", e))
+}
+
+/// Returns the claims of a new access token that expires after `ACCESS_TOKEN_TTL_SECS`
+pub fn new_claims(config: &Config, user_id: i32, username: &str) -> UserClaims {
+    let now = chrono::Utc::now().timestamp();
+
+    UserClaims {
+        id: user_id,
+        username: username.to_string(),
+        iss: config.get_jwt_issuer(),
+        aud: config.get_jwt_audience(),
+        iat: now,
+        nbf: now,
+        exp: now + config.get_access_token_ttl_secs(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        roles: Vec::new(),
+        act: None,
+    }
+}
+
+/// Issues an access token for the user carrying their roles
+pub fn issue_access_token(config: &Config, user_id: i32, username: &str, roles: &[String]) -> Result<String, String> {
+    let claims = UserClaims { roles: roles.to_vec(), ..new_claims(config, user_id, username) };
+    keyring(config)?.encode(&claims)
+}
+
+/// Issues a token for `actor` to act as the user, which expires after `IMPERSONATION_TOKEN_TTL_SECS`.
+///
+/// Returns the token and its claims.
+pub fn issue_impersonation_token(config: &Config, user_id: i32, username: &str, roles: &[String], actor: Actor) -> Result<(String, UserClaims), String> {
+    let claims = new_claims(config, user_id, username);
+    let claims = UserClaims {
+        exp: claims.iat + config.get_impersonation_token_ttl_secs(),
+        roles: roles.to_vec(),
+        act: Some(actor),
+        ..claims
+    };
+    let token = keyring(config)?.encode(&claims)?;
+    Ok((token, claims))
+}
+
+/// Decodes an access token and checks its signature and claims
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn decode_access_token(config: &Config, token: &str) -> Result<TokenData<UserClaims>, String> {
+    keyring(config)?.decode(token, &ClaimsPolicy::from_config(config))
+}
+
+fn read_pem(path: Option<&str>, name: &str) -> Result<Vec<u8>, String> {
+    let path = path.ok_or_else(|| format!("{} is required for asymmetric JWT algorithms", name))?;
+    std::fs::read(path).map_err(|e| format!("Failed to read {} '{}': {}", name, path, e))
+}
+
+/// Builds the JWK of a public key from its SubjectPublicKeyInfo PEM.
+///
+/// `Jwk::from_encoding_key` needs the private key and does not support
+/// EdDSA, so verify-only keys are converted here.
+fn public_jwk(algorithm: Algorithm, public_pem: &[u8]) -> Result<Jwk, String> {
+    let invalid = |reason: &str| format!("Invalid JWT public key: {}", reason);
+
+    let pem = std::str::from_utf8(public_pem).map_err(|e| invalid(&e.to_string()))?;
+    let body: String = pem.lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = STANDARD.decode(body.trim()).map_err(|e| invalid(&e.to_string()))?;
+
+    // SubjectPublicKeyInfo ::= SEQUENCE { algorithm AlgorithmIdentifier, subjectPublicKey BIT STRING }
+    let blocks = from_der(&der).map_err(|e| invalid(&e.to_string()))?;
+    let key = match blocks.first() {
+        Some(ASN1Block::Sequence(_, items)) => match items.get(1) {
+            Some(ASN1Block::BitString(_, _, key)) => key,
+            _ => return Err(invalid("missing subjectPublicKey")),
+        },
+        _ => return Err(invalid("not a SubjectPublicKeyInfo")),
+    };
+
+    let (key_algorithm, parameters) = match algorithm {
+        Algorithm::RS256 => {
+            // RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+            let blocks = from_der(key).map_err(|e| invalid(&e.to_string()))?;
+            let Some(ASN1Block::Sequence(_, items)) = blocks.first() else {
+                return Err(invalid("not an RSA public key"));
+            };
+            let [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] = items.as_slice() else {
+                return Err(invalid("not an RSA public key"));
+            };
+            (KeyAlgorithm::RS256, AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(n.to_bytes_be().1),
+                e: URL_SAFE_NO_PAD.encode(e.to_bytes_be().1),
+            }))
+        }
+        Algorithm::ES256 => {
+            // Uncompressed point: 0x04 || x || y
+            let Some(point) = key.strip_prefix(&[0x04]).filter(|point| point.len() == 64) else {
+                return Err(invalid("not a P-256 public key"));
+            };
+            (KeyAlgorithm::ES256, AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(&point[..32]),
+                y: URL_SAFE_NO_PAD.encode(&point[32..]),
+            }))
+        }
+        Algorithm::EdDSA => {
+            if key.len() != 32 {
+                return Err(invalid("not an Ed25519 public key"));
+            }
+            (KeyAlgorithm::EdDSA, AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key),
+            }))
+        }
+        other => return Err(format!("Unsupported JWT algorithm: {:?}", other)),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            key_algorithm: Some(key_algorithm),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
@@ -54,6 +54,14 @@ pub mod errors;
 pub mod traits;
 pub mod metrics;
 pub mod constants;
+pub mod jwt;
+pub mod revocation;
+pub mod rbac;
+pub mod lockout;
+pub mod rate_limit;
+pub mod mfa;
+pub mod session;
+pub mod user_cache;
 
 /// Initialize OpenTelemetry tracing and metrics with OTLP exporter
 /// 
@@ -0,0 +1,145 @@
+//! Per-account login lockout
+//!
+//! Failed logins are counted per username in `login_failures`, so that a
+//! guessing attack against one account is slowed down no matter how many
+//! client IPs it comes from. `LOGIN_LOCKOUT_THRESHOLD` failures within
+//! `LOGIN_LOCKOUT_WINDOW_SECS` lock the account for `LOGIN_LOCKOUT_BASE_SECS`,
+//! doubling with every further lockout up to `LOGIN_LOCKOUT_MAX_SECS`. Locks
+//! expire on their own or are lifted by an admin.
+
+use std::time::Duration;
+use actix_web::web;
+use chrono::{NaiveDateTime, Utc};
+use crate::{config::Config, errors::ServiceError, metrics::AuthMetrics, DbPool};
+use crate::models::login_failures::usecases::{
+    clear_login_failures, count_locked_accounts, find_login_lock, purge_stale_login_failures, record_login_failure,
+};
+
+/// Interval at which stale entries are purged and the number of locked accounts is recorded
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Thresholds and durations of the lockout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LockoutPolicy {
+    pub threshold: i32,
+    pub window_secs: i64,
+    pub base_secs: i64,
+    pub max_secs: i64,
+}
+
+impl LockoutPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        LockoutPolicy {
+            threshold: config.get_login_lockout_threshold(),
+            window_secs: config.get_login_lockout_window_secs(),
+            base_secs: config.get_login_lockout_base_secs(),
+            max_secs: config.get_login_lockout_max_secs(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    /// Returns the duration of the lock following `previous_lockouts` earlier ones, in seconds
+    pub fn lock_secs(&self, previous_lockouts: i32) -> i64 {
+        let factor = 1_i64.checked_shl(previous_lockouts.clamp(0, 62) as u32).unwrap_or(i64::MAX);
+        self.base_secs.saturating_mul(factor).min(self.max_secs)
+    }
+}
+
+/// Returns the key failures of `username` are counted under
+pub fn normalize_username(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+/// Returns the end of the lock on `username`, if it is locked
+pub async fn locked_until(pool: web::Data<DbPool>, username: &str) -> Result<Option<NaiveDateTime>, ServiceError> {
+    let username = normalize_username(username);
+    web::block(move || {
+        let mut conn = pool.get().map_err(|e| {
+            tracing::error!(error = ?e, "Failed to get database connection");
+            ServiceError::InternalServerError
+        })?;
+        find_login_lock(&mut conn, &username)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!(error = ?e, "Login lockout check failed");
+        ServiceError::InternalServerError
+    })?
+}
+
+/// Counts a failed login, and returns the end of the lock if it locked the account
+pub async fn record_failure(pool: web::Data<DbPool>, username: &str, policy: LockoutPolicy) -> Result<Option<NaiveDateTime>, ServiceError> {
+    let username = normalize_username(username);
+    let failure = web::block(move || {
+        let mut conn = pool.get().map_err(|e| {
+            tracing::error!(error = ?e, "Failed to get database connection");
+            ServiceError::InternalServerError
+        })?;
+        record_login_failure(&mut conn, &username, &policy)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!(error = ?e, "Recording login failure failed");
+        ServiceError::InternalServerError
+    })??;
+
+    // The failure that locks the account resets the count
+    let locked_until = failure.locked_until.filter(|until| failure.failure_count == 0 && *until > Utc::now().naive_utc());
+    if let Some(until) = locked_until {
+        tracing::warn!(username = %failure.username, locked_until = %until, lockout_count = failure.lockout_count, "Account locked after repeated failed logins");
+        AuthMetrics::record_lockout_event("locked");
+    }
+    Ok(locked_until)
+}
+
+/// Forgets the failures of `username` after a successful login
+pub async fn record_success(pool: web::Data<DbPool>, username: &str) -> Result<(), ServiceError> {
+    let username = normalize_username(username);
+    web::block(move || {
+        let mut conn = pool.get().map_err(|e| {
+            tracing::error!(error = ?e, "Failed to get database connection");
+            ServiceError::InternalServerError
+        })?;
+        clear_login_failures(&mut conn, &username)
+            .map(|_| ())
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!(error = ?e, "Clearing login failures failed");
+        ServiceError::InternalServerError
+    })?
+}
+
+/// Purges stale entries and records the number of locked accounts every minute on the current runtime
+pub fn spawn_purge_task(pool: DbPool, window_secs: i64) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let pool = pool.clone();
+            let result = web::block(move || -> Result<(usize, i64), String> {
+                let mut conn = pool.get().map_err(|e| e.to_string())?;
+                let purged = purge_stale_login_failures(&mut conn, window_secs).map_err(|e| e.to_string())?;
+                let locked = count_locked_accounts(&mut conn).map_err(|e| e.to_string())?;
+                Ok((purged, locked))
+            })
+            .await;
+
+            match result {
+                Ok(Ok((purged, locked))) => {
+                    AuthMetrics::record_locked_accounts(locked);
+                    tracing::debug!(purged = purged, locked = locked, "Purged stale login failures");
+                }
+                Ok(Err(e)) => tracing::error!(error = %e, "Failed to purge stale login failures"),
+                Err(e) => tracing::error!(error = ?e, "Login failure purge task failed"),
+            }
+        }
+    });
+}
@@ -1,9 +1,13 @@
 use actix_web::{web, App, HttpServer, http, middleware::Logger, cookie::{Key, SameSite}};
 use actix_cors::Cors;
 use actix_session::{SessionMiddleware, storage::CookieSessionStore};
-use actix_limitation::Limiter;
 use std::time::Duration;
-use rust_api::{create_connection_pool, DbPool, services, config::get_config, init_telemetry, middleware::TracingMiddleware};
+use rust_api::{create_connection_pool, DbPool, jwt, services, config::get_config, init_telemetry, middleware::TracingMiddleware};
+use rust_api::services::auth::backend::{self, AuthBackend, ldap::{LdapBackend, sync}};
+use rust_api::services::auth::oidc::OidcClient;
+use rust_api::revocation::RevocationStore;
+use rust_api::lockout::{self, LockoutPolicy};
+use rust_api::rate_limit::RateLimiter;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -31,36 +35,92 @@ async fn main() -> std::io::Result<()> {
     }
     
     let pool: DbPool = create_connection_pool();
+    let auth_backend: web::Data<dyn AuthBackend> = backend::from_config(&config, pool.clone())
+        .map(web::Data::from)
+        .map_err(|e| {
+            eprintln!("Failed to create authentication backend: {}", e);
+            std::io::Error::other(e)
+        })?;
+    // Fail fast on misconfigured signing keys instead of on the first login
+    jwt::keyring(&config).map_err(|e| {
+        eprintln!("Failed to load JWT signing keys: {}", e);
+        std::io::Error::other(e)
+    })?;
+    if config.jwt_keyring_file.is_some() {
+        jwt::spawn_reload_task(Duration::from_secs(config.get_jwt_keyring_reload_interval_secs()));
+    }
+    RevocationStore::spawn_purge_task(
+        pool.clone(),
+        Duration::from_secs(config.get_revocation_purge_interval_secs())
+    );
+    if LockoutPolicy::from_config(&config).is_enabled() {
+        lockout::spawn_purge_task(pool.clone(), config.get_login_lockout_window_secs());
+    }
+    if let Some(interval_secs) = config.get_ldap_sync_interval_secs() {
+        if config.get_auth_backend() != "ldap" {
+            log::warn!("LDAP_SYNC_INTERVAL_SECS is set but AUTH_BACKEND is not ldap; directory sync disabled");
+        } else {
+            // A backend of its own, so the sync does not hold the pooled connections logins use
+            let sync_backend = LdapBackend::from_config(&config).map_err(std::io::Error::other)?;
+            sync::spawn_sync_task(
+                std::sync::Arc::new(sync_backend),
+                pool.clone(),
+                Duration::from_secs(interval_secs),
+                config.get_ldap_sync_page_size()
+            );
+        }
+    }
     let allow_origin = config.client_host.clone().unwrap_or("http://localhost:3000".into());
     
     // Requirements: 11.2 - CSRF protection with SameSite cookie attributes
     let session_secret = Key::from(&config.get_session_secret());
     let cookie_secure = config.is_cookie_secure();
+    let session_auth_enabled = config.is_session_auth_enabled();
     
     // Requirements: 11.2 - Rate limiting to prevent brute force attacks
-    let rate_limiter = web::Data::new(
-        Limiter::builder("redis://127.0.0.1:6379")
-            .limit(config.get_rate_limit_requests())
-            .period(Duration::from_secs(config.get_rate_limit_period_secs()))
-            .build()
-            .unwrap_or_else(|e| {
-                log::warn!("Failed to connect to Redis for rate limiting: {}. Using in-memory limiter.", e);
-                // Fallback to in-memory rate limiter if Redis is not available
-                Limiter::builder("memory://")
-                    .limit(config.get_rate_limit_requests())
-                    .period(Duration::from_secs(config.get_rate_limit_period_secs()))
-                    .build()
-                    .expect("Failed to create in-memory rate limiter")
-            })
-    );
+    let rate_limiter = RateLimiter::from_config(&config)
+        .map(web::Data::new)
+        .map_err(|e| {
+            eprintln!("Failed to create rate limiter: {}", e);
+            std::io::Error::other(e)
+        })?;
+    // Without the backend every rate-limited request would be answered with 503
+    if let Err(e) = rate_limiter.check().await {
+        if !rate_limiter.is_fail_open() {
+            eprintln!("Rate limit backend is unreachable: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+        log::warn!("Rate limit backend is unreachable, letting requests pass until it is back: {}", e);
+    }
+
+    let oidc_client = OidcClient::from_config(&config)
+        .map(|client| client.map(web::Data::new))
+        .map_err(|e| {
+            eprintln!("Failed to configure OIDC login: {}", e);
+            std::io::Error::other(e)
+        })?;
 
     HttpServer::new(move || {
-        let cors = Cors::default()
+        let mut cors = Cors::default()
             .allowed_origin(&allow_origin)
             .allowed_origin("http://localhost:8080")
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec![http::header::CONTENT_TYPE, http::header::AUTHORIZATION])
-            .expose_headers(vec![http::header::AUTHORIZATION]);
+            .allowed_headers(vec![
+                http::header::CONTENT_TYPE,
+                http::header::AUTHORIZATION,
+                http::header::HeaderName::from_static(rust_api::rate_limit::API_KEY_HEADER),
+            ])
+            // Requirements: 11.2 - Browser clients can see their rate limit
+            .expose_headers(
+                [http::header::AUTHORIZATION, http::header::RETRY_AFTER].into_iter()
+                    .chain(rust_api::rate_limit::RESPONSE_HEADERS.map(http::header::HeaderName::from_static))
+            );
+        if session_auth_enabled {
+            // Requirements: 11.2 - The SPA sends the session cookie and the CSRF token
+            cors = cors
+                .allowed_header(http::header::HeaderName::from_static(rust_api::session::CSRF_HEADER))
+                .supports_credentials();
+        }
 
         // Configure session middleware with SameSite protection
         let session_middleware = SessionMiddleware::builder(
@@ -73,9 +133,15 @@ async fn main() -> std::io::Result<()> {
         .cookie_http_only(true)  // Prevent XSS attacks
         .build();
 
-        App::new()
+        let mut app = App::new()
             .app_data(web::Data::new(pool.clone()))
-            .app_data(rate_limiter.clone())  // Requirements: 11.2 - Rate limiter for login endpoint
+            .app_data(rate_limiter.clone())  // Requirements: 11.2 - Rate limiter shared by all workers
+            .app_data(auth_backend.clone());
+        if let Some(oidc_client) = &oidc_client {
+            app = app.app_data(oidc_client.clone());
+        }
+
+        app
             .wrap(cors)
             .wrap(session_middleware)  // Requirements: 11.2 - Session with CSRF protection
             .wrap(TracingMiddleware)  // Requirements: 14.1 - Add HTTP tracing middleware
@@ -45,6 +45,32 @@ lazy_static! {
         .u64_counter("jwt_validations_total")
         .with_description("Total number of JWT token validations")
         .build();
+    
+    static ref AUTH_LOCKOUT_EVENTS_TOTAL: Counter<u64> = METER
+        .u64_counter("auth_lockout_events_total")
+        .with_description("Total number of account lockout events")
+        .build();
+    
+    // LDAP Connection Pool Metrics
+    static ref LDAP_POOL_ACQUIRE_DURATION: Histogram<f64> = METER
+        .f64_histogram("ldap_pool_acquire_duration_seconds")
+        .with_description("Time spent waiting for a pooled LDAP connection in seconds")
+        .build();
+    
+    static ref LDAP_POOL_CONNECTIONS_CREATED: Counter<u64> = METER
+        .u64_counter("ldap_pool_connections_created_total")
+        .with_description("Total number of LDAP connections opened by the pool")
+        .build();
+    
+    static ref LDAP_POOL_CONNECTIONS_DISCARDED: Counter<u64> = METER
+        .u64_counter("ldap_pool_connections_discarded_total")
+        .with_description("Total number of pooled LDAP connections closed")
+        .build();
+    
+    static ref LDAP_POOL_IN_USE: UpDownCounter<i64> = METER
+        .i64_up_down_counter("ldap_pool_connections_in_use")
+        .with_description("Number of pooled LDAP connections currently checked out")
+        .build();
 }
 
 /// HTTP Metrics
@@ -138,6 +164,64 @@ impl AuthMetrics {
         )];
         JWT_VALIDATIONS_TOTAL.add(1, &labels);
     }
+    
+    /// Record a lockout event ("locked", "rejected" or "unlocked")
+    pub fn record_lockout_event(event: &str) {
+        let labels = [KeyValue::new("event", event.to_string())];
+        AUTH_LOCKOUT_EVENTS_TOTAL.add(1, &labels);
+    }
+    
+    /// Record the number of currently locked accounts
+    pub fn record_locked_accounts(locked_accounts: i64) {
+        let locked_gauge = METER
+            .u64_gauge("auth_accounts_locked")
+            .with_description("Number of accounts currently locked after failed logins")
+            .build();
+        
+        locked_gauge.record(locked_accounts.max(0) as u64, &[]);
+    }
+}
+
+/// LDAP Connection Pool Metrics
+pub struct LdapPoolMetrics;
+
+impl LdapPoolMetrics {
+    /// Record how long acquiring a connection took ("reused", "created", "timeout" or "error")
+    pub fn record_acquire(outcome: &str, duration_secs: f64) {
+        let labels = [KeyValue::new("outcome", outcome.to_string())];
+        LDAP_POOL_ACQUIRE_DURATION.record(duration_secs, &labels);
+    }
+    
+    /// Record a newly opened connection
+    pub fn record_created() {
+        LDAP_POOL_CONNECTIONS_CREATED.add(1, &[]);
+    }
+    
+    /// Record a closed connection ("idle_timeout", "health_check" or "broken")
+    pub fn record_discarded(reason: &str) {
+        let labels = [KeyValue::new("reason", reason.to_string())];
+        LDAP_POOL_CONNECTIONS_DISCARDED.add(1, &labels);
+    }
+    
+    /// Increment checked out connections
+    pub fn increment_in_use() {
+        LDAP_POOL_IN_USE.add(1, &[]);
+    }
+    
+    /// Decrement checked out connections
+    pub fn decrement_in_use() {
+        LDAP_POOL_IN_USE.add(-1, &[]);
+    }
+    
+    /// Record the number of idle connections
+    pub fn record_idle(idle_connections: usize) {
+        let idle_gauge = METER
+            .u64_gauge("ldap_pool_connections_idle")
+            .with_description("Number of idle connections in the LDAP pool")
+            .build();
+        
+        idle_gauge.record(idle_connections as u64, &[]);
+    }
 }
 
 /// Helper struct to measure duration automatically
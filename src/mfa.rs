@@ -0,0 +1,151 @@
+//! TOTP two-factor authentication (RFC 6238)
+//!
+//! Users enrol an authenticator app with a random secret shared through an
+//! `otpauth://` URI (usually shown as a QR code), and confirm it with a first
+//! code. Once enabled, a password login only returns a short-lived challenge
+//! token, which `POST /auth/login/mfa` exchanges together with a code for the
+//! real tokens. Holders of the roles in `MFA_REQUIRED_ROLES` get a challenge
+//! even before they enrolled, and have to enrol with it first.
+//!
+//! Codes are 6 digits for 30 second steps with HMAC-SHA1, which is what
+//! authenticator apps support. One step of clock skew is accepted either way,
+//! and each step can be used once. Single-use recovery codes replace the
+//! authenticator if it is lost.
+
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha1::Sha1;
+use crate::{config::Config, DbConnection};
+use crate::models::mfa::UserMfa;
+use crate::models::mfa::usecases::{record_totp_step, use_recovery_code};
+
+/// Digits of a code
+pub const DIGITS: u32 = 6;
+
+/// Seconds per time step
+pub const PERIOD_SECS: i64 = 30;
+
+/// Time steps a code may be off by, for clocks that drift
+const SKEW_STEPS: i64 = 1;
+
+/// Bytes of a secret, the HMAC-SHA1 block size recommended by RFC 4226
+const SECRET_BYTES: usize = 20;
+
+/// Number of recovery codes issued at a time
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// Who has to use a second factor, and the limits of a two-step login
+#[derive(Clone, Debug, PartialEq)]
+pub struct MfaPolicy {
+    pub required_roles: Vec<String>,
+    pub issuer: String,
+    pub challenge_ttl_secs: i64,
+    pub max_attempts: i32,
+}
+
+impl MfaPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        MfaPolicy {
+            required_roles: config.get_mfa_required_roles()
+                .split(';')
+                .map(str::trim)
+                .filter(|role| !role.is_empty())
+                .map(str::to_string)
+                .collect(),
+            issuer: config.get_mfa_issuer(),
+            challenge_ttl_secs: config.get_mfa_challenge_ttl_secs(),
+            max_attempts: config.get_mfa_max_attempts(),
+        }
+    }
+
+    /// Returns true if any of `roles` makes a second factor mandatory
+    pub fn is_required(&self, roles: &[String]) -> bool {
+        roles.iter().any(|role| self.required_roles.contains(role))
+    }
+}
+
+/// Returns a new random secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(BASE32, &bytes)
+}
+
+/// Returns the `otpauth://` URI that authenticator apps read from a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let mut uri = reqwest::Url::parse("otpauth://totp/").expect("valid base URI");
+    uri.path_segments_mut()
+        .expect("otpauth URIs have a path")
+        .pop_if_empty()
+        .push(&format!("{}:{}", issuer, account));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD_SECS.to_string());
+    uri.into()
+}
+
+/// Returns the code of a secret for a time step
+pub fn totp(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation (RFC 4226 section 5.3)
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    binary % 10_u32.pow(DIGITS)
+}
+
+/// Returns the time step of a Unix timestamp
+pub fn time_step(unix_secs: i64) -> i64 {
+    unix_secs.div_euclid(PERIOD_SECS)
+}
+
+/// Returns the time step `code` was generated for, if it is valid around `unix_secs`
+pub fn match_code(secret: &str, code: &str, unix_secs: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = base32::decode(BASE32, secret)?;
+
+    let now = time_step(unix_secs);
+    (now - SKEW_STEPS..=now + SKEW_STEPS).find(|step| totp(&secret, *step) == code)
+}
+
+/// Returns a new recovery code, e.g. `k3m7q-x2ab9`
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    let code = base32::encode(BASE32, &bytes).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+/// Returns a recovery code the way it is hashed, ignoring case, spaces and dashes
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Checks a TOTP code, or a recovery code once MFA is enabled, and consumes it.
+///
+/// A code is accepted once: the matched time step, or the recovery code, is
+/// recorded so that a code seen by someone else cannot be replayed.
+pub fn verify_code(conn: &mut DbConnection, mfa: &UserMfa, code: &str) -> diesel::QueryResult<bool> {
+    if let Some(step) = match_code(&mfa.secret, code, chrono::Utc::now().timestamp()) {
+        return record_totp_step(conn, mfa.user_id, step);
+    }
+    if mfa.is_enabled() && code.trim().len() > DIGITS as usize {
+        return use_recovery_code(conn, mfa.user_id, code);
+    }
+    Ok(false)
+}
@@ -1,64 +1,239 @@
-use actix_web::{error, HttpMessage, web};
-use actix_web::{dev::{ServiceRequest, forward_ready, Service, ServiceResponse, Transform}, Error};
+use actix_web::{error, FromRequest, HttpMessage, HttpRequest, web};
+use actix_web::{dev::{Payload, ServiceRequest, forward_ready, Service, ServiceResponse, Transform}, Error};
 use actix_web_httpauth::extractors::bearer::{BearerAuth, Config};
 use actix_web_httpauth::extractors::AuthenticationError;
-use jsonwebtoken::{DecodingKey, Validation, Algorithm, decode};
 use serde::{Serialize, Deserialize};
 use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::rc::Rc;
 use futures_util::future::LocalBoxFuture;
 use tracing::{info_span, Instrument};
 use uuid::Uuid;
 
+use crate::errors::ServiceError;
+use crate::models::api_tokens::{is_api_token, ApiToken};
 use crate::models::users::User;
-use crate::models::users::usecases::search_user;
-use crate::{config, DbPool, DbConnection};
+use crate::{config, jwt, DbPool};
+use crate::revocation::RevocationStore;
+use crate::user_cache::{UserCache, UserCachePolicy};
 
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UserClaims {
     pub id: i32,
     pub username: String,
+    /// Issuer, `JWT_ISSUER` of the environment that issued the token
+    pub iss: String,
+    /// Audience, must match `JWT_AUDIENCE`
+    pub aud: String,
+    /// Issued at (Unix timestamp)
+    pub iat: i64,
+    /// Not valid before (Unix timestamp)
+    pub nbf: i64,
     pub exp: i64,
+    /// Unique token id used for revocation
+    pub jti: String,
+    /// Roles of the user when the token was issued
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Admin acting as the user, in impersonation tokens (RFC 8693 `act` claim)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
 }
 
+/// The admin behind an impersonation token
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Actor {
+    pub id: i32,
+    pub username: String,
+}
+
+/// Set by [`validator`] on requests authenticated with an API token
+#[derive(Clone, Debug)]
+pub struct ApiTokenAuth {
+    pub token_id: i32,
+    /// Claims standing in for an access token of the token's user
+    pub claims: UserClaims,
+    /// Permissions the token grants, if the user's roles grant them too
+    pub scopes: Vec<String>,
+}
+
+/// A bearer token that passed [`validate_bearer_token`]
+#[derive(Clone, Debug)]
+pub enum ValidatedToken {
+    Access(UserClaims),
+    Api(ApiTokenAuth),
+}
+
+impl ValidatedToken {
+    /// Returns the claims of the token, or those standing in for an API token
+    pub fn claims(&self) -> &UserClaims {
+        match self {
+            ValidatedToken::Access(claims) => claims,
+            ValidatedToken::Api(api_token) => &api_token.claims,
+        }
+    }
+}
+
+/// Accepts access tokens (JWT) and API tokens as bearer tokens
 pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<ServiceRequest, (Error, ServiceRequest)> {
     let config = req
         .app_data::<Config>()
         .map(|data| data.clone())
         .unwrap_or_else(Default::default);
 
-    match validate_token(credentials.token().replace("Bearer ", "").as_str()) {
-        Ok(res) => {
-            if res == true {
-                Ok(req)
-            } else {
-                tracing::warn!("Token validation failed: invalid token");
-                Err((AuthenticationError::from(config).into(), req))
-            }
+    let pool = req.app_data::<web::Data<DbPool>>().cloned();
+    let token = credentials.token();
+    let validated = if is_api_token(token) {
+        validate_bearer_token(pool, token).await
+    } else {
+        // The rate limit in front of `/api` has usually decoded the token already
+        match decode_request_token(&req, token) {
+            Some(claims) => check_revocation(pool, claims).await,
+            None => Ok(None),
+        }
+    };
+    match validated {
+        Ok(Some(ValidatedToken::Api(api_token))) => {
+            req.extensions_mut().insert(api_token);
+            Ok(req)
         }
+        Ok(Some(ValidatedToken::Access(claims))) => {
+            // Reused by `ReqDataCreator` and the extractors instead of decoding the token again
+            req.extensions_mut().insert(claims);
+            Ok(req)
+        }
+        Ok(None) => Err((AuthenticationError::from(config).into(), req)),
+        Err(e) => Err((e, req)),
+    }
+}
+
+/// Checks an access token or API token the way `/api` does.
+///
+/// Returns `None` if the token is invalid, expired or revoked, or the user of
+/// an API token is deactivated; errors mean its state could not be checked.
+pub async fn validate_bearer_token(pool: Option<web::Data<DbPool>>, token: &str) -> Result<Option<ValidatedToken>, Error> {
+    if is_api_token(token) {
+        return validate_api_token(pool, token.to_string())
+            .await
+            .map(|api_token| api_token.map(ValidatedToken::Api));
+    }
+
+    let claims = match validate_token(token.replace("Bearer ", "").as_str()) {
+        Ok(claims) => claims,
         Err(e) => {
             tracing::error!(error = ?e, "Token validation error");
-            Err((AuthenticationError::from(config).into(), req))
+            return Ok(None);
         }
+    };
+    check_revocation(pool, claims).await
+}
+
+/// Access token of a request and its claims, if it could be decoded
+#[derive(Clone, Debug)]
+struct DecodedToken {
+    token: String,
+    claims: Option<UserClaims>,
+}
+
+/// Decodes the access token `token` of `req` once per request.
+///
+/// Middlewares that need the claims before [`validator`] runs, like the rate
+/// limit, leave them for it here. Only the signature and the time claims are
+/// checked; `None` means the token is invalid.
+pub fn decode_request_token(req: &ServiceRequest, token: &str) -> Option<UserClaims> {
+    if let Some(decoded) = req.extensions().get::<DecodedToken>().filter(|decoded| decoded.token == token) {
+        return decoded.claims.clone();
+    }
+
+    let claims = validate_token(token).ok();
+    req.extensions_mut().insert(DecodedToken { token: token.to_string(), claims: claims.clone() });
+    claims
+}
+
+/// Accepts decoded access token claims unless the token has been revoked
+async fn check_revocation(pool: Option<web::Data<DbPool>>, claims: UserClaims) -> Result<Option<ValidatedToken>, Error> {
+    let cache_ttl_secs = config::get_config()
+        .map(|c| c.get_revocation_cache_ttl_secs())
+        .unwrap_or_default();
+
+    match RevocationStore::check(pool, &claims, cache_ttl_secs).await {
+        Ok(false) => Ok(Some(ValidatedToken::Access(claims))),
+        Ok(true) => {
+            tracing::warn!(user_id = %claims.id, jti = %claims.jti, "Token validation failed: token revoked");
+            Ok(None)
+        }
+        Err(e) => {
+            // Fail closed: a token whose revocation state is unknown is not accepted
+            tracing::error!(error = %e, "Failed to check token revocation");
+            Err(error::ErrorServiceUnavailable("Token revocation check failed"))
+        }
+    }
+}
+
+/// Resolves an API token to its user; `None` if it is unknown, expired or revoked, or the user is deactivated
+#[tracing::instrument(skip(pool, token), fields(auth.user_id = tracing::field::Empty))]
+async fn validate_api_token(pool: Option<web::Data<DbPool>>, token: String) -> Result<Option<ApiTokenAuth>, Error> {
+    use crate::models::api_tokens::usecases::authenticate_api_token;
+    use crate::models::roles::usecases::find_user_roles;
+
+    let config = config::get_config().map_err(error::ErrorInternalServerError)?;
+    let pool = pool.ok_or_else(|| error::ErrorInternalServerError("Database pool is not configured"))?;
+
+    let found = web::block(move || -> Result<Option<(ApiToken, User, Vec<String>)>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some((api_token, user)) = authenticate_api_token(&mut conn, &token)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })? else {
+            return Ok(None);
+        };
+        let roles = find_user_roles(&mut conn, user.id)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+        Ok(Some((api_token, user, roles)))
+    })
+    .await??;
+
+    let Some((api_token, user, roles)) = found else {
+        tracing::warn!("API token validation failed: unknown, expired or revoked token");
+        return Ok(None);
+    };
+    tracing::Span::current().record("auth.user_id", user.id);
+
+    if user.deactivated_at.is_some() {
+        tracing::warn!(user_id = %user.id, api_token_id = %api_token.id, "Rejected API token of deactivated user");
+        return Ok(None);
     }
+
+    let claims = UserClaims {
+        iat: api_token.created_at.and_utc().timestamp(),
+        nbf: api_token.created_at.and_utc().timestamp(),
+        exp: api_token.expires_at.and_utc().timestamp(),
+        jti: format!("api-token-{}", api_token.id),
+        roles,
+        ..jwt::new_claims(&config, user.id, &user.login_id)
+    };
+    Ok(Some(ApiTokenAuth { token_id: api_token.id, claims, scopes: api_token.scopes }))
 }
 
 #[tracing::instrument(skip(token), fields(auth.token_valid = tracing::field::Empty))]
-fn validate_token(token: &str) -> Result<bool, Error> {
+fn validate_token(token: &str) -> Result<UserClaims, Error> {
     use crate::metrics::AuthMetrics;
     
-    let secret = config::get_config().unwrap().jwt_secret;
-    let secret = secret.split(" ").map(|hex_str| u8::from_str_radix(hex_str, 16).unwrap()).collect::<Vec<u8>>();
+    let config = config::get_config().map_err(error::ErrorInternalServerError)?;
 
-    match decode::<UserClaims>(&token, &DecodingKey::from_secret(&secret), &Validation::new(Algorithm::HS256)) {
-        Ok(_claims) => {
+    match jwt::decode_access_token(&config, token) {
+        Ok(data) => {
             tracing::Span::current().record("auth.token_valid", true);
             tracing::debug!("Token validation successful");
             
             // Requirements: 12.5 - Authentication metrics collection
             AuthMetrics::record_jwt_validation(true);
             
-            Ok(true)
+            Ok(data.claims)
         }
         Err(err) => {
             tracing::Span::current().record("auth.token_valid", false);
@@ -74,34 +249,81 @@ fn validate_token(token: &str) -> Result<bool, Error> {
 
 #[derive(Clone, Debug)]
 pub struct ApiReqeustData {
-    user: Option<User>
+    user: Option<User>,
+    /// Admin acting as `user`, for requests with an impersonation token
+    impersonator: Option<User>,
+    /// Claims of the bearer token, or those standing in for an API token
+    claims: Option<UserClaims>,
 }
 
 impl ApiReqeustData {
-     fn set_current_user(&mut self, conn: &mut DbConnection, uid: String) -> Result<(), diesel::result::Error>{
-        let users = search_user(conn, &uid).map_err(|e| {
-            tracing::error!(error = ?e, uid = %uid, "Failed to search user in database");
-            e
-        })?;
-        let user = users.first();
-        self.user = match user {
-            Some(user) => {
-                tracing::debug!(user_id = %user.id, "Current user set successfully");
-                Some(user.to_owned())
-            }
-            None => {
-                tracing::debug!(uid = %uid, "User not found in database");
-                None
-            }
-        };
-        Ok(())
+     /// Returns the user the request acts as
+     pub fn current_user(&self) -> Option<&User> {
+        self.user.as_ref()
+     }
+
+     /// Returns the admin behind an impersonation token
+     pub fn impersonator(&self) -> Option<&User> {
+        self.impersonator.as_ref()
+     }
+
+     /// Returns the claims of the bearer token the request was made with
+     pub fn claims(&self) -> Option<&UserClaims> {
+        self.claims.as_ref()
+     }
+
+     /// Returns true if an admin makes the request as another user
+     pub fn is_impersonated(&self) -> bool {
+        self.impersonator.is_some()
      }
 
      fn new() -> Self {
-        ApiReqeustData { user: None }
+        ApiReqeustData { user: None, impersonator: None, claims: None }
      }
 }
 
+/// The user a request acts as, resolved by [`ReqDataCreator`].
+///
+/// Unlike `web::ReqData<ApiReqeustData>`, the extractor rejects requests
+/// whose token names a user missing from the database with 401, so handlers
+/// always get a `User`. Deactivated users never get this far: `ReqDataCreator`
+/// rejects their tokens with 401 for every handler.
+#[derive(Clone, Debug)]
+pub struct CurrentUser {
+    pub user: User,
+    /// Claims of the bearer token, or those standing in for an API token
+    pub claims: UserClaims,
+}
+
+impl Deref for CurrentUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.user
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(current_user(req))
+    }
+}
+
+fn current_user(req: &HttpRequest) -> Result<CurrentUser, ServiceError> {
+    let extensions = req.extensions();
+    let Some(claims) = extensions.get::<ApiReqeustData>().and_then(|req_data| req_data.claims()) else {
+        return Err(ServiceError::AuthenticationError { message: "Missing bearer token".to_string() });
+    };
+    let Some(user) = extensions.get::<ApiReqeustData>().and_then(|req_data| req_data.current_user()) else {
+        tracing::warn!(user_id = %claims.id, username = %claims.username, "Rejected token of unknown user");
+        return Err(ServiceError::AuthenticationError { message: "Unknown user".to_string() });
+    };
+    Ok(CurrentUser { user: user.clone(), claims: claims.clone() })
+}
+
 // There are two steps in middleware processing.
 // 1. Middleware initialization, middleware factory gets called with
 //    next service in chain as parameter.
@@ -113,7 +335,7 @@ pub struct  ReqDataCreator;
 // `B` - type of response's body
 impl<S, B> Transform<S, ServiceRequest> for  ReqDataCreator
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -124,17 +346,17 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok( ReqDataCreatorMiddleware { service }))
+        ready(Ok( ReqDataCreatorMiddleware { service: Rc::new(service) }))
     }
 }
 
 pub struct  ReqDataCreatorMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for  ReqDataCreatorMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -145,42 +367,168 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let pool = req.app_data::<web::Data<DbPool>>().unwrap();
-        let mut req_data = ApiReqeustData::new();
-        let mut conn = pool.get().unwrap();
-
-        let bearer_token = if let Some(token) = req.headers().get("authorization") {
-            token.to_str().unwrap_or_default().replace("Bearer ", "")
-        } else {
-            String::from("")
-        };
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let Some(pool) = req.app_data::<web::Data<DbPool>>().cloned() else {
+                tracing::error!("Database pool is not registered as app data");
+                return Err(error::ErrorInternalServerError("Database pool is not configured"));
+            };
+            let config = config::get_config();
+            let mut req_data = ApiReqeustData::new();
 
-        let secret = config::get_config().unwrap().jwt_secret;
-        let secret = secret.split(" ").map(|hex_str| u8::from_str_radix(hex_str, 16).unwrap()).collect::<Vec<u8>>();
-        let user_claims = decode::<UserClaims>(&bearer_token, &DecodingKey::from_secret(&secret), &Validation::new(Algorithm::HS256));
+            // Set by `validator`, which already decoded the token and checked its revocation
+            let validated_claims = req.extensions().get::<ApiTokenAuth>().map(|auth| auth.claims.clone())
+                .or_else(|| req.extensions().get::<UserClaims>().cloned());
 
-        let uid = if let Ok(data) = user_claims {
-            data.claims.username
-        } else {
-            String::from("")
-        };
+            let claims = match validated_claims {
+                Some(claims) => Some(claims),
+                None => {
+                    let bearer_token = if let Some(token) = req.headers().get("authorization") {
+                        token.to_str().unwrap_or_default().replace("Bearer ", "")
+                    } else {
+                        String::from("")
+                    };
+                    let user_claims = config
+                        .as_ref()
+                        .map_err(|e| e.clone())
+                        .and_then(|config| jwt::decode_access_token(config, &bearer_token));
+
+                    match user_claims {
+                        Ok(data) => {
+                            let cache_ttl_secs = config.as_ref().map(|c| c.get_revocation_cache_ttl_secs()).unwrap_or_default();
+                            match RevocationStore::check(Some(pool.clone()), &data.claims, cache_ttl_secs).await {
+                                Ok(false) => Some(data.claims),
+                                Ok(true) => {
+                                    tracing::warn!(user_id = %data.claims.id, jti = %data.claims.jti, "Rejected revoked token");
+                                    return Err(error::ErrorUnauthorized("Token has been revoked"));
+                                }
+                                Err(e) => {
+                                    tracing::error!(error = %e, "Failed to check token revocation");
+                                    return Err(error::ErrorServiceUnavailable("Token revocation check failed"));
+                                }
+                            }
+                        }
+                        Err(_) => None,
+                    }
+                }
+            };
 
-        let result = req_data.set_current_user(&mut conn, uid);
-        if let Ok(_) = result {
+            let mut impersonation = None;
+            if let Some(claims) = claims {
+                let cache_policy = config.as_ref().map(UserCachePolicy::from_config).unwrap_or_default();
+                let (user, impersonator) = resolve_users(pool.clone(), &claims, cache_policy).await.map_err(|e| {
+                    tracing::error!(error = %e, user_id = %claims.id, "Failed to resolve current user");
+                    error::ErrorServiceUnavailable("User lookup failed")
+                })?;
+
+                // Tokens stay valid until they expire, so users removed from the directory are rejected here
+                if let Some(user) = user.as_ref().filter(|user| user.deactivated_at.is_some()) {
+                    tracing::warn!(user_id = %user.id, "Rejected token of deactivated user");
+                    return Err(error::ErrorUnauthorized("Account has been deactivated"));
+                }
+                if let Some(actor) = &claims.act {
+                    // The token dies with the admin's account, like the user's own tokens
+                    if impersonator.as_ref().is_none_or(|admin| admin.deactivated_at.is_some()) {
+                        tracing::warn!(impersonator_id = %actor.id, "Rejected impersonation token of deactivated admin");
+                        return Err(error::ErrorUnauthorized("Account has been deactivated"));
+                    }
+                    impersonation = Some(claims.clone());
+                }
+                if user.is_none() {
+                    tracing::debug!(uid = %claims.username, "User not found in database");
+                }
+
+                req_data.user = user;
+                req_data.impersonator = impersonator;
+                req_data.claims = Some(claims);
+            }
             req.extensions_mut().insert(req_data);
-        } else if let Err(e) = result {
-            tracing::warn!(error = ?e, "Failed to set current user in request data");
-        }
 
-        let fut = self.service.call(req);
+            // Requests under impersonation are tagged in traces and recorded in the audit trail
+            let span = match impersonation.as_ref().and_then(|claims| claims.act.as_ref().map(|actor| (claims, actor))) {
+                Some((claims, actor)) => info_span!("impersonated_request", auth.user_id = %claims.id, auth.impersonator_id = %actor.id, auth.impersonated = true),
+                None => tracing::Span::none(),
+            };
+            let audit = impersonation.map(|claims| (claims, req.method().to_string(), req.path().to_string()));
 
-        Box::pin(async move {
-            let res = fut.await.map_err(|e| {
-                tracing::error!(error = ?e, "Request processing error");
-                e
+            async move {
+                let res = service.call(req).await.map_err(|e| {
+                    tracing::error!(error = ?e, "Request processing error");
+                    e
+                });
+                if let Some((claims, method, path)) = audit {
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    record_impersonated_request(pool, claims, method, path, status.as_u16()).await;
+                }
+                res
+            }
+            .instrument(span)
+            .await
+        })
+    }
+}
+
+/// Looks up the user of a token, and the admin behind an impersonation token.
+///
+/// Cached users are returned right away; otherwise the lookup runs on the
+/// blocking pool, so a slow database or an exhausted pool does not stall the
+/// worker.
+async fn resolve_users(pool: web::Data<DbPool>, claims: &UserClaims, cache_policy: UserCachePolicy) -> Result<(Option<User>, Option<User>), String> {
+    let cached_user = UserCache::cached_login(claims.id, &claims.username);
+    let cached_impersonator = claims.act.as_ref().map(|actor| UserCache::cached(actor.id));
+    if let Some(user) = cached_user
+        && cached_impersonator.as_ref().is_none_or(Option::is_some)
+    {
+        return Ok((Some(user), cached_impersonator.flatten()));
+    }
+
+    let claims = claims.clone();
+    web::block(move || -> Result<(Option<User>, Option<User>), String> {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        let user = UserCache::find_login(&mut conn, claims.id, &claims.username, &cache_policy)
+            .map_err(|e| e.to_string())?;
+        let impersonator = match &claims.act {
+            Some(actor) => UserCache::find(&mut conn, actor.id, &cache_policy).map_err(|e| e.to_string())?,
+            None => None,
+        };
+        Ok((user, impersonator))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Records a request made with an impersonation token; failures are logged, as the request already ran
+async fn record_impersonated_request(pool: web::Data<DbPool>, claims: UserClaims, method: String, path: String, status: u16) {
+    use crate::models::audit_events::{IMPERSONATED_REQUEST, usecases::{record_audit_event, NewAuditEvent}};
+
+    let recorded = web::block(move || -> Result<(), ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
             })?;
-            Ok(res)
+
+        record_audit_event(&mut conn, &NewAuditEvent {
+            action: IMPERSONATED_REQUEST,
+            user_id: claims.id,
+            impersonator_id: claims.act.as_ref().map(|actor| actor.id),
+            token_id: Some(&claims.jti),
+            http_method: Some(&method),
+            http_path: Some(&path),
+            http_status: Some(status.into()),
         })
+        .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await;
+
+    match recorded {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!(error = ?e, "Failed to record impersonated request"),
+        Err(e) => tracing::error!(error = ?e, "Failed to record impersonated request"),
     }
 }
 
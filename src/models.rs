@@ -4,6 +4,15 @@ use crate::{traits::IntoValidator, errors::ServiceError};
 
 pub mod users;
 pub mod customers;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
+pub mod roles;
+pub mod login_failures;
+pub mod api_tokens;
+pub mod oidc_logins;
+pub mod oidc_identities;
+pub mod mfa;
+pub mod audit_events;
 
 pub fn validate<T: Validate>(item: &impl IntoValidator<T>) -> Result<(), ServiceError>  {
     item.validator().validate().map_err(|err| ServiceError::ValidationError { value: err })
@@ -0,0 +1,69 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+use crate::schema::api_tokens;
+
+pub mod usecases;
+
+/// Prefix of every API token, which tells them apart from JWT access tokens
+pub const API_TOKEN_PREFIX: &str = "pat_";
+
+/// Returns true if a bearer token is an API token rather than an access token
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(API_TOKEN_PREFIX)
+}
+
+#[derive(Validate)]
+#[validate(schema(function = "validate_expiry"))]
+struct ApiTokenValidator {
+    #[validate(length(min = 1, max = 100, message = "トークン名は1文字以上100文字以下で入力してください"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "スコープを1つ以上指定してください"))]
+    #[validate(custom(function = "validate_scopes"))]
+    pub scopes: Vec<String>,
+    pub expires_in_days: i64,
+    pub max_ttl_days: i64,
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if scopes.iter().all(|scope| crate::rbac::API_TOKEN_SCOPES.contains(&scope.as_str())) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_scope").with_message("指定できないスコープが含まれています".into()))
+    }
+}
+
+fn validate_expiry(token: &ApiTokenValidator) -> Result<(), ValidationError> {
+    if (1..=token.max_ttl_days).contains(&token.expires_in_days) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("expires_in_days").with_message(
+            format!("有効期間は1日以上{}日以下で指定してください", token.max_ttl_days).into()
+        ))
+    }
+}
+
+/// A long-lived token a user created for machine clients.
+///
+/// Only the SHA-256 hash of the token is stored; `token_prefix` keeps its
+/// first characters so that users can tell their tokens apart. A token grants
+/// the permissions in its `scopes` that the user's roles still grant.
+#[derive(Clone, Queryable, Identifiable, Serialize, ToSchema, Debug)]
+#[diesel(table_name = api_tokens)]
+pub struct ApiToken {
+    pub id: i32,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    /// Time the token was last used, recorded at most once a minute
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
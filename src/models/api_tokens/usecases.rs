@@ -0,0 +1,241 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+use crate::{DbConnection, errors::ServiceError, models::validate, traits::IntoValidator};
+use crate::models::refresh_tokens::usecases::{generate_token, hash_token};
+use crate::models::users::User;
+use super::{ApiToken, ApiTokenValidator, API_TOKEN_PREFIX};
+use crate::schema::{api_tokens::dsl, users};
+
+/// Characters of a token kept in `token_prefix`
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+/// `last_used_at` is only updated when it is older than this, to spare a write per request
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::api_tokens)]
+struct NewApiToken<'a> {
+    user_id: i32,
+    name: &'a str,
+    token_hash: &'a str,
+    token_prefix: &'a str,
+    scopes: &'a [String],
+    expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct NewApiTokenBody {
+    pub name: String,
+    /// Permissions the token grants, e.g. `customers:read`
+    pub scopes: Vec<String>,
+    /// Days until the token expires, at most `API_TOKEN_MAX_TTL_DAYS`
+    pub expires_in_days: i64,
+}
+
+/// A newly created token; its value is only ever returned here
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
+
+struct NewApiTokenInput<'a> {
+    body: &'a NewApiTokenBody,
+    max_ttl_days: i64,
+}
+
+impl<'a> IntoValidator<ApiTokenValidator> for NewApiTokenInput<'a> {
+    fn validator(&self) -> ApiTokenValidator {
+        ApiTokenValidator {
+            name: self.body.name.clone(),
+            scopes: self.body.scopes.clone(),
+            expires_in_days: self.body.expires_in_days,
+            max_ttl_days: self.max_ttl_days,
+        }
+    }
+}
+
+#[instrument(skip(conn, body), fields(db.operation = "create_api_token", db.user_id = %user_id))]
+pub fn create_api_token(
+    conn: &mut DbConnection,
+    user_id: i32,
+    body: &NewApiTokenBody,
+    max_ttl_days: i64
+) -> Result<CreatedApiToken, ServiceError> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("create_api_token");
+
+    validate::<ApiTokenValidator>(&NewApiTokenInput { body, max_ttl_days })?;
+
+    let token = format!("{}{}", API_TOKEN_PREFIX, generate_token());
+    let mut scopes = body.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let api_token = diesel::insert_into(dsl::api_tokens)
+        .values(&NewApiToken {
+            user_id,
+            name: &body.name,
+            token_hash: &hash_token(&token),
+            token_prefix: &token[..DISPLAY_PREFIX_LEN],
+            scopes: &scopes,
+            expires_at: (Utc::now() + Duration::days(body.expires_in_days)).naive_utc(),
+        })
+        .get_result::<ApiToken>(conn)
+        .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+
+    // Record query duration
+    DbMetrics::record_duration("create_api_token", timer.elapsed_secs());
+
+    Ok(CreatedApiToken { token, api_token })
+}
+
+/// Returns the tokens of a user that are not revoked, including expired ones
+#[instrument(skip(conn), fields(db.operation = "list_api_tokens", db.user_id = %user_id))]
+pub fn list_api_tokens(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<Vec<ApiToken>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("list_api_tokens");
+
+    let tokens = dsl::api_tokens
+        .filter(dsl::user_id.eq(user_id))
+        .filter(dsl::revoked_at.is_null())
+        .order(dsl::id)
+        .load::<ApiToken>(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("list_api_tokens", timer.elapsed_secs());
+
+    Ok(tokens)
+}
+
+/// Revokes a token of the user; returns false if they have no such active token
+#[instrument(skip(conn), fields(db.operation = "revoke_api_token", db.user_id = %user_id))]
+pub fn revoke_api_token(
+    conn: &mut DbConnection,
+    user_id: i32,
+    id: i32
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("revoke_api_token");
+
+    let count = diesel::update(dsl::api_tokens)
+        .filter(dsl::id.eq(id))
+        .filter(dsl::user_id.eq(user_id))
+        .filter(dsl::revoked_at.is_null())
+        .set(dsl::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("revoke_api_token", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Looks up an unexpired, unrevoked token and its user, and records its use
+#[instrument(skip(conn, token), fields(db.operation = "authenticate_api_token"))]
+pub fn authenticate_api_token(
+    conn: &mut DbConnection,
+    token: &str
+) -> QueryResult<Option<(ApiToken, User)>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("authenticate_api_token");
+
+    let now = Utc::now().naive_utc();
+    let found = dsl::api_tokens
+        .inner_join(users::table)
+        .filter(dsl::token_hash.eq(hash_token(token)))
+        .filter(dsl::revoked_at.is_null())
+        .filter(dsl::expires_at.gt(now))
+        .first::<(ApiToken, User)>(conn)
+        .optional()?;
+
+    if let Some((api_token, _)) = &found {
+        let stale = now - Duration::seconds(LAST_USED_RESOLUTION_SECS);
+        diesel::update(dsl::api_tokens.find(api_token.id))
+            .filter(dsl::last_used_at.is_null().or(dsl::last_used_at.lt(stale)))
+            .set(dsl::last_used_at.eq(now))
+            .execute(conn)?;
+    }
+
+    // Record query duration
+    DbMetrics::record_duration("authenticate_api_token", timer.elapsed_secs());
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_test_connection_pool;
+    use crate::models::users::usecases::insert_new_user;
+
+    fn body(scopes: &[&str], expires_in_days: i64) -> NewApiTokenBody {
+        NewApiTokenBody {
+            name: "batch".to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_in_days,
+        }
+    }
+
+    #[test]
+    fn api_token_lifecycle_test() {
+        let pool = create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+
+        conn.test_transaction::<_, ServiceError, _>(|conn| {
+            let user = insert_new_user(conn, "api_token_owner".to_string(), None, None, None, None, None)?;
+            let created = create_api_token(conn, user.id, &body(&["customers:read"], 30), 365)?;
+            assert!(created.token.starts_with(API_TOKEN_PREFIX));
+            assert!(created.token.starts_with(&created.api_token.token_prefix));
+            assert_ne!(created.api_token.token_hash, created.token);
+
+            let (api_token, owner) = authenticate_api_token(conn, &created.token)
+                .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?
+                .expect("a new token should authenticate");
+            assert_eq!(owner.id, user.id);
+            assert!(api_token.last_used_at.is_none());
+            let used = list_api_tokens(conn, user.id).unwrap().remove(0);
+            assert!(used.last_used_at.is_some());
+
+            assert!(revoke_api_token(conn, user.id, api_token.id).unwrap());
+            assert!(!revoke_api_token(conn, user.id, api_token.id).unwrap());
+            assert!(authenticate_api_token(conn, &created.token).unwrap().is_none());
+            assert!(list_api_tokens(conn, user.id).unwrap().is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_api_token_request_test() {
+        let pool = create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+
+        conn.test_transaction::<_, ServiceError, _>(|conn| {
+            let user = insert_new_user(conn, "api_token_invalid".to_string(), None, None, None, None, None)?;
+            assert!(create_api_token(conn, user.id, &body(&[], 30), 365).is_err());
+            assert!(create_api_token(conn, user.id, &body(&["tokens:manage"], 30), 365).is_err());
+            assert!(create_api_token(conn, user.id, &body(&["customers:read"], 0), 365).is_err());
+            assert!(create_api_token(conn, user.id, &body(&["customers:read"], 366), 365).is_err());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::audit_events;
+
+pub mod usecases;
+
+/// An admin started impersonating the user
+pub const IMPERSONATION_STARTED: &str = "impersonation.started";
+
+/// A request was made with an impersonation token of the user
+pub const IMPERSONATED_REQUEST: &str = "impersonation.request";
+
+/// Something done on behalf of `user_id`.
+///
+/// `impersonator_id` is the admin who acted as the user, and `token_id` the
+/// `jti` of the token used. Requests are recorded with their method, path and
+/// response status.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub action: String,
+    pub user_id: i32,
+    pub impersonator_id: Option<i32>,
+    pub token_id: Option<String>,
+    pub http_method: Option<String>,
+    pub http_path: Option<String>,
+    pub http_status: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
@@ -0,0 +1,61 @@
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use crate::schema::audit_events::dsl;
+use super::AuditEvent;
+
+#[derive(Debug, Default, Insertable)]
+#[diesel(table_name = crate::schema::audit_events)]
+pub struct NewAuditEvent<'a> {
+    pub action: &'a str,
+    pub user_id: i32,
+    pub impersonator_id: Option<i32>,
+    pub token_id: Option<&'a str>,
+    pub http_method: Option<&'a str>,
+    pub http_path: Option<&'a str>,
+    pub http_status: Option<i32>,
+}
+
+#[instrument(skip(conn, event), fields(db.operation = "record_audit_event", db.user_id = %event.user_id, audit.action = %event.action))]
+pub fn record_audit_event(
+    conn: &mut DbConnection,
+    event: &NewAuditEvent
+) -> QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("record_audit_event");
+
+    diesel::insert_into(dsl::audit_events)
+        .values(event)
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("record_audit_event", timer.elapsed_secs());
+
+    Ok(())
+}
+
+/// Returns the audit events of a user, oldest first
+#[instrument(skip(conn), fields(db.operation = "find_user_audit_events", db.user_id = %user_id))]
+pub fn find_user_audit_events(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<Vec<AuditEvent>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_user_audit_events");
+
+    let events = dsl::audit_events
+        .filter(dsl::user_id.eq(user_id))
+        .order(dsl::id.asc())
+        .load::<AuditEvent>(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_user_audit_events", timer.elapsed_secs());
+
+    Ok(events)
+}
@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::login_failures;
+
+pub mod usecases;
+
+/// Failed logins of one username, and its lock if the threshold was reached
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = login_failures, primary_key(username))]
+pub struct LoginFailure {
+    /// Lowercased, so that case variations count against the same account
+    pub username: String,
+    /// Failures since `first_failed_at`, reset when the account is locked
+    pub failure_count: i32,
+    pub first_failed_at: NaiveDateTime,
+    pub last_failed_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+    /// Lockouts so far, which doubles the next lock's duration
+    pub lockout_count: i32,
+}
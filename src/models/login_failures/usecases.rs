@@ -0,0 +1,172 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use crate::lockout::LockoutPolicy;
+use crate::schema::login_failures::dsl;
+use super::LoginFailure;
+
+/// Returns the end of the lock on `username`, if it is locked
+#[instrument(skip(conn), fields(db.operation = "find_login_lock"))]
+pub fn find_login_lock(
+    conn: &mut DbConnection,
+    username: &str
+) -> QueryResult<Option<NaiveDateTime>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_login_lock");
+
+    // Timestamps are stored in UTC, while `now` would be in the session's time zone
+    let locked_until = dsl::login_failures
+        .filter(dsl::username.eq(username))
+        .filter(dsl::locked_until.gt(Utc::now().naive_utc()))
+        .select(dsl::locked_until)
+        .first::<Option<NaiveDateTime>>(conn)
+        .optional()?
+        .flatten();
+
+    // Record query duration
+    DbMetrics::record_duration("find_login_lock", timer.elapsed_secs());
+
+    Ok(locked_until)
+}
+
+/// Counts a failed login of `username` and locks it when the threshold is reached.
+///
+/// Returns the stored failures; `locked_until` is only in the future if this
+/// failure locked the account or it was locked already.
+#[instrument(skip(conn, policy), fields(db.operation = "record_login_failure"))]
+pub fn record_login_failure(
+    conn: &mut DbConnection,
+    username: &str,
+    policy: &LockoutPolicy
+) -> QueryResult<LoginFailure> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("record_login_failure");
+
+    let failure = conn.transaction(|conn| {
+        let now = Utc::now().naive_utc();
+        let existing = dsl::login_failures
+            .filter(dsl::username.eq(username))
+            .for_update()
+            .first::<LoginFailure>(conn)
+            .optional()?;
+
+        // Failures are forgotten a window after the last failure or the end of the lock,
+        // and so are earlier lockouts
+        let cutoff = now - TimeDelta::seconds(policy.window_secs);
+        let (mut failure_count, mut first_failed_at, mut locked_until, mut lockout_count) = match existing {
+            Some(existing) if existing.last_failed_at.max(existing.locked_until.unwrap_or_default()) > cutoff => (
+                existing.failure_count + 1,
+                existing.first_failed_at,
+                existing.locked_until,
+                existing.lockout_count,
+            ),
+            _ => (1, now, None, 0),
+        };
+
+        if failure_count >= policy.threshold {
+            locked_until = Some(now + TimeDelta::seconds(policy.lock_secs(lockout_count)));
+            lockout_count += 1;
+            failure_count = 0;
+            first_failed_at = now;
+        }
+
+        diesel::insert_into(dsl::login_failures)
+            .values((
+                dsl::username.eq(username),
+                dsl::failure_count.eq(failure_count),
+                dsl::first_failed_at.eq(first_failed_at),
+                dsl::last_failed_at.eq(now),
+                dsl::locked_until.eq(locked_until),
+                dsl::lockout_count.eq(lockout_count),
+            ))
+            .on_conflict(dsl::username)
+            .do_update()
+            .set((
+                dsl::failure_count.eq(failure_count),
+                dsl::first_failed_at.eq(first_failed_at),
+                dsl::last_failed_at.eq(now),
+                dsl::locked_until.eq(locked_until),
+                dsl::lockout_count.eq(lockout_count),
+            ))
+            .get_result::<LoginFailure>(conn)
+    })?;
+
+    // Record query duration
+    DbMetrics::record_duration("record_login_failure", timer.elapsed_secs());
+
+    Ok(failure)
+}
+
+/// Forgets the failures and lock of `username`, and returns whether there were any
+#[instrument(skip(conn), fields(db.operation = "clear_login_failures"))]
+pub fn clear_login_failures(
+    conn: &mut DbConnection,
+    username: &str
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("clear_login_failures");
+
+    let count = diesel::delete(dsl::login_failures.filter(dsl::username.eq(username)))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("clear_login_failures", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Deletes the entries whose last failure and lock ended more than `window_secs` ago
+#[instrument(skip(conn), fields(db.operation = "purge_stale_login_failures"))]
+pub fn purge_stale_login_failures(
+    conn: &mut DbConnection,
+    window_secs: i64
+) -> QueryResult<usize> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("purge_stale_login_failures");
+
+    let cutoff = Utc::now().naive_utc() - TimeDelta::seconds(window_secs);
+    let count = diesel::delete(dsl::login_failures)
+        .filter(dsl::last_failed_at.lt(cutoff))
+        .filter(dsl::locked_until.is_null().or(dsl::locked_until.lt(cutoff)))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("purge_stale_login_failures", timer.elapsed_secs());
+
+    Ok(count)
+}
+
+/// Returns the number of currently locked accounts
+#[instrument(skip(conn), fields(db.operation = "count_locked_accounts"))]
+pub fn count_locked_accounts(
+    conn: &mut DbConnection
+) -> QueryResult<i64> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("count_locked_accounts");
+
+    let count = dsl::login_failures
+        .filter(dsl::locked_until.gt(Utc::now().naive_utc()))
+        .count()
+        .get_result(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("count_locked_accounts", timer.elapsed_secs());
+
+    Ok(count)
+}
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::{mfa_challenges, user_mfa};
+
+pub mod usecases;
+
+/// The TOTP second factor of a user.
+///
+/// It is pending until the user confirms it with a first code, and only
+/// enabled ones are asked for at login. `last_used_step` is the time step of
+/// the last accepted code, so that no code is accepted twice.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = user_mfa, primary_key(user_id))]
+pub struct UserMfa {
+    pub user_id: i32,
+    pub secret: String,
+    pub enabled_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+impl UserMfa {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+}
+
+/// A login that passed the password check and waits for the second factor.
+///
+/// Only the SHA-256 hash of the challenge token is stored. It is invalidated
+/// after `MFA_MAX_ATTEMPTS` wrong codes.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = mfa_challenges, primary_key(token_hash))]
+pub struct MfaChallenge {
+    pub token_hash: String,
+    pub user_id: i32,
+    pub attempts: i32,
+    pub expires_at: NaiveDateTime,
+}
@@ -0,0 +1,362 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+use crate::DbConnection;
+use crate::mfa::{generate_recovery_code, generate_secret, normalize_recovery_code, provisioning_uri, RECOVERY_CODE_COUNT};
+use crate::models::refresh_tokens::usecases::{generate_token, hash_token};
+use super::{MfaChallenge, UserMfa};
+use crate::schema::{mfa_challenges, mfa_recovery_codes, user_mfa};
+
+/// A started enrolment; the secret is only ever returned here
+#[derive(Serialize, ToSchema, Debug)]
+pub struct MfaEnrollment {
+    /// Base32 secret, for authenticator apps that cannot scan QR codes
+    pub secret: String,
+    /// `otpauth://` URI to show as a QR code
+    pub otpauth_uri: String,
+}
+
+/// New recovery codes, which replace any earlier ones; they are only ever returned here
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::user_mfa)]
+struct NewUserMfa<'a> {
+    user_id: i32,
+    secret: &'a str,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::mfa_recovery_codes)]
+struct NewRecoveryCode {
+    user_id: i32,
+    code_hash: String,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::mfa_challenges)]
+struct NewMfaChallenge<'a> {
+    token_hash: &'a str,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+}
+
+#[instrument(skip(conn), fields(db.operation = "find_user_mfa", db.user_id = %user_id))]
+pub fn find_user_mfa(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<Option<UserMfa>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_user_mfa");
+
+    let mfa = user_mfa::table
+        .find(user_id)
+        .first::<UserMfa>(conn)
+        .optional()?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_user_mfa", timer.elapsed_secs());
+
+    Ok(mfa)
+}
+
+/// Replaces a pending enrolment with a new secret; returns None if MFA is already enabled
+#[instrument(skip(conn, issuer), fields(db.operation = "start_mfa_enrollment", db.user_id = %user_id))]
+pub fn start_mfa_enrollment(
+    conn: &mut DbConnection,
+    user_id: i32,
+    issuer: &str,
+    login_id: &str
+) -> QueryResult<Option<MfaEnrollment>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("start_mfa_enrollment");
+
+    let enrollment = conn.transaction(|conn| {
+        let enabled = user_mfa::table
+            .find(user_id)
+            .select(user_mfa::enabled_at.is_not_null())
+            .for_update()
+            .first::<bool>(conn)
+            .optional()?;
+        if enabled == Some(true) {
+            return Ok(None);
+        }
+
+        let secret = generate_secret();
+        diesel::delete(user_mfa::table.find(user_id)).execute(conn)?;
+        diesel::insert_into(user_mfa::table)
+            .values(&NewUserMfa { user_id, secret: &secret })
+            .execute(conn)?;
+
+        let otpauth_uri = provisioning_uri(issuer, login_id, &secret);
+        Ok(Some(MfaEnrollment { secret, otpauth_uri }))
+    });
+
+    // Record query duration
+    DbMetrics::record_duration("start_mfa_enrollment", timer.elapsed_secs());
+
+    enrollment
+}
+
+/// Enables a pending enrolment; returns false if there is none
+#[instrument(skip(conn), fields(db.operation = "enable_mfa", db.user_id = %user_id))]
+pub fn enable_mfa(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("enable_mfa");
+
+    let count = diesel::update(user_mfa::table.find(user_id))
+        .filter(user_mfa::enabled_at.is_null())
+        .set(user_mfa::enabled_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("enable_mfa", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Removes the second factor and recovery codes of a user; returns false if they had none
+#[instrument(skip(conn), fields(db.operation = "disable_mfa", db.user_id = %user_id))]
+pub fn disable_mfa(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("disable_mfa");
+
+    let count = conn.transaction(|conn| {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(user_mfa::table.find(user_id)).execute(conn)
+    })?;
+
+    // Record query duration
+    DbMetrics::record_duration("disable_mfa", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Records the time step of an accepted code; returns false if it, or a later one, was already used
+#[instrument(skip(conn), fields(db.operation = "record_totp_step", db.user_id = %user_id))]
+pub fn record_totp_step(
+    conn: &mut DbConnection,
+    user_id: i32,
+    step: i64
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("record_totp_step");
+
+    let count = diesel::update(user_mfa::table.find(user_id))
+        .filter(user_mfa::last_used_step.is_null().or(user_mfa::last_used_step.lt(step)))
+        .set(user_mfa::last_used_step.eq(step))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("record_totp_step", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Replaces the recovery codes of a user with new ones
+#[instrument(skip(conn), fields(db.operation = "replace_recovery_codes", db.user_id = %user_id))]
+pub fn replace_recovery_codes(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<RecoveryCodes> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("replace_recovery_codes");
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let rows: Vec<NewRecoveryCode> = recovery_codes.iter()
+        .map(|code| NewRecoveryCode { user_id, code_hash: hash_token(&normalize_recovery_code(code)) })
+        .collect();
+    conn.transaction(|conn| {
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::insert_into(mfa_recovery_codes::table)
+            .values(&rows)
+            .execute(conn)
+    })?;
+
+    // Record query duration
+    DbMetrics::record_duration("replace_recovery_codes", timer.elapsed_secs());
+
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+/// Marks an unused recovery code of the user as used; returns false if there is no such code
+#[instrument(skip(conn, code), fields(db.operation = "use_recovery_code", db.user_id = %user_id))]
+pub fn use_recovery_code(
+    conn: &mut DbConnection,
+    user_id: i32,
+    code: &str
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("use_recovery_code");
+
+    let count = diesel::update(mfa_recovery_codes::table)
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::code_hash.eq(hash_token(&normalize_recovery_code(code))))
+        .filter(mfa_recovery_codes::used_at.is_null())
+        .set(mfa_recovery_codes::used_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("use_recovery_code", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
+
+/// Returns the number of unused recovery codes of a user
+#[instrument(skip(conn), fields(db.operation = "count_recovery_codes", db.user_id = %user_id))]
+pub fn count_recovery_codes(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<i64> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("count_recovery_codes");
+
+    let count = mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::used_at.is_null())
+        .count()
+        .get_result(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("count_recovery_codes", timer.elapsed_secs());
+
+    Ok(count)
+}
+
+/// Creates a challenge for a user who passed the password check and returns its token
+#[instrument(skip(conn), fields(db.operation = "create_mfa_challenge", db.user_id = %user_id))]
+pub fn create_mfa_challenge(
+    conn: &mut DbConnection,
+    user_id: i32,
+    ttl_secs: i64
+) -> QueryResult<String> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("create_mfa_challenge");
+
+    let now = Utc::now().naive_utc();
+    diesel::delete(mfa_challenges::table.filter(mfa_challenges::expires_at.le(now)))
+        .execute(conn)?;
+
+    let token = generate_token();
+    diesel::insert_into(mfa_challenges::table)
+        .values(&NewMfaChallenge {
+            token_hash: &hash_token(&token),
+            user_id,
+            expires_at: now + Duration::seconds(ttl_secs),
+        })
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("create_mfa_challenge", timer.elapsed_secs());
+
+    Ok(token)
+}
+
+/// Returns the challenge of a token unless it expired or had too many wrong codes
+#[instrument(skip(conn, token), fields(db.operation = "find_mfa_challenge"))]
+pub fn find_mfa_challenge(
+    conn: &mut DbConnection,
+    token: &str,
+    max_attempts: i32
+) -> QueryResult<Option<MfaChallenge>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_mfa_challenge");
+
+    let challenge = mfa_challenges::table
+        .find(hash_token(token))
+        .filter(mfa_challenges::expires_at.gt(Utc::now().naive_utc()))
+        .filter(mfa_challenges::attempts.lt(max_attempts))
+        .first::<MfaChallenge>(conn)
+        .optional()?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_mfa_challenge", timer.elapsed_secs());
+
+    Ok(challenge)
+}
+
+/// Counts a wrong code against a challenge
+#[instrument(skip(conn, token), fields(db.operation = "record_mfa_challenge_failure"))]
+pub fn record_mfa_challenge_failure(
+    conn: &mut DbConnection,
+    token: &str
+) -> QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("record_mfa_challenge_failure");
+
+    diesel::update(mfa_challenges::table.find(hash_token(token)))
+        .set(mfa_challenges::attempts.eq(mfa_challenges::attempts + 1))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("record_mfa_challenge_failure", timer.elapsed_secs());
+
+    Ok(())
+}
+
+/// Removes a challenge once its login completed; returns false if it was already used
+#[instrument(skip(conn, token), fields(db.operation = "complete_mfa_challenge"))]
+pub fn complete_mfa_challenge(
+    conn: &mut DbConnection,
+    token: &str
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("complete_mfa_challenge");
+
+    let count = diesel::delete(mfa_challenges::table.find(hash_token(token)))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("complete_mfa_challenge", timer.elapsed_secs());
+
+    Ok(count > 0)
+}
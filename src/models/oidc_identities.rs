@@ -0,0 +1,19 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::oidc_identities;
+
+pub mod usecases;
+
+/// An OIDC identity and the user it logs in as.
+///
+/// Identities are known by the issuer and the `sub` claim, which the provider
+/// never reassigns, rather than by a username claim that could name a user of
+/// the directory.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = oidc_identities, primary_key(issuer, subject))]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+}
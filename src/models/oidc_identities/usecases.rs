@@ -0,0 +1,68 @@
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use crate::models::users::User;
+use crate::schema::oidc_identities::dsl;
+
+/// Returns the user an identity is linked to
+#[instrument(skip(conn), fields(db.operation = "find_oidc_user"))]
+pub fn find_oidc_user(
+    conn: &mut DbConnection,
+    issuer: &str,
+    subject: &str
+) -> QueryResult<Option<User>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+    use crate::schema::users;
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_oidc_user");
+
+    let user = dsl::oidc_identities
+        .inner_join(users::table)
+        .filter(dsl::issuer.eq(issuer))
+        .filter(dsl::subject.eq(subject))
+        .select(users::all_columns)
+        .first::<User>(conn)
+        .optional()?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_oidc_user", timer.elapsed_secs());
+
+    Ok(user)
+}
+
+/// Links an identity to a user unless it is linked already, and returns the
+/// id of the user it is linked to
+#[instrument(skip(conn), fields(db.operation = "link_oidc_identity", db.user_id = %user_id))]
+pub fn link_oidc_identity(
+    conn: &mut DbConnection,
+    issuer: &str,
+    subject: &str,
+    user_id: i32
+) -> QueryResult<i32> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("link_oidc_identity");
+
+    diesel::insert_into(dsl::oidc_identities)
+        .values((
+            dsl::issuer.eq(issuer),
+            dsl::subject.eq(subject),
+            dsl::user_id.eq(user_id),
+        ))
+        .on_conflict((dsl::issuer, dsl::subject))
+        .do_nothing()
+        .execute(conn)?;
+    let linked = dsl::oidc_identities
+        .find((issuer, subject))
+        .select(dsl::user_id)
+        .first(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("link_oidc_identity", timer.elapsed_secs());
+
+    Ok(linked)
+}
@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::oidc_logins;
+
+pub mod usecases;
+
+/// An OIDC login waiting for the provider to redirect back to the callback.
+///
+/// The PKCE code verifier and the nonce never leave the server; the browser
+/// only carries `state`, which finds them again.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = oidc_logins, primary_key(state))]
+pub struct OidcLogin {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub expires_at: NaiveDateTime,
+    /// User who started the login to link the identity to their account
+    pub link_user_id: Option<i32>,
+}
@@ -0,0 +1,79 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use super::OidcLogin;
+use crate::schema::oidc_logins::dsl;
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::oidc_logins)]
+struct NewOidcLogin<'a> {
+    state: &'a str,
+    code_verifier: &'a str,
+    nonce: &'a str,
+    expires_at: chrono::NaiveDateTime,
+    link_user_id: Option<i32>,
+}
+
+/// Stores a started login and purges the expired ones.
+///
+/// `link_user_id` is set for logins that link the identity to a logged-in user.
+#[instrument(skip_all, fields(db.operation = "insert_oidc_login"))]
+pub fn insert_oidc_login(
+    conn: &mut DbConnection,
+    state: &str,
+    code_verifier: &str,
+    nonce: &str,
+    ttl_secs: i64,
+    link_user_id: Option<i32>
+) -> QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("insert_oidc_login");
+
+    let now = Utc::now().naive_utc();
+    diesel::delete(dsl::oidc_logins.filter(dsl::expires_at.le(now)))
+        .execute(conn)?;
+
+    diesel::insert_into(dsl::oidc_logins)
+        .values(&NewOidcLogin {
+            state,
+            code_verifier,
+            nonce,
+            expires_at: now + Duration::seconds(ttl_secs),
+            link_user_id,
+        })
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("insert_oidc_login", timer.elapsed_secs());
+
+    Ok(())
+}
+
+/// Removes the login started with `state` and returns it unless it has expired.
+///
+/// A state can only be used once, so a replayed callback finds nothing.
+#[instrument(skip_all, fields(db.operation = "take_oidc_login"))]
+pub fn take_oidc_login(
+    conn: &mut DbConnection,
+    state: &str
+) -> QueryResult<Option<OidcLogin>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("take_oidc_login");
+
+    let login = diesel::delete(dsl::oidc_logins.find(state))
+        .get_result::<OidcLogin>(conn)
+        .optional()?
+        .filter(|login| login.expires_at > Utc::now().naive_utc());
+
+    // Record query duration
+    DbMetrics::record_duration("take_oidc_login", timer.elapsed_secs());
+
+    Ok(login)
+}
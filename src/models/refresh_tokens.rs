@@ -0,0 +1,23 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::refresh_tokens;
+
+pub mod usecases;
+
+/// A refresh token issued at login or by rotation.
+///
+/// Only the SHA-256 hash of the token is stored. All tokens descending from
+/// the same login share a `family_id`, so that reuse of a rotated token can
+/// revoke the whole chain.
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub family_id: String,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
@@ -0,0 +1,249 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+use uuid::Uuid;
+use crate::{DbConnection, errors::ServiceError};
+use super::RefreshToken;
+use crate::schema::refresh_tokens::dsl;
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::refresh_tokens)]
+struct NewRefreshToken<'a> {
+    user_id: i32,
+    token_hash: &'a str,
+    family_id: &'a str,
+    expires_at: NaiveDateTime,
+}
+
+/// Result of presenting a refresh token for rotation
+#[derive(Debug, PartialEq)]
+pub enum RotationOutcome {
+    /// The token was valid; it is now used and `token` replaces it
+    Rotated { user_id: i32, token: String },
+    /// No token with this value exists
+    Invalid,
+    /// The token exists but has expired
+    Expired,
+    /// The token was already rotated or revoked; its whole family is now revoked
+    Reused { user_id: i32, family_id: String },
+}
+
+/// Generates a new random refresh token value
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Returns the hex encoded SHA-256 hash under which a token is stored
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn insert_token(
+    conn: &mut DbConnection,
+    user_id: i32,
+    family_id: &str,
+    ttl_secs: i64
+) -> QueryResult<String> {
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+
+    diesel::insert_into(dsl::refresh_tokens)
+        .values(&NewRefreshToken {
+            user_id,
+            token_hash: &token_hash,
+            family_id,
+            expires_at: (Utc::now() + Duration::seconds(ttl_secs)).naive_utc(),
+        })
+        .execute(conn)?;
+
+    Ok(token)
+}
+
+/// Issues the first refresh token of a new family and returns its plain value
+#[instrument(skip(conn), fields(db.operation = "issue_refresh_token", db.user_id = %user_id))]
+pub fn issue_refresh_token(
+    conn: &mut DbConnection,
+    user_id: i32,
+    ttl_secs: i64
+) -> Result<String, ServiceError> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("issue_refresh_token");
+
+    let family_id = Uuid::new_v4().to_string();
+    let token = insert_token(conn, user_id, &family_id, ttl_secs)
+        .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+
+    // Record query duration
+    DbMetrics::record_duration("issue_refresh_token", timer.elapsed_secs());
+
+    Ok(token)
+}
+
+/// Exchanges a refresh token for a new one in the same family.
+///
+/// Presenting a token that was already rotated or revoked is treated as token
+/// theft, and every token of the family is revoked.
+#[instrument(skip(conn, token), fields(db.operation = "rotate_refresh_token"))]
+pub fn rotate_refresh_token(
+    conn: &mut DbConnection,
+    token: &str,
+    ttl_secs: i64
+) -> Result<RotationOutcome, ServiceError> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("rotate_refresh_token");
+
+    let token_hash = hash_token(token);
+    let outcome = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let current = dsl::refresh_tokens
+            .filter(dsl::token_hash.eq(&token_hash))
+            .for_update()
+            .first::<RefreshToken>(conn)
+            .optional()?;
+
+        let Some(current) = current else {
+            return Ok(RotationOutcome::Invalid);
+        };
+
+        let now = Utc::now().naive_utc();
+
+        if current.used_at.is_some() || current.revoked_at.is_some() {
+            revoke_family(conn, &current.family_id)?;
+            return Ok(RotationOutcome::Reused { user_id: current.user_id, family_id: current.family_id });
+        }
+
+        if current.expires_at <= now {
+            return Ok(RotationOutcome::Expired);
+        }
+
+        diesel::update(&current)
+            .set(dsl::used_at.eq(now))
+            .execute(conn)?;
+
+        let token = insert_token(conn, current.user_id, &current.family_id, ttl_secs)?;
+
+        Ok(RotationOutcome::Rotated { user_id: current.user_id, token })
+    })
+    .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+
+    // Record query duration
+    DbMetrics::record_duration("rotate_refresh_token", timer.elapsed_secs());
+
+    Ok(outcome)
+}
+
+/// Revokes every token of a family that is not revoked yet
+#[instrument(skip(conn), fields(db.operation = "revoke_refresh_token_family"))]
+pub fn revoke_family(
+    conn: &mut DbConnection,
+    family_id: &str
+) -> QueryResult<usize> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("revoke_refresh_token_family");
+
+    let count = diesel::update(dsl::refresh_tokens)
+        .filter(dsl::family_id.eq(family_id))
+        .filter(dsl::revoked_at.is_null())
+        .set(dsl::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("revoke_refresh_token_family", timer.elapsed_secs());
+
+    Ok(count)
+}
+
+/// Revokes the family of the given refresh token, e.g. on logout
+#[instrument(skip(conn, token), fields(db.operation = "revoke_refresh_token"))]
+pub fn revoke_refresh_token(
+    conn: &mut DbConnection,
+    token: &str
+) -> QueryResult<usize> {
+    let family_id = dsl::refresh_tokens
+        .filter(dsl::token_hash.eq(hash_token(token)))
+        .select(dsl::family_id)
+        .first::<String>(conn)
+        .optional()?;
+
+    match family_id {
+        Some(family_id) => revoke_family(conn, &family_id),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_test_connection_pool;
+    use crate::models::users::usecases::insert_new_user;
+
+    #[test]
+    fn rotate_refresh_token_test() {
+        let pool = create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+
+        conn.test_transaction::<_, ServiceError, _>(|conn| {
+            let user = insert_new_user(conn, "refresh_rotate".to_string(), None, None, None, None, None)?;
+            let first = issue_refresh_token(conn, user.id, 60)?;
+
+            let RotationOutcome::Rotated { user_id, token: second } = rotate_refresh_token(conn, &first, 60)? else {
+                panic!("first rotation should succeed");
+            };
+            assert_eq!(user_id, user.id);
+            assert_ne!(first, second);
+
+            assert!(matches!(rotate_refresh_token(conn, &second, 60)?, RotationOutcome::Rotated { .. }));
+            assert_eq!(rotate_refresh_token(conn, "unknown", 60)?, RotationOutcome::Invalid);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn refresh_token_reuse_revokes_family_test() {
+        let pool = create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+
+        conn.test_transaction::<_, ServiceError, _>(|conn| {
+            let user = insert_new_user(conn, "refresh_reuse".to_string(), None, None, None, None, None)?;
+            let first = issue_refresh_token(conn, user.id, 60)?;
+            let RotationOutcome::Rotated { token: second, .. } = rotate_refresh_token(conn, &first, 60)? else {
+                panic!("first rotation should succeed");
+            };
+
+            // Replaying the rotated token revokes the family, including the latest token
+            assert!(matches!(rotate_refresh_token(conn, &first, 60)?, RotationOutcome::Reused { .. }));
+            assert!(matches!(rotate_refresh_token(conn, &second, 60)?, RotationOutcome::Reused { .. }));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn expired_refresh_token_test() {
+        let pool = create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+
+        conn.test_transaction::<_, ServiceError, _>(|conn| {
+            let user = insert_new_user(conn, "refresh_expired".to_string(), None, None, None, None, None)?;
+            let token = issue_refresh_token(conn, user.id, -1)?;
+
+            assert_eq!(rotate_refresh_token(conn, &token, 60)?, RotationOutcome::Expired);
+            Ok(())
+        })
+    }
+}
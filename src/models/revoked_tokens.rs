@@ -0,0 +1,15 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use crate::schema::revoked_tokens;
+
+pub mod usecases;
+
+/// An access token revoked before its expiry, identified by its `jti` claim
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = revoked_tokens, primary_key(jti))]
+pub struct RevokedToken {
+    pub jti: String,
+    pub user_id: i32,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: NaiveDateTime,
+}
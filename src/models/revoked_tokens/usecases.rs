@@ -0,0 +1,81 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use crate::schema::revoked_tokens::dsl;
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::revoked_tokens)]
+struct NewRevokedToken<'a> {
+    jti: &'a str,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+}
+
+#[instrument(skip(conn), fields(db.operation = "insert_revoked_token", db.user_id = %user_id))]
+pub fn insert_revoked_token(
+    conn: &mut DbConnection,
+    jti: &str,
+    user_id: i32,
+    expires_at: NaiveDateTime
+) -> QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("insert_revoked_token");
+
+    diesel::insert_into(dsl::revoked_tokens)
+        .values(&NewRevokedToken { jti, user_id, expires_at })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("insert_revoked_token", timer.elapsed_secs());
+
+    Ok(())
+}
+
+#[instrument(skip(conn), fields(db.operation = "is_token_revoked"))]
+pub fn is_token_revoked(
+    conn: &mut DbConnection,
+    jti: &str
+) -> QueryResult<bool> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("is_token_revoked");
+
+    let revoked = diesel::select(diesel::dsl::exists(
+        dsl::revoked_tokens.filter(dsl::jti.eq(jti))
+    ))
+    .get_result(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("is_token_revoked", timer.elapsed_secs());
+
+    Ok(revoked)
+}
+
+/// Deletes revocations of tokens that have expired anyway
+#[instrument(skip(conn), fields(db.operation = "purge_expired_revocations"))]
+pub fn purge_expired_revocations(
+    conn: &mut DbConnection
+) -> QueryResult<usize> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("purge_expired_revocations");
+
+    let count = diesel::delete(dsl::revoked_tokens)
+        // Expiries are stored in UTC, while `now` would be in the session's time zone
+        .filter(dsl::expires_at.lt(Utc::now().naive_utc()))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("purge_expired_revocations", timer.elapsed_secs());
+
+    Ok(count)
+}
@@ -0,0 +1,13 @@
+use diesel::prelude::*;
+use crate::schema::roles;
+
+pub mod usecases;
+
+/// A role that can be granted to users; its permissions are defined in `rbac`
+#[derive(Clone, Queryable, Identifiable, Debug)]
+#[diesel(table_name = roles)]
+pub struct Role {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+}
@@ -0,0 +1,65 @@
+use diesel::prelude::*;
+use tracing::instrument;
+use crate::DbConnection;
+use crate::schema::{roles, user_roles};
+
+#[instrument(skip(conn), fields(db.operation = "find_user_roles", db.user_id = %user_id))]
+pub fn find_user_roles(
+    conn: &mut DbConnection,
+    user_id: i32
+) -> QueryResult<Vec<String>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_user_roles");
+
+    let names = user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::user_id.eq(user_id))
+        .select(roles::name)
+        .order(roles::name)
+        .load::<String>(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_user_roles", timer.elapsed_secs());
+
+    Ok(names)
+}
+
+/// Replaces the roles of a user; names without a `roles` row are ignored
+#[instrument(skip(conn), fields(db.operation = "set_user_roles", db.user_id = %user_id))]
+pub fn set_user_roles(
+    conn: &mut DbConnection,
+    user_id: i32,
+    names: &[String]
+) -> QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("set_user_roles");
+
+    conn.transaction(|conn| {
+        diesel::delete(user_roles::table.filter(user_roles::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        let role_ids = roles::table
+            .filter(roles::name.eq_any(names))
+            .select(roles::id)
+            .load::<i32>(conn)?;
+
+        let rows: Vec<_> = role_ids.into_iter()
+            .map(|role_id| (user_roles::user_id.eq(user_id), user_roles::role_id.eq(role_id)))
+            .collect();
+
+        diesel::insert_into(user_roles::table)
+            .values(&rows)
+            .execute(conn)
+    })?;
+
+    // Record query duration
+    DbMetrics::record_duration("set_user_roles", timer.elapsed_secs());
+
+    Ok(())
+}
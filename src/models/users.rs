@@ -1,8 +1,14 @@
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 pub mod usecases;
 
+/// `users.source` of users provisioned by the directory, on login or by the sync
+pub const SOURCE_DIRECTORY: &str = "directory";
+/// `users.source` of users provisioned by an OIDC login
+pub const SOURCE_OIDC: &str = "oidc";
+
 #[derive(Clone, Queryable, Deserialize, Serialize, ToSchema, Debug)]
 pub struct User {
     pub id: i32,
@@ -11,7 +17,13 @@ pub struct User {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub email: Option<String>,
-    pub gecos: Option<String>
+    pub gecos: Option<String>,
+    /// Time of the last successful login
+    pub last_login_at: Option<NaiveDateTime>,
+    /// Set when the user left the directory; deactivated users are rejected
+    pub deactivated_at: Option<NaiveDateTime>,
+    /// How the user was provisioned: `directory` or `oidc`
+    pub source: String,
 }
 
 use validator::Validate;
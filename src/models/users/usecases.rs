@@ -2,7 +2,8 @@ use diesel::prelude::*;
 use utoipa::ToSchema;
 use tracing::instrument;
 use crate::DbConnection;
-use super::User;
+use crate::user_cache::UserCache;
+use super::{User, SOURCE_DIRECTORY, SOURCE_OIDC};
 use crate::schema::users::dsl;
 
 #[derive(Debug, Insertable, ToSchema)]
@@ -13,7 +14,8 @@ pub struct NewUser<'a> {
     first_name: Option<&'a str>,
     last_name: Option<&'a str>,
     email: Option<&'a str>,
-    gecos: Option<&'a str>
+    gecos: Option<&'a str>,
+    source: &'a str,
 }
 
 #[instrument(skip(conn), fields(db.operation = "insert_user", db.user = %uid))]
@@ -25,8 +27,6 @@ pub fn insert_new_user(conn: &mut DbConnection, uid: String,
         gecos: Option<String>
     ) -> Result<User, crate::errors::ServiceError> {
         use crate::metrics::{DbMetrics, DurationTimer};
-        use crate::traits::IntoValidator;
-        use validator::Validate;
 
         // Requirements: 12.5 - Database metrics collection
         let timer = DurationTimer::new();
@@ -42,15 +42,13 @@ pub fn insert_new_user(conn: &mut DbConnection, uid: String,
             last_name: last_name.clone(),
             email: email.clone(),
             gecos: gecos.clone(),
+            last_login_at: None,
+            deactivated_at: None,
+            source: SOURCE_DIRECTORY.to_string(),
         };
 
         // Validate user data before insertion
-        temp_user.validator()
-            .validate()
-            .map_err(|e| {
-                tracing::warn!(error = ?e, "User validation failed");
-                crate::errors::ServiceError::ValidationError { value: e }
-            })?;
+        validate_user(&temp_user)?;
 
         // Create insertion model
         let new_user = NewUser {
@@ -60,6 +58,7 @@ pub fn insert_new_user(conn: &mut DbConnection, uid: String,
             last_name: last_name.as_ref().map(|s| s.as_ref()),
             email: email.as_ref().map(|s| s.as_ref()),
             gecos: gecos.as_ref().map(|s| s.as_ref()),
+            source: SOURCE_DIRECTORY,
         };
 
         // normal diesel operations
@@ -77,6 +76,348 @@ pub fn insert_new_user(conn: &mut DbConnection, uid: String,
         Ok(user)
     }
 
+/// Result of saving a directory profile with [`upsert_login_user`] or [`sync_directory_user`]
+#[derive(Debug)]
+pub struct ProfileUpsert {
+    pub user: User,
+    /// True if the user did not exist yet
+    pub created: bool,
+    /// True if the directory sync reactivated the user
+    pub reactivated: bool,
+    /// Names of the profile attributes that differed from the stored ones
+    pub changed: Vec<&'static str>,
+}
+
+/// Creates or updates a user from the profile returned by the directory on login,
+/// and records the login time.
+///
+/// Deactivated users are left untouched and rejected with `ServiceError::Forbidden`;
+/// only the directory sync reactivates them. So are users provisioned by an OIDC
+/// login, whose login id the directory may give to someone else.
+#[instrument(skip(conn, employee_number, first_name, last_name, email, gecos), fields(db.operation = "upsert_login_user", db.user = %uid))]
+pub fn upsert_login_user(conn: &mut DbConnection, uid: String,
+        employee_number: Option<i32>,
+        first_name: Option<String>,
+        last_name: Option<String>,
+        email: Option<String>,
+        gecos: Option<String>
+    ) -> Result<ProfileUpsert, crate::errors::ServiceError> {
+        use crate::metrics::{DbMetrics, DurationTimer};
+
+        // Requirements: 12.5 - Database metrics collection
+        let timer = DurationTimer::new();
+        DbMetrics::record_query("upsert_login_user");
+
+        // Requirements: 11.2 - Input validation
+        let profile = profile_user(uid, employee_number, first_name, last_name, email, gecos)?;
+
+        let result = conn.transaction(|conn| {
+            let existing = dsl::users
+                .filter(dsl::login_id.eq(&profile.login_id))
+                .select((dsl::deactivated_at, dsl::source))
+                .for_update()
+                .first::<(Option<chrono::NaiveDateTime>, String)>(conn)
+                .optional()?;
+            match existing {
+                Some((Some(_), _)) => return Ok(Err("Account has been deactivated")),
+                Some((_, source)) if source != SOURCE_DIRECTORY => return Ok(Err("Account is not provisioned by the directory")),
+                _ => {}
+            }
+
+            let mut upsert = upsert_profile(conn, &profile, false)?;
+            upsert.user = diesel::update(dsl::users.find(upsert.user.id))
+                .set(dsl::last_login_at.eq(diesel::dsl::now))
+                .get_result(conn)?;
+            Ok(Ok(upsert))
+        })
+        .map_err(|e: diesel::result::Error| {
+            tracing::error!(error = ?e, "Database error during user upsert");
+            crate::errors::ServiceError::InternalServerError
+        })?;
+        let result = result.map_err(|message| {
+            tracing::warn!(username = %profile.login_id, reason = %message, "Login rejected");
+            crate::errors::ServiceError::Forbidden { message: message.to_string() }
+        })?;
+
+        UserCache::invalidate(result.user.id);
+
+        // Record query duration
+        DbMetrics::record_duration("upsert_login_user", timer.elapsed_secs());
+
+        Ok(result)
+    }
+
+/// Creates or updates a user found by the directory sync.
+///
+/// Users provisioned by an OIDC login are left untouched and rejected with
+/// `ServiceError::Forbidden`.
+#[instrument(skip(conn, employee_number, first_name, last_name, email, gecos), fields(db.operation = "sync_directory_user", db.user = %uid))]
+pub fn sync_directory_user(conn: &mut DbConnection, uid: String,
+        employee_number: Option<i32>,
+        first_name: Option<String>,
+        last_name: Option<String>,
+        email: Option<String>,
+        gecos: Option<String>
+    ) -> Result<ProfileUpsert, crate::errors::ServiceError> {
+        use crate::metrics::{DbMetrics, DurationTimer};
+
+        // Requirements: 12.5 - Database metrics collection
+        let timer = DurationTimer::new();
+        DbMetrics::record_query("sync_directory_user");
+
+        // Requirements: 11.2 - Input validation
+        let profile = profile_user(uid, employee_number, first_name, last_name, email, gecos)?;
+
+        let result = conn.transaction(|conn| {
+            let source = dsl::users
+                .filter(dsl::login_id.eq(&profile.login_id))
+                .select(dsl::source)
+                .for_update()
+                .first::<String>(conn)
+                .optional()?;
+            if source.is_some_and(|source| source != SOURCE_DIRECTORY) {
+                return Ok(None);
+            }
+            upsert_profile(conn, &profile, true).map(Some)
+        })
+        .map_err(|e: diesel::result::Error| {
+            tracing::error!(error = ?e, "Database error during user sync");
+            crate::errors::ServiceError::InternalServerError
+        })?;
+        let Some(result) = result else {
+            return Err(crate::errors::ServiceError::Forbidden { message: "Account is not provisioned by the directory".to_string() });
+        };
+
+        UserCache::invalidate(result.user.id);
+
+        // Record query duration
+        DbMetrics::record_duration("sync_directory_user", timer.elapsed_secs());
+
+        Ok(result)
+    }
+
+/// Deactivates the active users of the directory whose login id is not in
+/// `present_login_ids`, and returns their login ids
+#[instrument(skip(conn, present_login_ids), fields(db.operation = "deactivate_missing_users", present = present_login_ids.len()))]
+pub fn deactivate_missing_users(
+    conn: &mut DbConnection,
+    present_login_ids: &[String]
+) -> diesel::QueryResult<Vec<String>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("deactivate_missing_users");
+
+    let deactivated = diesel::update(
+        dsl::users
+            .filter(dsl::deactivated_at.is_null())
+            .filter(dsl::source.eq(SOURCE_DIRECTORY))
+            .filter(dsl::login_id.ne_all(present_login_ids))
+    )
+    .set(dsl::deactivated_at.eq(diesel::dsl::now))
+    .returning(dsl::login_id)
+    .get_results(conn)?;
+    UserCache::invalidate_login_ids(&deactivated);
+
+    // Record query duration
+    DbMetrics::record_duration("deactivate_missing_users", timer.elapsed_secs());
+
+    Ok(deactivated)
+}
+
+/// Logs in the user an OIDC identity is linked to, creating the user on the
+/// identity's first login, and records the login time.
+///
+/// Only the profiles of users the OIDC login created are updated; users of the
+/// directory keep the directory's. A new identity whose login id is taken is
+/// rejected with `ServiceError::Forbidden` rather than linked to that user, and
+/// so are deactivated users.
+#[instrument(skip(conn, employee_number, first_name, last_name, email, gecos), fields(db.operation = "upsert_oidc_user", db.user = %uid))]
+#[allow(clippy::too_many_arguments)]
+pub fn upsert_oidc_user(conn: &mut DbConnection, issuer: &str, subject: &str, uid: String,
+        employee_number: Option<i32>,
+        first_name: Option<String>,
+        last_name: Option<String>,
+        email: Option<String>,
+        gecos: Option<String>
+    ) -> Result<ProfileUpsert, crate::errors::ServiceError> {
+        use crate::metrics::{DbMetrics, DurationTimer};
+        use crate::models::oidc_identities::usecases::{find_oidc_user, link_oidc_identity};
+
+        // Requirements: 12.5 - Database metrics collection
+        let timer = DurationTimer::new();
+        DbMetrics::record_query("upsert_oidc_user");
+
+        // Requirements: 11.2 - Input validation
+        let profile = profile_user(uid, employee_number, first_name, last_name, email, gecos)?;
+
+        let result = conn.transaction(|conn| {
+            let linked = match find_oidc_user(conn, issuer, subject)? {
+                Some(user) => Some(dsl::users.find(user.id).for_update().first::<User>(conn)?),
+                None => None,
+            };
+
+            let (user, created, changed) = match linked {
+                Some(user) if user.deactivated_at.is_some() => return Ok(Err("Account has been deactivated")),
+                Some(user) if user.source == SOURCE_OIDC => {
+                    let updated = diesel::update(dsl::users.find(user.id))
+                        .set((
+                            dsl::employee_number.eq(profile.employee_number),
+                            dsl::first_name.eq(&profile.first_name),
+                            dsl::last_name.eq(&profile.last_name),
+                            dsl::email.eq(&profile.email),
+                            dsl::gecos.eq(&profile.gecos),
+                        ))
+                        .get_result::<User>(conn)?;
+                    let changed = changed_attributes(&user, &updated);
+                    (updated, false, changed)
+                }
+                Some(user) => (user, false, Vec::new()),
+                None => {
+                    let new_user = NewUser {
+                        login_id: &profile.login_id,
+                        employee_number: profile.employee_number,
+                        first_name: profile.first_name.as_deref(),
+                        last_name: profile.last_name.as_deref(),
+                        email: profile.email.as_deref(),
+                        gecos: profile.gecos.as_deref(),
+                        source: SOURCE_OIDC,
+                    };
+                    let inserted = diesel::insert_into(dsl::users)
+                        .values(&new_user)
+                        .on_conflict(dsl::login_id)
+                        .do_nothing()
+                        .get_result::<User>(conn)
+                        .optional()?;
+                    let Some(user) = inserted else {
+                        return Ok(Err("Login id is taken by a user the identity is not linked to"));
+                    };
+                    if link_oidc_identity(conn, issuer, subject, user.id)? != user.id {
+                        // A concurrent first login linked the identity to another user
+                        return Err(diesel::result::Error::RollbackTransaction);
+                    }
+                    (user, true, Vec::new())
+                }
+            };
+
+            let user = diesel::update(dsl::users.find(user.id))
+                .set(dsl::last_login_at.eq(diesel::dsl::now))
+                .get_result(conn)?;
+            Ok(Ok(ProfileUpsert { user, created, reactivated: false, changed }))
+        })
+        .map_err(|e: diesel::result::Error| {
+            tracing::error!(error = ?e, "Database error during OIDC user upsert");
+            crate::errors::ServiceError::InternalServerError
+        })?;
+        let result = result.map_err(|message| {
+            tracing::warn!(username = %profile.login_id, reason = %message, "OIDC login rejected");
+            crate::errors::ServiceError::Forbidden { message: message.to_string() }
+        })?;
+
+        UserCache::invalidate(result.user.id);
+
+        // Record query duration
+        DbMetrics::record_duration("upsert_oidc_user", timer.elapsed_secs());
+
+        Ok(result)
+    }
+
+/// Builds and validates the user described by a directory profile
+fn profile_user(uid: String,
+        employee_number: Option<i32>,
+        first_name: Option<String>,
+        last_name: Option<String>,
+        email: Option<String>,
+        gecos: Option<String>
+    ) -> Result<User, crate::errors::ServiceError> {
+        let profile = User {
+            id: 0, // Temporary ID for validation
+            login_id: uid,
+            employee_number,
+            first_name,
+            last_name,
+            email,
+            gecos,
+            last_login_at: None,
+            deactivated_at: None,
+            source: SOURCE_DIRECTORY.to_string(),
+        };
+        validate_user(&profile)?;
+        Ok(profile)
+    }
+
+/// Inserts or updates the profile attributes, and reactivates the user if `reactivate`; runs inside the caller's transaction
+fn upsert_profile(conn: &mut DbConnection, profile: &User, reactivate: bool) -> diesel::QueryResult<ProfileUpsert> {
+    let existing = dsl::users
+        .filter(dsl::login_id.eq(&profile.login_id))
+        .for_update()
+        .first::<User>(conn)
+        .optional()?;
+
+    let new_user = NewUser {
+        login_id: &profile.login_id,
+        employee_number: profile.employee_number,
+        first_name: profile.first_name.as_deref(),
+        last_name: profile.last_name.as_deref(),
+        email: profile.email.as_deref(),
+        gecos: profile.gecos.as_deref(),
+        source: SOURCE_DIRECTORY,
+    };
+
+    // A concurrent first login of the same user ends up in the update branch
+    let mut user = diesel::insert_into(dsl::users)
+        .values(&new_user)
+        .on_conflict(dsl::login_id)
+        .do_update()
+        .set((
+            dsl::employee_number.eq(new_user.employee_number),
+            dsl::first_name.eq(new_user.first_name),
+            dsl::last_name.eq(new_user.last_name),
+            dsl::email.eq(new_user.email),
+            dsl::gecos.eq(new_user.gecos),
+        ))
+        .get_result::<User>(conn)?;
+    if reactivate && user.deactivated_at.is_some() {
+        user = diesel::update(dsl::users.find(user.id))
+            .set(dsl::deactivated_at.eq(None::<chrono::NaiveDateTime>))
+            .get_result(conn)?;
+    }
+
+    Ok(ProfileUpsert {
+        changed: existing.as_ref().map(|existing| changed_attributes(existing, &user)).unwrap_or_default(),
+        created: existing.is_none(),
+        reactivated: reactivate && existing.as_ref().is_some_and(|existing| existing.deactivated_at.is_some()),
+        user,
+    })
+}
+
+/// Returns the names of the profile attributes that differ between two versions of a user
+fn changed_attributes(before: &User, after: &User) -> Vec<&'static str> {
+    [
+        ("employee_number", before.employee_number != after.employee_number),
+        ("first_name", before.first_name != after.first_name),
+        ("last_name", before.last_name != after.last_name),
+        ("email", before.email != after.email),
+        ("gecos", before.gecos != after.gecos),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect()
+}
+
+fn validate_user(user: &User) -> Result<(), crate::errors::ServiceError> {
+    use crate::traits::IntoValidator;
+    use validator::Validate;
+
+    user.validator()
+        .validate()
+        .map_err(|e| {
+            tracing::warn!(error = ?e, "User validation failed");
+            crate::errors::ServiceError::ValidationError { value: e }
+        })
+}
+
 #[instrument(skip(conn), fields(db.operation = "find_user", db.user_id = %user_id))]
 pub fn find_user(
     conn: &mut DbConnection,
@@ -162,3 +503,60 @@ pub fn all_user_paginated(
 
     Ok(results)
 }
+
+#[instrument(skip(conn), fields(db.operation = "find_local_credential", db.user = %login_id))]
+pub fn find_local_credential(
+    conn: &mut DbConnection,
+    login_id: &str
+) -> diesel::QueryResult<Option<(User, String)>> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+    use crate::schema::local_credentials;
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("find_local_credential");
+
+    let result = dsl::users
+        .inner_join(local_credentials::table)
+        .filter(dsl::login_id.eq(login_id))
+        .select((crate::schema::users::all_columns, local_credentials::password_hash))
+        .first::<(User, String)>(conn)
+        .optional()?;
+
+    // Record query duration
+    DbMetrics::record_duration("find_local_credential", timer.elapsed_secs());
+
+    Ok(result)
+}
+
+#[instrument(skip(conn, password_hash), fields(db.operation = "set_local_password_hash", db.user_id = %user_id))]
+pub fn set_local_password_hash(
+    conn: &mut DbConnection,
+    user_id: i32,
+    password_hash: &str
+) -> diesel::QueryResult<()> {
+    use crate::metrics::{DbMetrics, DurationTimer};
+    use crate::schema::local_credentials;
+
+    // Requirements: 12.5 - Database metrics collection
+    let timer = DurationTimer::new();
+    DbMetrics::record_query("set_local_password_hash");
+
+    diesel::insert_into(local_credentials::table)
+        .values((
+            local_credentials::user_id.eq(user_id),
+            local_credentials::password_hash.eq(password_hash),
+        ))
+        .on_conflict(local_credentials::user_id)
+        .do_update()
+        .set((
+            local_credentials::password_hash.eq(password_hash),
+            local_credentials::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+
+    // Record query duration
+    DbMetrics::record_duration("set_local_password_hash", timer.elapsed_secs());
+
+    Ok(())
+}
@@ -0,0 +1,439 @@
+//! Rate limiting middleware
+//!
+//! A [`RateLimit`] wraps a route or scope with a [`RateLimitPolicy`], which
+//! says what requests are counted by (client IP, user id or API key) and how
+//! many are allowed per period. Counts are kept by the [`RateLimiter`]
+//! registered as app data, in Redis so that every instance shares them, or in
+//! memory for a single instance:
+//!
+//! ```ignore
+//! App::new()
+//!     .app_data(web::Data::new(RateLimiter::from_config(&config)?))
+//!     .service(web::scope("/api").wrap(RateLimit::api()))
+//! ```
+//!
+//! Responses carry `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset`
+//! and `RateLimit-Policy` headers, and rejected requests a `Retry-After`.
+
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_limitation::{Error as LimitationError, Limiter};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use crate::config::{self, Config};
+use crate::middleware::decode_request_token;
+use crate::models::api_tokens::is_api_token;
+use crate::models::refresh_tokens::usecases::hash_token;
+
+/// Header carrying the key for [`KeyBy::ApiKey`]
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Headers added to responses besides `Retry-After`: limit, remaining, reset and policy
+pub const RESPONSE_HEADERS: [&str; 4] = ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset", "ratelimit-policy"];
+
+/// Number of in-memory windows above which expired ones are dropped
+const MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+/// What requests are counted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyBy {
+    /// The client IP
+    Ip,
+    /// The user id of a valid access token, the API token, or the client IP without either
+    User,
+    /// The `X-API-Key` header or the bearer token, or the client IP without either
+    ApiKey,
+}
+
+impl KeyBy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "ip" => Ok(KeyBy::Ip),
+            "user" => Ok(KeyBy::User),
+            "api_key" => Ok(KeyBy::ApiKey),
+            other => Err(format!("Invalid rate limit key: '{}'. Must be 'ip', 'user' or 'api_key'", other)),
+        }
+    }
+}
+
+/// How many requests a route allows per period, and per what
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitPolicy {
+    /// Distinguishes the counts of different policies for the same client
+    pub name: &'static str,
+    pub key_by: KeyBy,
+    /// Requests allowed per period; 0 disables the limit
+    pub limit: usize,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    /// `POST /login`: `RATE_LIMIT_REQUESTS` per `RATE_LIMIT_PERIOD_SECS` and client IP
+    pub fn login(config: &Config) -> Self {
+        RateLimitPolicy {
+            name: "login",
+            key_by: KeyBy::Ip,
+            limit: config.get_rate_limit_requests(),
+            period: Duration::from_secs(config.get_rate_limit_period_secs()),
+        }
+    }
+
+    /// `/api`: `RATE_LIMIT_API_REQUESTS` per `RATE_LIMIT_API_PERIOD_SECS`, counted by `RATE_LIMIT_API_KEY_BY`
+    pub fn api(config: &Config) -> Result<Self, String> {
+        Ok(RateLimitPolicy {
+            name: "api",
+            key_by: KeyBy::parse(&config.get_rate_limit_api_key_by())
+                .map_err(|e| format!("RATE_LIMIT_API_KEY_BY: {}", e))?,
+            limit: config.get_rate_limit_api_requests(),
+            period: Duration::from_secs(config.get_rate_limit_api_period_secs()),
+        })
+    }
+
+    /// Returns the key the request is counted under, trusting the forwarding headers for the client IP if `trust_proxy`
+    pub fn key(&self, req: &ServiceRequest, trust_proxy: bool) -> String {
+        let bearer = || req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        let client = match self.key_by {
+            KeyBy::Ip => None,
+            KeyBy::User => bearer().and_then(|token| if is_api_token(token) {
+                // Resolving the user would need the database, so the token stands in for them
+                Some(format!("key:{}", hash_token(token)))
+            } else {
+                // Left in the request for `validator`, so the token is decoded once
+                decode_request_token(req, token).map(|claims| format!("user:{}", claims.id))
+            }),
+            KeyBy::ApiKey => req.headers()
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .or_else(bearer)
+                // Keys are hashed so that they do not end up in Redis
+                .map(|key| format!("key:{}", hash_token(key))),
+        };
+
+        let client = client.unwrap_or_else(|| format!("ip:{}", client_ip(req, trust_proxy)));
+        format!("rate_limit:{}:{}", self.name, client)
+    }
+
+    /// Returns the `RateLimit-Policy` header value
+    fn header_value(&self) -> String {
+        format!("{};w={}", self.limit, self.period.as_secs())
+    }
+}
+
+/// Returns the client IP, from the forwarding headers only if `trust_proxy`
+fn client_ip(req: &ServiceRequest, trust_proxy: bool) -> String {
+    let forwarded = trust_proxy
+        .then(|| req.connection_info().realip_remote_addr().map(str::to_string))
+        .flatten();
+
+    forwarded
+        .or_else(|| req.peer_addr().map(|addr| addr.ip().to_string()))
+        // Only requests without a socket, e.g. in tests, share this bucket
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Outcome of counting one request
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: usize,
+    pub remaining: usize,
+    /// Time until the period ends and the count starts over
+    pub reset_after: Duration,
+    /// True if the request is over the limit
+    pub exceeded: bool,
+}
+
+struct MemoryWindow {
+    count: usize,
+    reset_at: Instant,
+}
+
+enum Backend {
+    /// One limiter per limit and period, since `actix-limitation` fixes them per instance
+    Redis { url: String, limiters: Mutex<HashMap<(usize, Duration), Limiter>> },
+    Memory(Mutex<HashMap<String, MemoryWindow>>),
+}
+
+/// Keeps request counts in the backend selected by `RATE_LIMIT_BACKEND_URL`
+pub struct RateLimiter {
+    backend: Backend,
+    fail_open: bool,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let backend = match &self.backend {
+            Backend::Redis { .. } => "redis",
+            Backend::Memory(_) => "memory",
+        };
+        f.debug_struct("RateLimiter")
+            .field("backend", &backend)
+            .field("fail_open", &self.fail_open)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        // Fail fast on an invalid policy instead of on the first request
+        RateLimitPolicy::api(config)?;
+
+        let url = config.get_rate_limit_backend_url();
+        let backend = if url == "memory://" {
+            Backend::Memory(Mutex::new(HashMap::new()))
+        } else if url.starts_with("redis://") || url.starts_with("rediss://") {
+            Limiter::builder(url.as_str())
+                .build()
+                .map_err(|e| format!("Invalid RATE_LIMIT_BACKEND_URL: {}", e))?;
+            Backend::Redis { url, limiters: Mutex::new(HashMap::new()) }
+        } else {
+            return Err(format!(
+                "Invalid RATE_LIMIT_BACKEND_URL: '{}'. Must be a redis:// URL or memory://",
+                url
+            ));
+        };
+
+        Ok(RateLimiter { backend, fail_open: config.is_rate_limit_fail_open() })
+    }
+
+    /// Returns a limiter counting in this process only
+    pub fn memory() -> Self {
+        RateLimiter { backend: Backend::Memory(Mutex::new(HashMap::new())), fail_open: false }
+    }
+
+    /// Returns true if requests pass while the backend fails
+    pub fn is_fail_open(&self) -> bool {
+        self.fail_open
+    }
+
+    /// Checks that the backend answers, by counting a request under a key of its own
+    pub async fn check(&self) -> Result<(), String> {
+        let policy = RateLimitPolicy { name: "health", key_by: KeyBy::Ip, limit: 1, period: Duration::from_secs(1) };
+        self.hit("rate_limit:health", &policy).await.map(|_| ())
+    }
+
+    /// Counts a request under `key`
+    pub async fn hit(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitStatus, String> {
+        match &self.backend {
+            Backend::Redis { url, limiters } => {
+                let limiter = {
+                    let mut limiters = limiters.lock().map_err(|e| e.to_string())?;
+                    match limiters.get(&(policy.limit, policy.period)) {
+                        Some(limiter) => limiter.clone(),
+                        None => {
+                            let limiter = Limiter::builder(url.as_str())
+                                .limit(policy.limit)
+                                .period(policy.period)
+                                .build()
+                                .map_err(|e| e.to_string())?;
+                            limiters.insert((policy.limit, policy.period), limiter.clone());
+                            limiter
+                        }
+                    }
+                };
+
+                let (status, exceeded) = match limiter.count(key).await {
+                    Ok(status) => (status, false),
+                    Err(LimitationError::LimitExceeded(status)) => (status, true),
+                    Err(e) => return Err(e.to_string()),
+                };
+                let now = chrono::Utc::now().timestamp().max(0) as usize;
+                Ok(RateLimitStatus {
+                    limit: status.limit(),
+                    remaining: status.remaining(),
+                    reset_after: Duration::from_secs(status.reset_epoch_utc().saturating_sub(now) as u64),
+                    exceeded,
+                })
+            }
+            Backend::Memory(windows) => {
+                let mut windows = windows.lock().map_err(|e| e.to_string())?;
+                let now = Instant::now();
+                if windows.len() > MEMORY_PRUNE_THRESHOLD {
+                    windows.retain(|_, window| window.reset_at > now);
+                }
+
+                let window = windows.entry(key.to_string())
+                    .and_modify(|window| if window.reset_at <= now {
+                        *window = MemoryWindow { count: 0, reset_at: now + policy.period };
+                    })
+                    .or_insert_with(|| MemoryWindow { count: 0, reset_at: now + policy.period });
+                window.count += 1;
+
+                Ok(RateLimitStatus {
+                    limit: policy.limit,
+                    remaining: policy.limit.saturating_sub(window.count),
+                    reset_after: window.reset_at.saturating_duration_since(now),
+                    exceeded: window.count > policy.limit,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum PolicySource {
+    Fixed(RateLimitPolicy),
+    /// Read from the configuration on every request, like the rest of the settings
+    Config(fn(&Config) -> Result<RateLimitPolicy, String>),
+}
+
+/// Middleware that rejects requests over the policy's limit with 429.
+///
+/// Requests pass unchecked if rate limiting is disabled or no
+/// [`RateLimiter`] is registered as app data.
+#[derive(Clone)]
+pub struct RateLimit {
+    policy: PolicySource,
+    /// Overrides `RATE_LIMIT_ENABLED`
+    enabled: Option<bool>,
+    /// Overrides `RATE_LIMIT_TRUST_PROXY`
+    trust_proxy: Option<bool>,
+}
+
+impl RateLimit {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        RateLimit { policy: PolicySource::Fixed(policy), enabled: None, trust_proxy: None }
+    }
+
+    /// Applies [`RateLimitPolicy::login`]
+    pub fn login() -> Self {
+        RateLimit { policy: PolicySource::Config(|config| Ok(RateLimitPolicy::login(config))), enabled: None, trust_proxy: None }
+    }
+
+    /// Applies [`RateLimitPolicy::api`]
+    pub fn api() -> Self {
+        RateLimit { policy: PolicySource::Config(RateLimitPolicy::api), enabled: None, trust_proxy: None }
+    }
+
+    /// Limits requests or lets them all pass, regardless of `RATE_LIMIT_ENABLED`
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = Some(enabled);
+        self
+    }
+
+    /// Takes the client IP from the forwarding headers or not, regardless of `RATE_LIMIT_TRUST_PROXY`
+    pub fn trust_proxy(mut self, trust_proxy: bool) -> Self {
+        self.trust_proxy = Some(trust_proxy);
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service: Rc::new(service), limit: self.clone() }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limit = self.limit.clone();
+
+        Box::pin(async move {
+            let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+            let config = config::get_config();
+            let (Some(limiter), Ok(config)) = (limiter, config) else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let policy = match limit.policy {
+                PolicySource::Fixed(policy) => policy,
+                PolicySource::Config(resolve) => match resolve(&config) {
+                    Ok(policy) => policy,
+                    Err(e) => {
+                        tracing::error!(error = %e, "Invalid rate limit policy");
+                        let response = HttpResponse::InternalServerError().finish();
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                },
+            };
+            let enabled = limit.enabled.unwrap_or_else(|| config.is_rate_limit_enabled());
+            if !enabled || policy.limit == 0 {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            }
+
+            let trust_proxy = limit.trust_proxy.unwrap_or_else(|| config.is_rate_limit_trust_proxy());
+            let key = policy.key(&req, trust_proxy);
+            let status = match limiter.hit(&key, &policy).await {
+                Ok(status) => status,
+                Err(e) if limiter.fail_open => {
+                    tracing::error!(error = %e, policy = policy.name, "Rate limiter error, letting the request pass");
+                    return service.call(req).await.map(ServiceResponse::map_into_left_body);
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, policy = policy.name, "Rate limiter error, rejecting the request");
+                    let response = HttpResponse::ServiceUnavailable().body("Rate limiting is unavailable");
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+            };
+
+            if status.exceeded {
+                tracing::warn!(key = %key, policy = policy.name, "Rate limit exceeded");
+                let mut response = HttpResponse::TooManyRequests()
+                    .insert_header((header::RETRY_AFTER, reset_secs(&status).to_string()))
+                    .body("Too many requests. Please try again later.");
+                insert_headers(response.headers_mut(), &policy, &status);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            insert_headers(res.headers_mut(), &policy, &status);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+/// Returns the seconds until the count starts over, rounded up
+fn reset_secs(status: &RateLimitStatus) -> u64 {
+    status.reset_after.as_secs() + u64::from(status.reset_after.subsec_nanos() > 0)
+}
+
+fn insert_headers(headers: &mut HeaderMap, policy: &RateLimitPolicy, status: &RateLimitStatus) {
+    let values = [
+        status.limit.to_string(),
+        status.remaining.to_string(),
+        reset_secs(status).to_string(),
+        policy.header_value(),
+    ];
+    for (name, value) in RESPONSE_HEADERS.into_iter().zip(values) {
+        if let Ok(value) = HeaderValue::try_from(value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
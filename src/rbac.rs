@@ -0,0 +1,173 @@
+//! Role-based access control
+//!
+//! Users hold roles through `user_roles`, which are issued in the `roles`
+//! claim of access tokens. What each role may do is defined here, and
+//! handlers declare what they need with an [`Authorized`] argument:
+//!
+//! ```ignore
+//! pub async fn insert_category(_auth: Authorized<CustomersWrite>, ...)
+//! ```
+//!
+//! Requests authenticated with an API token are limited to the token's
+//! scopes, on top of the user's roles. Impersonation tokens grant what the
+//! user's roles grant, except [`IMPERSONATION_EXCLUDED`].
+
+use std::fmt;
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use actix_web::{dev::Payload, http::header, FromRequest, HttpRequest};
+use actix_web::HttpMessage;
+use crate::{config, constants::permissions::*, errors::ServiceError, jwt};
+use crate::middleware::{ApiTokenAuth, UserClaims};
+
+/// Roles seeded by the `create_roles` migration and the permissions they grant
+const ROLES: [(&str, &[&str]); 4] = [
+    ("admin", &[CUSTOMERS_READ, CUSTOMERS_WRITE, USERS_READ, USERS_UNLOCK, USERS_IMPERSONATE, TOKENS_MANAGE, TOKENS_INTROSPECT, MFA_MANAGE, IDENTITIES_LINK]),
+    ("manager", &[CUSTOMERS_READ, CUSTOMERS_WRITE, USERS_READ, TOKENS_MANAGE, MFA_MANAGE, IDENTITIES_LINK]),
+    ("user", &[CUSTOMERS_READ, USERS_READ, TOKENS_MANAGE, MFA_MANAGE, IDENTITIES_LINK]),
+    ("readonly", &[CUSTOMERS_READ, USERS_READ, TOKENS_MANAGE, MFA_MANAGE, IDENTITIES_LINK]),
+];
+
+/// Permissions an API token may be granted; tokens cannot manage tokens, second factors or linked identities
+pub const API_TOKEN_SCOPES: [&str; 5] = [CUSTOMERS_READ, CUSTOMERS_WRITE, USERS_READ, USERS_UNLOCK, TOKENS_INTROSPECT];
+
+/// Permissions impersonation tokens never grant: an admin acting as a user
+/// cannot impersonate further, nor leave credentials, change the second factor
+/// or link an identity to log in as the user
+pub const IMPERSONATION_EXCLUDED: [&str; 4] = [USERS_IMPERSONATE, TOKENS_MANAGE, MFA_MANAGE, IDENTITIES_LINK];
+
+/// Returns true if `role` is defined
+pub fn is_known_role(role: &str) -> bool {
+    ROLES.iter().any(|(name, _)| *name == role)
+}
+
+/// Returns the permissions granted by `role`
+pub fn permissions_for(role: &str) -> &'static [&'static str] {
+    ROLES.iter()
+        .find(|(name, _)| *name == role)
+        .map(|(_, permissions)| *permissions)
+        .unwrap_or_default()
+}
+
+/// Returns true if any of `roles`, or the default role every user holds, grants `permission`
+pub fn has_permission(config: &config::Config, roles: &[String], permission: &str) -> bool {
+    config.get_rbac_default_role().iter()
+        .chain(roles)
+        .any(|role| permissions_for(role).contains(&permission))
+}
+
+/// Returns the permissions a token grants: those of its roles, limited to the
+/// scopes of an API token, and without [`IMPERSONATION_EXCLUDED`] when impersonating
+pub fn granted_permissions(config: &config::Config, claims: &UserClaims, api_token_scopes: Option<&[String]>) -> Vec<&'static str> {
+    let mut granted: Vec<&'static str> = Vec::new();
+    for permission in config.get_rbac_default_role().iter().chain(&claims.roles).flat_map(|role| permissions_for(role)) {
+        if !granted.contains(permission) {
+            granted.push(*permission);
+        }
+    }
+    granted.retain(|permission| {
+        api_token_scopes.is_none_or(|scopes| scopes.iter().any(|scope| scope == permission))
+            && !(claims.act.is_some() && IMPERSONATION_EXCLUDED.contains(permission))
+    });
+    granted
+}
+
+/// A permission a handler can require
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($name:ident => $value:expr),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl Permission for $name {
+                const NAME: &'static str = $value;
+            }
+        )*
+    };
+}
+
+permissions! {
+    CustomersRead => CUSTOMERS_READ,
+    CustomersWrite => CUSTOMERS_WRITE,
+    UsersRead => USERS_READ,
+    UsersUnlock => USERS_UNLOCK,
+    UsersImpersonate => USERS_IMPERSONATE,
+    TokensManage => TOKENS_MANAGE,
+    TokensIntrospect => TOKENS_INTROSPECT,
+    MfaManage => MFA_MANAGE,
+    IdentitiesLink => IDENTITIES_LINK,
+}
+
+/// Extractor that rejects the request with 403 unless the bearer token grants `P`
+pub struct Authorized<P: Permission> {
+    pub claims: UserClaims,
+    permission: PhantomData<P>,
+}
+
+impl<P: Permission> fmt::Debug for Authorized<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authorized")
+            .field("permission", &P::NAME)
+            .field("user_id", &self.claims.id)
+            .finish()
+    }
+}
+
+impl<P: Permission> FromRequest for Authorized<P> {
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authorize(req, P::NAME).map(|claims| Authorized { claims, permission: PhantomData }))
+    }
+}
+
+fn authorize(req: &HttpRequest, permission: &str) -> Result<UserClaims, ServiceError> {
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        ServiceError::InternalServerError
+    })?;
+
+    // Set by `middleware::validator`, which already checked the token
+    if let Some(api_token) = req.extensions().get::<ApiTokenAuth>() {
+        let claims = &api_token.claims;
+        if !api_token.scopes.iter().any(|scope| scope == permission) || !has_permission(&config, &claims.roles, permission) {
+            tracing::warn!(user_id = %claims.id, api_token_id = %api_token.token_id, scopes = ?api_token.scopes, permission = %permission, "Permission denied");
+            return Err(ServiceError::Forbidden { message: format!("Missing permission {}", permission) });
+        }
+        return Ok(claims.clone());
+    }
+
+    // Also set by `validator`; decoded here only where it does not run
+    let validated_claims = req.extensions().get::<UserClaims>().cloned();
+    let claims = match validated_claims {
+        Some(claims) => claims,
+        None => {
+            let token = req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or_else(|| ServiceError::AuthenticationError { message: "Missing bearer token".to_string() })?;
+
+            jwt::decode_access_token(&config, token)
+                .map_err(|message| ServiceError::AuthenticationError { message })?
+                .claims
+        }
+    };
+
+    if !has_permission(&config, &claims.roles, permission) {
+        tracing::warn!(user_id = %claims.id, roles = ?claims.roles, permission = %permission, "Permission denied");
+        return Err(ServiceError::Forbidden { message: format!("Missing permission {}", permission) });
+    }
+    if let Some(actor) = &claims.act
+        && IMPERSONATION_EXCLUDED.contains(&permission)
+    {
+        tracing::warn!(user_id = %claims.id, impersonator_id = %actor.id, permission = %permission, "Permission denied to impersonation token");
+        return Err(ServiceError::Forbidden { message: format!("Permission {} is not available while impersonating", permission) });
+    }
+
+    Ok(claims)
+}
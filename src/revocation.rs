@@ -0,0 +1,131 @@
+//! Access token revocation store
+//!
+//! Revoked `jti`s are persisted in `revoked_tokens` so that every instance sees
+//! them, and answers are cached in memory so that most requests skip the
+//! database. Revocations are cached until the token expires; "not revoked"
+//! answers only for `REVOCATION_CACHE_TTL_SECS`, which bounds how long a
+//! logout on another instance can go unnoticed.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use crate::{DbConnection, DbPool, middleware::UserClaims};
+use crate::models::revoked_tokens::usecases::{insert_revoked_token, is_token_revoked, purge_expired_revocations};
+
+lazy_static! {
+    static ref CACHE: RwLock<HashMap<String, CacheEntry>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CacheEntry {
+    revoked: bool,
+    /// Unix timestamp after which the entry must be looked up again
+    valid_until: i64,
+}
+
+pub struct RevocationStore;
+
+impl RevocationStore {
+    /// Revokes the token described by `claims` until it expires
+    pub fn revoke(conn: &mut DbConnection, claims: &UserClaims) -> diesel::QueryResult<()> {
+        let expires_at = DateTime::from_timestamp(claims.exp, 0)
+            .unwrap_or_else(Utc::now)
+            .naive_utc();
+
+        insert_revoked_token(conn, &claims.jti, claims.id, expires_at)?;
+        Self::remember(&claims.jti, true, claims.exp);
+
+        tracing::info!(user_id = %claims.id, jti = %claims.jti, "Access token revoked");
+        Ok(())
+    }
+
+    /// Returns the cached answer for a token, if there is a fresh one
+    pub fn cached(jti: &str) -> Option<bool> {
+        let now = Utc::now().timestamp();
+        CACHE.read()
+            .ok()?
+            .get(jti)
+            .filter(|entry| entry.valid_until > now)
+            .map(|entry| entry.revoked)
+    }
+
+    /// Checks whether a token is revoked, consulting the cache before the database
+    pub fn is_revoked(conn: &mut DbConnection, claims: &UserClaims, cache_ttl_secs: i64) -> diesel::QueryResult<bool> {
+        if let Some(revoked) = Self::cached(&claims.jti) {
+            return Ok(revoked);
+        }
+
+        let revoked = is_token_revoked(conn, &claims.jti)?;
+        let valid_until = if revoked {
+            claims.exp
+        } else {
+            Utc::now().timestamp() + cache_ttl_secs
+        };
+        Self::remember(&claims.jti, revoked, valid_until);
+
+        Ok(revoked)
+    }
+
+    /// Async variant of [`RevocationStore::is_revoked`] that runs the lookup on the blocking pool.
+    ///
+    /// Without a pool only the cache is consulted.
+    pub async fn check(pool: Option<web::Data<DbPool>>, claims: &UserClaims, cache_ttl_secs: i64) -> Result<bool, String> {
+        if let Some(revoked) = Self::cached(&claims.jti) {
+            return Ok(revoked);
+        }
+
+        let Some(pool) = pool else {
+            return Ok(false);
+        };
+
+        let claims = claims.clone();
+        web::block(move || -> Result<bool, String> {
+            let mut conn = pool.get().map_err(|e| e.to_string())?;
+            Self::is_revoked(&mut conn, &claims, cache_ttl_secs).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    /// Drops expired entries from the cache and the database
+    pub fn purge_expired(conn: &mut DbConnection) -> diesel::QueryResult<usize> {
+        let now = Utc::now().timestamp();
+        if let Ok(mut cache) = CACHE.write() {
+            cache.retain(|_, entry| entry.valid_until > now);
+        }
+
+        purge_expired_revocations(conn)
+    }
+
+    /// Runs [`RevocationStore::purge_expired`] every `period` on the current runtime
+    pub fn spawn_purge_task(pool: DbPool, period: Duration) {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+
+                let pool = pool.clone();
+                let result = web::block(move || -> Result<usize, String> {
+                    let mut conn = pool.get().map_err(|e| e.to_string())?;
+                    Self::purge_expired(&mut conn).map_err(|e| e.to_string())
+                })
+                .await;
+
+                match result {
+                    Ok(Ok(count)) => tracing::debug!(count = count, "Purged expired token revocations"),
+                    Ok(Err(e)) => tracing::error!(error = %e, "Failed to purge expired token revocations"),
+                    Err(e) => tracing::error!(error = ?e, "Revocation purge task failed"),
+                }
+            }
+        });
+    }
+
+    fn remember(jti: &str, revoked: bool, valid_until: i64) {
+        if let Ok(mut cache) = CACHE.write() {
+            cache.insert(jti.to_string(), CacheEntry { revoked, valid_until });
+        }
+    }
+}
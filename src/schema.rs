@@ -1,5 +1,40 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        #[max_length = 12]
+        token_prefix -> Varchar,
+        scopes -> Array<Text>,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Int8,
+        #[max_length = 50]
+        action -> Varchar,
+        user_id -> Int4,
+        impersonator_id -> Nullable<Int4>,
+        #[max_length = 36]
+        token_id -> Nullable<Varchar>,
+        #[max_length = 10]
+        http_method -> Nullable<Varchar>,
+        http_path -> Nullable<Text>,
+        http_status -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     customer_categories (id) {
         id -> Int4,
@@ -8,6 +43,123 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    local_credentials (user_id) {
+        user_id -> Int4,
+        password_hash -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_failures (username) {
+        #[max_length = 255]
+        username -> Varchar,
+        failure_count -> Int4,
+        first_failed_at -> Timestamp,
+        last_failed_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+        lockout_count -> Int4,
+    }
+}
+
+diesel::table! {
+    mfa_challenges (token_hash) {
+        #[max_length = 64]
+        token_hash -> Varchar,
+        user_id -> Int4,
+        attempts -> Int4,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    oidc_identities (issuer, subject) {
+        #[max_length = 255]
+        issuer -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_logins (state) {
+        #[max_length = 64]
+        state -> Varchar,
+        #[max_length = 128]
+        code_verifier -> Varchar,
+        #[max_length = 64]
+        nonce -> Varchar,
+        expires_at -> Timestamp,
+        link_user_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        #[max_length = 36]
+        family_id -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    revoked_tokens (jti) {
+        #[max_length = 36]
+        jti -> Varchar,
+        user_id -> Int4,
+        expires_at -> Timestamp,
+        revoked_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Int4,
+        #[max_length = 50]
+        name -> Varchar,
+        description -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    user_mfa (user_id) {
+        user_id -> Int4,
+        #[max_length = 64]
+        secret -> Varchar,
+        enabled_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int4,
+        role_id -> Int4,
+        assigned_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -17,7 +169,39 @@ diesel::table! {
         last_name -> Nullable<Varchar>,
         email -> Nullable<Varchar>,
         gecos -> Nullable<Varchar>,
+        last_login_at -> Nullable<Timestamp>,
+        deactivated_at -> Nullable<Timestamp>,
+        #[max_length = 20]
+        source -> Varchar,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(customer_categories, users,);
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(local_credentials -> users (user_id));
+diesel::joinable!(mfa_challenges -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(oidc_identities -> users (user_id));
+diesel::joinable!(oidc_logins -> users (link_user_id));
+diesel::joinable!(refresh_tokens -> users (user_id));
+diesel::joinable!(revoked_tokens -> users (user_id));
+diesel::joinable!(user_mfa -> users (user_id));
+diesel::joinable!(user_roles -> roles (role_id));
+diesel::joinable!(user_roles -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
+    customer_categories,
+    local_credentials,
+    login_failures,
+    mfa_challenges,
+    mfa_recovery_codes,
+    oidc_identities,
+    oidc_logins,
+    refresh_tokens,
+    revoked_tokens,
+    roles,
+    user_mfa,
+    user_roles,
+    users,
+);
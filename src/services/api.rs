@@ -1,11 +1,17 @@
 use actix_web::{web};
 use actix_web_httpauth::middleware::HttpAuthentication;
 use crate::middleware::{validator, ReqDataCreator};
+use crate::rate_limit::RateLimit;
+use crate::session::SessionAuth;
 
 const API_PREFIX: &str = "/api";
 
 pub mod users;
 pub mod customers;
+pub mod tokens;
+pub mod mfa;
+pub mod admin;
+pub mod me;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     let auth = HttpAuthentication::bearer(validator);
@@ -14,7 +20,15 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         web::scope(API_PREFIX)
         .wrap(ReqDataCreator)
         .wrap(auth)
+        // Outside the authentication, so that requests with invalid tokens are counted too
+        .wrap(RateLimit::api())
+        // Outermost, so that the rate limit sees the token of the session like a bearer token
+        .wrap(SessionAuth)
         .configure(users::config)
         .configure(customers::config)
+        .configure(tokens::config)
+        .configure(mfa::config)
+        .configure(admin::config)
+        .configure(me::config)
     );
 }
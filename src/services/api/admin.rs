@@ -0,0 +1,133 @@
+//! Administration endpoints
+//!
+//! `POST /api/admin/impersonate/{user_id}` lets support staff see what a user
+//! sees. The token it issues carries the user as subject and the admin in the
+//! `act` claim, expires after `IMPERSONATION_TOKEN_TTL_SECS` and cannot be
+//! refreshed. The impersonation and every request made with the token are
+//! recorded in `audit_events`.
+
+use actix_web::{post, web, HttpResponse, Responder, error, http::header};
+use diesel::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{DbPool, config, constants, errors::ServiceError, jwt};
+use crate::middleware::Actor;
+use crate::rbac::{Authorized, UsersImpersonate};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(constants::paths::ADMIN)
+        .service(impersonate)
+    );
+}
+
+/// Answer to `POST /api/admin/impersonate/{user_id}`; the token is in the Authorization header
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct ImpersonationResponse {
+    /// User the token acts as
+    pub user_id: i32,
+    pub username: String,
+    /// Admin who requested the token
+    pub impersonator_id: i32,
+    /// Lifetime of the token in seconds
+    pub expires_in: i64,
+}
+
+fn database_error(e: diesel::result::Error) -> ServiceError {
+    ServiceError::DatabaseError { message: e.to_string() }
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::ADMIN,
+    context_path = "/api/admin",
+    params(
+        ("user_id" = i32, Path, description = "ID of the user to act as")
+    ),
+    responses(
+        (status = 200, description = "Impersonation token issued", body = ImpersonationResponse, headers(
+            ("authorization" = String, description = "Authorization Header")
+        )),
+        (status = BAD_REQUEST, description = "Admins cannot impersonate themselves"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires users:impersonate, which impersonation tokens never grant"),
+        (status = NOT_FOUND, description = "Unknown or deactivated user"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "Impersonation Failed")
+    ),
+    security(
+        ("BearerAuth" = ["users:impersonate"])
+    )
+)]
+#[post("/impersonate/{user_id}")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id))]
+pub async fn impersonate(
+    auth: Authorized<UsersImpersonate>,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::audit_events::{IMPERSONATION_STARTED, usecases::{record_audit_event, NewAuditEvent}};
+    use crate::models::roles::usecases::find_user_roles;
+    use crate::models::users::usecases::find_user;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    let user_id = path.into_inner();
+    if user_id == auth.claims.id {
+        return Err(error::ErrorBadRequest("Admins cannot impersonate themselves"));
+    }
+    let admin_id = auth.claims.id;
+    let actor = Actor { id: admin_id, username: auth.claims.username.clone() };
+
+    let issued = web::block(move || -> Result<Option<(String, ImpersonationResponse)>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(user) = find_user(&mut conn, user_id).optional().map_err(database_error)? else {
+            return Ok(None);
+        };
+        if user.deactivated_at.is_some() {
+            tracing::warn!(user_id = %user.id, "Impersonation rejected: user is deactivated");
+            return Ok(None);
+        }
+        let roles = find_user_roles(&mut conn, user.id).map_err(database_error)?;
+        let (token, claims) = jwt::issue_impersonation_token(&config, user.id, &user.login_id, &roles, actor)
+            .map_err(|e| {
+                tracing::error!(error = %e, "Failed to issue impersonation token");
+                ServiceError::InternalServerError
+            })?;
+
+        // The token is only handed out once the impersonation is on record
+        record_audit_event(&mut conn, &NewAuditEvent {
+            action: IMPERSONATION_STARTED,
+            user_id: user.id,
+            impersonator_id: Some(admin_id),
+            token_id: Some(&claims.jti),
+            ..Default::default()
+        }).map_err(database_error)?;
+
+        let response = ImpersonationResponse {
+            user_id: user.id,
+            username: user.login_id,
+            impersonator_id: admin_id,
+            expires_in: claims.exp - claims.iat,
+        };
+        Ok(Some((token, response)))
+    })
+    .await??;
+
+    let Some((token, response)) = issued else {
+        return Err(error::ErrorNotFound("User not found"));
+    };
+
+    tracing::info!(user_id = %response.user_id, impersonator_id = %response.impersonator_id, "Impersonation started");
+    Ok(HttpResponse::Ok()
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+        .json(response))
+}
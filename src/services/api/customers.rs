@@ -1,6 +1,7 @@
 use actix_web::{get, put, delete, web, HttpResponse, Responder, post};
 use serde::Deserialize;
 use crate::{DbPool, models::customers::usecases::NewCategoryBody, models::customers::CustomerCategory, constants};
+use crate::rbac::{Authorized, CustomersRead, CustomersWrite};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -28,15 +29,18 @@ pub struct PaginationParams {
         (status = 200, description = "customer category insert successfully"),
         (status = INTERNAL_SERVER_ERROR, description = "failed to insert customer category"),
         (status = BAD_REQUEST, description = "validation error"),
-        (status = UNAUTHORIZED, description = "invalid authorization token")
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires customers:write"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["customers:write"])
     )
 )]
 #[post("/categories")]
 #[tracing::instrument(skip(pool, form), fields(category.name = %form.name))]
 pub async fn insert_category(
+    _auth: Authorized<CustomersWrite>,
     pool: web::Data<DbPool>,
     form: web::Json<NewCategoryBody>
 ) -> actix_web::Result<impl Responder> {
@@ -69,15 +73,18 @@ pub async fn insert_category(
         (status = 200, description = "customer category update successfully"),
         (status = INTERNAL_SERVER_ERROR, description = "failed to update customer category"),
         (status = BAD_REQUEST, description = "validation error"),
-        (status = UNAUTHORIZED, description = "invalid authorization token")
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires customers:write"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["customers:write"])
     )
 )]
 #[put("/categories/{id}/edit")]
 #[tracing::instrument(skip(pool, form), fields(category.id = %path, category.name = %form.name))]
 pub async fn update_category(
+    _auth: Authorized<CustomersWrite>,
     pool: web::Data<DbPool>,
     path: web::Path<i32>,
     form: web::Json<NewCategoryBody>
@@ -116,15 +123,18 @@ pub async fn update_category(
     responses(
         (status = 200, description = "customer category list", body = Vec<CustomerCategory>),
         (status = INTERNAL_SERVER_ERROR, description = "failed to get customer categories"),
-        (status = UNAUTHORIZED, description = "invalid authorization token")
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires customers:read"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["customers:read"])
     )
 )]
 #[get("/categories")]
 #[tracing::instrument(skip(pool, pagination))]
 pub async fn categories(
+    _auth: Authorized<CustomersRead>,
     pool: web::Data<DbPool>,
     pagination: web::Query<PaginationParams>
 ) -> actix_web::Result<impl Responder> {
@@ -164,15 +174,18 @@ pub async fn categories(
     responses(
         (status = 200, description = "customer category detail", body = CustomerCategory),
         (status = INTERNAL_SERVER_ERROR, description = "failed to get category detail"),
-        (status = UNAUTHORIZED, description = "invalid authorization token")
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires customers:read"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["customers:read"])
     )
 )]
 #[get("/categories/{id}")]
 #[tracing::instrument(skip(pool), fields(category.id = %path))]
 pub async fn get_category(
+    _auth: Authorized<CustomersRead>,
     pool: web::Data<DbPool>,
     path: web::Path<i32>,
 ) -> actix_web::Result<impl Responder> {
@@ -205,15 +218,18 @@ pub async fn get_category(
     responses(
         (status = 200, description = "delete customer category", body = CustomerCategory),
         (status = INTERNAL_SERVER_ERROR, description = "failed to delete customer category"),
-        (status = UNAUTHORIZED, description = "invalid authorization token")
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires customers:write"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["customers:write"])
     )
 )]
 #[delete("/categories/{id}/delete")]
 #[tracing::instrument(skip(pool), fields(category.id = %path))]
 pub async fn delete_category(
+    _auth: Authorized<CustomersWrite>,
     pool: web::Data<DbPool>,
     path: web::Path<i32>,
 ) -> actix_web::Result<impl Responder> {
@@ -244,7 +260,7 @@ mod tests {
     use super::*;
     use crate::create_test_connection_pool;
     use actix_web::{
-        http::{header::ContentType},
+        http::{header::{self, ContentType}},
         test, App, web
     };
 
@@ -252,6 +268,9 @@ mod tests {
     async fn test_insert_category() {
         let pool = create_test_connection_pool();
 
+        let config = crate::config::get_config().unwrap();
+        let token = crate::jwt::issue_access_token(&config, 1, "testuser", &["manager".to_string()]).unwrap();
+
         let data = NewCategoryBody {
             name: "test".into()
         };
@@ -266,6 +285,7 @@ mod tests {
             .uri("/categories")
             .set_json(data)
             .insert_header(ContentType::json())
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
@@ -0,0 +1,99 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, error};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::{DbPool, config, constants, middleware::CurrentUser, models::users::User};
+use crate::rbac::{Authorized, IdentitiesLink};
+use crate::services::auth::oidc;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(show)
+        .service(link_oidc);
+}
+
+/// The user a request is made as, and what its token says about them
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CurrentUserResponse {
+    #[serde(flatten)]
+    pub user: User,
+    /// Roles of the user when the token was issued
+    pub roles: Vec<String>,
+    /// Expiry of the token the request was made with (Unix timestamp)
+    pub token_expires_at: i64,
+    /// Admin acting as the user, for impersonation tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<i32>,
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::USERS,
+    context_path = "/api",
+    responses(
+        (status = 200, description = "The current user", body = CurrentUserResponse),
+        (status = UNAUTHORIZED, description = "invalid authorization token, or its user is not in the database or deactivated"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)")
+    ),
+    security(
+        ("BearerAuth" = [])
+    )
+)]
+#[get("/me")]
+#[tracing::instrument(skip(current_user), fields(auth.user_id = %current_user.id))]
+pub async fn show(current_user: CurrentUser) -> impl Responder {
+    let CurrentUser { user, claims } = current_user;
+    HttpResponse::Ok().json(CurrentUserResponse {
+        user,
+        roles: claims.roles,
+        token_expires_at: claims.exp,
+        impersonator_id: claims.act.map(|actor| actor.id),
+    })
+}
+
+/// Where to send the browser to link an OIDC identity
+#[derive(Serialize, ToSchema, Debug)]
+pub struct OidcLinkResponse {
+    /// Authorization endpoint of the provider; its redirect to `/auth/oidc/callback` links the identity
+    pub authorization_url: String,
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::USERS,
+    context_path = "/api",
+    responses(
+        (status = 200, description = "Login to complete at the provider", body = OidcLinkResponse, headers(
+            ("set-cookie" = String, description = "oidc_state cookie checked by the callback")
+        )),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires identities:link, which API and impersonation tokens never grant"),
+        (status = NOT_FOUND, description = "OIDC login is not configured"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "The provider is unavailable")
+    ),
+    security(
+        ("BearerAuth" = ["identities:link"])
+    )
+)]
+#[post("/me/oidc/link")]
+#[tracing::instrument(skip(pool, req, auth), fields(auth.user_id = %auth.claims.id))]
+pub async fn link_oidc(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    auth: Authorized<IdentitiesLink>,
+) -> actix_web::Result<HttpResponse> {
+    let client = oidc::oidc_client(&req)?;
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    let (authorization_url, cookie) = match oidc::start_login(&client, &config, pool, Some(auth.claims.id)).await? {
+        Ok(started) => started,
+        Err(e) => return oidc::auth_error_response(&config, e),
+    };
+
+    tracing::info!("OIDC identity link started");
+    Ok(HttpResponse::Ok()
+        .cookie(cookie)
+        .json(OidcLinkResponse { authorization_url }))
+}
@@ -0,0 +1,304 @@
+use actix_web::{get, post, web, HttpResponse, Responder, error};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{DbPool, config, constants, errors::ServiceError};
+use crate::mfa::{self, MfaPolicy};
+use crate::models::mfa::usecases::{MfaEnrollment, RecoveryCodes};
+use crate::rbac::{Authorized, MfaManage};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(constants::paths::MFA)
+        .service(status)
+        .service(enroll)
+        .service(confirm)
+        .service(regenerate_recovery_codes)
+        .service(disable)
+    );
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct MfaStatus {
+    pub enabled: bool,
+    /// The user's roles require a second factor (`MFA_REQUIRED_ROLES`)
+    pub required: bool,
+    pub recovery_codes_remaining: i64,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct MfaCodeBody {
+    /// 6-digit code from the authenticator app; recovery codes are accepted where noted
+    pub code: String,
+}
+
+/// What a request that needs a code found
+enum CodeCheck<T> {
+    /// MFA is not in the state the request needs
+    Conflict,
+    InvalidCode,
+    Done(T),
+}
+
+fn database_error(e: diesel::result::Error) -> ServiceError {
+    ServiceError::DatabaseError { message: e.to_string() }
+}
+
+fn code_check_response<T>(check: CodeCheck<T>, ok: impl FnOnce(T) -> HttpResponse) -> actix_web::Result<HttpResponse> {
+    match check {
+        CodeCheck::Conflict => Ok(HttpResponse::Conflict().finish()),
+        CodeCheck::InvalidCode => Err(error::ErrorBadRequest("Invalid code")),
+        CodeCheck::Done(value) => Ok(ok(value)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::MFA,
+    context_path = "/api/mfa",
+    responses(
+        (status = 200, description = "Two-factor authentication status of the current user", body = MfaStatus),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires mfa:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to get MFA status")
+    ),
+    security(
+        ("BearerAuth" = ["mfa:manage"])
+    )
+)]
+#[get("/")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id))]
+pub async fn status(
+    auth: Authorized<MfaManage>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::mfa::usecases::{count_recovery_codes, find_user_mfa};
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+    let required = MfaPolicy::from_config(&config).is_required(&auth.claims.roles);
+
+    let user_id = auth.claims.id;
+    let (enabled, recovery_codes_remaining) = web::block(move || -> Result<(bool, i64), ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let enabled = find_user_mfa(&mut conn, user_id).map_err(database_error)?.is_some_and(|mfa| mfa.is_enabled());
+        let remaining = count_recovery_codes(&mut conn, user_id).map_err(database_error)?;
+        Ok((enabled, remaining))
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(MfaStatus { enabled, required, recovery_codes_remaining }))
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::MFA,
+    context_path = "/api/mfa",
+    responses(
+        (status = 200, description = "Enrolment started, replacing a pending one. Confirm it with POST /api/mfa/confirm", body = MfaEnrollment),
+        (status = CONFLICT, description = "MFA is already enabled"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires mfa:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to start enrolment")
+    ),
+    security(
+        ("BearerAuth" = ["mfa:manage"])
+    )
+)]
+#[post("/enroll")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id))]
+pub async fn enroll(
+    auth: Authorized<MfaManage>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::mfa::usecases::start_mfa_enrollment;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+    let issuer = MfaPolicy::from_config(&config).issuer;
+
+    let user_id = auth.claims.id;
+    let login_id = auth.claims.username.clone();
+    let enrollment = web::block(move || -> Result<Option<MfaEnrollment>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        start_mfa_enrollment(&mut conn, user_id, &issuer, &login_id).map_err(database_error)
+    })
+    .await??;
+
+    let Some(enrollment) = enrollment else {
+        return Ok(HttpResponse::Conflict().finish());
+    };
+    tracing::info!("MFA enrolment started");
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::MFA,
+    context_path = "/api/mfa",
+    request_body = MfaCodeBody,
+    responses(
+        (status = 200, description = "MFA enabled. The recovery codes are only shown in this response", body = RecoveryCodes),
+        (status = BAD_REQUEST, description = "invalid code"),
+        (status = CONFLICT, description = "No pending enrolment"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires mfa:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to enable MFA")
+    ),
+    security(
+        ("BearerAuth" = ["mfa:manage"])
+    )
+)]
+#[post("/confirm")]
+#[tracing::instrument(skip(auth, pool, body), fields(auth.user_id = %auth.claims.id))]
+pub async fn confirm(
+    auth: Authorized<MfaManage>,
+    pool: web::Data<DbPool>,
+    body: web::Json<MfaCodeBody>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::models::mfa::usecases::{enable_mfa, find_user_mfa, replace_recovery_codes};
+
+    let user_id = auth.claims.id;
+    let check = web::block(move || -> Result<CodeCheck<RecoveryCodes>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(user_mfa) = find_user_mfa(&mut conn, user_id).map_err(database_error)?.filter(|mfa| !mfa.is_enabled()) else {
+            return Ok(CodeCheck::Conflict);
+        };
+        if !mfa::verify_code(&mut conn, &user_mfa, &body.code).map_err(database_error)? {
+            return Ok(CodeCheck::InvalidCode);
+        }
+        if !enable_mfa(&mut conn, user_id).map_err(database_error)? {
+            return Ok(CodeCheck::Conflict);
+        }
+        replace_recovery_codes(&mut conn, user_id).map(CodeCheck::Done).map_err(database_error)
+    })
+    .await??;
+
+    code_check_response(check, |codes| {
+        tracing::info!("MFA enabled");
+        HttpResponse::Ok().json(codes)
+    })
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::MFA,
+    context_path = "/api/mfa",
+    request_body = MfaCodeBody,
+    responses(
+        (status = 200, description = "New recovery codes, replacing the earlier ones. They are only shown in this response", body = RecoveryCodes),
+        (status = BAD_REQUEST, description = "invalid code"),
+        (status = CONFLICT, description = "MFA is not enabled"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires mfa:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to create recovery codes")
+    ),
+    security(
+        ("BearerAuth" = ["mfa:manage"])
+    )
+)]
+#[post("/recovery-codes")]
+#[tracing::instrument(skip(auth, pool, body), fields(auth.user_id = %auth.claims.id))]
+pub async fn regenerate_recovery_codes(
+    auth: Authorized<MfaManage>,
+    pool: web::Data<DbPool>,
+    body: web::Json<MfaCodeBody>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::models::mfa::usecases::{find_user_mfa, replace_recovery_codes};
+
+    let user_id = auth.claims.id;
+    let check = web::block(move || -> Result<CodeCheck<RecoveryCodes>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(user_mfa) = find_user_mfa(&mut conn, user_id).map_err(database_error)?.filter(|mfa| mfa.is_enabled()) else {
+            return Ok(CodeCheck::Conflict);
+        };
+        if !mfa::verify_code(&mut conn, &user_mfa, &body.code).map_err(database_error)? {
+            return Ok(CodeCheck::InvalidCode);
+        }
+        replace_recovery_codes(&mut conn, user_id).map(CodeCheck::Done).map_err(database_error)
+    })
+    .await??;
+
+    code_check_response(check, |codes| {
+        tracing::info!("MFA recovery codes replaced");
+        HttpResponse::Ok().json(codes)
+    })
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::MFA,
+    context_path = "/api/mfa",
+    request_body = MfaCodeBody,
+    responses(
+        (status = NO_CONTENT, description = "MFA disabled. Users whose roles require it enrol again at their next login"),
+        (status = BAD_REQUEST, description = "invalid code; a recovery code is accepted too"),
+        (status = CONFLICT, description = "MFA is not enabled"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires mfa:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to disable MFA")
+    ),
+    security(
+        ("BearerAuth" = ["mfa:manage"])
+    )
+)]
+#[post("/disable")]
+#[tracing::instrument(skip(auth, pool, body), fields(auth.user_id = %auth.claims.id))]
+pub async fn disable(
+    auth: Authorized<MfaManage>,
+    pool: web::Data<DbPool>,
+    body: web::Json<MfaCodeBody>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::models::mfa::usecases::{disable_mfa, find_user_mfa};
+
+    let user_id = auth.claims.id;
+    let check = web::block(move || -> Result<CodeCheck<()>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(user_mfa) = find_user_mfa(&mut conn, user_id).map_err(database_error)?.filter(|mfa| mfa.is_enabled()) else {
+            return Ok(CodeCheck::Conflict);
+        };
+        if !mfa::verify_code(&mut conn, &user_mfa, &body.code).map_err(database_error)? {
+            return Ok(CodeCheck::InvalidCode);
+        }
+        disable_mfa(&mut conn, user_id).map(|_| CodeCheck::Done(())).map_err(database_error)
+    })
+    .await??;
+
+    code_check_response(check, |()| {
+        tracing::info!("MFA disabled");
+        HttpResponse::NoContent().finish()
+    })
+}
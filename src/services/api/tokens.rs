@@ -0,0 +1,159 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder, error};
+use crate::{DbPool, config, constants, errors::ServiceError};
+use crate::models::api_tokens::{ApiToken, usecases::{CreatedApiToken, NewApiTokenBody}};
+use crate::rbac::{self, Authorized, TokensManage};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(constants::paths::TOKENS)
+        .service(index)
+        .service(create)
+        .service(revoke)
+    );
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::TOKENS,
+    context_path = "/api/tokens",
+    responses(
+        (status = 200, description = "API tokens of the current user that are not revoked", body = Vec<ApiToken>),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires tokens:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to get API tokens")
+    ),
+    security(
+        ("BearerAuth" = ["tokens:manage"])
+    )
+)]
+#[get("/")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id))]
+pub async fn index(
+    auth: Authorized<TokensManage>,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::api_tokens::usecases::list_api_tokens;
+
+    let user_id = auth.claims.id;
+    let tokens = web::block(move || -> Result<Vec<ApiToken>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        list_api_tokens(&mut conn, user_id)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+
+    tracing::debug!(count = tokens.len(), "API tokens fetched successfully");
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::TOKENS,
+    context_path = "/api/tokens",
+    request_body = NewApiTokenBody,
+    responses(
+        (status = CREATED, description = "API token created. The token is only shown in this response", body = CreatedApiToken),
+        (status = BAD_REQUEST, description = "validation error"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires tokens:manage, and the user's roles must grant every scope"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to create API token")
+    ),
+    security(
+        ("BearerAuth" = ["tokens:manage"])
+    )
+)]
+#[post("/")]
+#[tracing::instrument(skip(auth, pool, body), fields(auth.user_id = %auth.claims.id, api_token.name = %body.name))]
+pub async fn create(
+    auth: Authorized<TokensManage>,
+    pool: web::Data<DbPool>,
+    body: web::Json<NewApiTokenBody>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::api_tokens::usecases::create_api_token;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    // Tokens cannot grant more than their user may do; unknown scopes fail validation
+    if let Some(scope) = body.scopes.iter()
+        .filter(|scope| rbac::API_TOKEN_SCOPES.contains(&scope.as_str()))
+        .find(|scope| !rbac::has_permission(&config, &auth.claims.roles, scope)) {
+        return Err(ServiceError::Forbidden { message: format!("Cannot grant scope {}", scope) }.into());
+    }
+
+    let user_id = auth.claims.id;
+    let max_ttl_days = config.get_api_token_max_ttl_days();
+    let created = web::block(move || -> Result<CreatedApiToken, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        create_api_token(&mut conn, user_id, &body, max_ttl_days)
+    })
+    .await??;
+
+    tracing::info!(api_token_id = %created.api_token.id, scopes = ?created.api_token.scopes, "API token created");
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[utoipa::path(
+    delete,
+    tag = constants::tags::TOKENS,
+    context_path = "/api/tokens",
+    params(
+        ("id" = i32, Path, description = "ID of the API token")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "API token revoked"),
+        (status = NOT_FOUND, description = "The current user has no such active token"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires tokens:manage"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "failed to revoke API token")
+    ),
+    security(
+        ("BearerAuth" = ["tokens:manage"])
+    )
+)]
+#[delete("/{id}")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id, api_token.id = %path))]
+pub async fn revoke(
+    auth: Authorized<TokensManage>,
+    pool: web::Data<DbPool>,
+    path: web::Path<i32>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::api_tokens::usecases::revoke_api_token;
+
+    let user_id = auth.claims.id;
+    let token_id = path.into_inner();
+    let revoked = web::block(move || -> Result<bool, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        revoke_api_token(&mut conn, user_id, token_id)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+
+    if !revoked {
+        tracing::debug!(api_token_id = %token_id, "No such active API token");
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    tracing::info!(api_token_id = %token_id, "API token revoked");
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,11 +1,13 @@
-use actix_web::{get, web, HttpResponse, Responder, error};
+use actix_web::{delete, get, web, HttpResponse, Responder, error};
 use serde::Deserialize;
-use crate::{DbPool, middleware::ApiReqeustData, models::users::User, constants};
+use crate::{DbPool, middleware::CurrentUser, models::users::User, constants};
+use crate::rbac::{Authorized, UsersRead, UsersUnlock};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope(constants::paths::USERS)
         .service(index)
+        .service(unlock)
     );
 }
 
@@ -25,22 +27,25 @@ pub struct PaginationParams {
     ),
     responses(
         (status = 200, description = "Register User", body = Vec<User>),
+        (status = FORBIDDEN, description = "requires users:read"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
         (status = INTERNAL_SERVER_ERROR, description = "Register User Failed")
     ),
     security(
-        ("BearerAuth" = [])
+        ("BearerAuth" = ["users:read"])
     )
 )]
 #[get("/")]
-#[tracing::instrument(skip(pool, req_data, pagination))]
+#[tracing::instrument(skip(pool, current_user, pagination))]
 pub async fn index(
+    _auth: Authorized<UsersRead>,
     pool: web::Data<DbPool>,
-    req_data: web::ReqData<ApiReqeustData>,
+    current_user: CurrentUser,
     pagination: web::Query<PaginationParams>
 ) -> actix_web::Result<impl Responder> {
     use crate::models::users::usecases::*;
 
-    dbg!(req_data); // current_user 使用例
+    tracing::debug!(user_id = %current_user.id, "Listing users"); // current_user 使用例
 
     // Requirements: 11.1 - Pagination with query parameters
     let page = pagination.page.unwrap_or(1).max(1);
@@ -65,3 +70,55 @@ pub async fn index(
     tracing::debug!(count = users.len(), page = page, per_page = per_page, "Users fetched successfully");
     Ok(HttpResponse::Ok().json(users))
 }
+
+#[utoipa::path(
+    delete,
+    tag = constants::tags::USERS,
+    context_path = "/api/users",
+    params(
+        ("login_id" = String, Path, description = "Login ID of the locked account")
+    ),
+    responses(
+        (status = NO_CONTENT, description = "Failed logins forgotten and lock lifted"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "requires users:unlock"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "Unlock Failed")
+    ),
+    security(
+        ("BearerAuth" = ["users:unlock"])
+    )
+)]
+#[delete("/{login_id}/lockout")]
+#[tracing::instrument(skip(auth, pool), fields(auth.user_id = %auth.claims.id))]
+pub async fn unlock(
+    auth: Authorized<UsersUnlock>,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    use crate::lockout::normalize_username;
+    use crate::metrics::AuthMetrics;
+    use crate::models::login_failures::usecases::clear_login_failures;
+
+    let username = normalize_username(&path.into_inner());
+    let cloned_username = username.clone();
+    let cleared = web::block(move || -> Result<bool, crate::errors::ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                crate::errors::ServiceError::InternalServerError
+            })?;
+
+        clear_login_failures(&mut conn, &cloned_username)
+            .map_err(|e| crate::errors::ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+
+    if cleared {
+        AuthMetrics::record_lockout_event("unlocked");
+        tracing::info!(username = %username, admin_id = %auth.claims.id, "Account unlocked by admin");
+    } else {
+        tracing::debug!(username = %username, "No failed logins to clear");
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
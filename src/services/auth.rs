@@ -1,14 +1,32 @@
-use std::borrow::Cow;
-use actix_web::{post, web, HttpResponse, Responder, error, http::header};
-use actix_limitation::Limiter;
-use jsonwebtoken::{encode, Header, EncodingKey};
+use actix_session::SessionExt;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, error, http::header};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
 use serde::{Deserialize, Serialize};
-use tracing::Instrument;
 use utoipa::{IntoParams, ToSchema};
-use crate::{DbPool, models::users::User, middleware::UserClaims, config, constants};
+use crate::{DbPool, jwt, models::users::User, metrics::AuthMetrics, config, constants};
+use crate::lockout::{self, LockoutPolicy};
+use crate::mfa::MfaPolicy;
+use crate::rate_limit::RateLimit;
+use crate::session;
+use crate::models::refresh_tokens::usecases::{issue_refresh_token, revoke_refresh_token, rotate_refresh_token, RotationOutcome};
+use backend::{AuthBackend, AuthError, DirectoryProfile};
+
+pub mod backend;
+pub mod introspection;
+pub mod mfa;
+pub mod oidc;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(login);
+    cfg.service(login)
+        .service(jwks)
+        .service(
+            web::scope(constants::AUTH_PREFIX)
+            .service(refresh)
+            .service(logout)
+            .configure(introspection::config)
+            .configure(mfa::config)
+            .configure(oidc::config)
+        );
 }
 
 #[derive(Clone, Deserialize, Serialize, IntoParams, ToSchema, Debug)]
@@ -29,9 +47,15 @@ pub struct LoginInfoValidator {
     pub password: String,
 }
 
+/// Characters allowed in usernames besides letters and digits; the LDAP backend escapes them in DNs and filters
+const USERNAME_SPECIAL_CHARS: &[char] = &['_', '-', '.', ' ', ',', '@', '+', '\\', '*', '(', ')'];
+
 fn validate_username(username: &str) -> Result<(), ValidationError> {
-    // Check for valid characters (alphanumeric, underscore, hyphen, dot)
-    if !username.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.') {
+    if !username.chars().all(|c| c.is_alphanumeric() || USERNAME_SPECIAL_CHARS.contains(&c)) {
+        return Err(ValidationError::new("invalid_username"));
+    }
+    // Directories ignore surrounding spaces, so they would not tell two such usernames apart
+    if username.trim() != username {
         return Err(ValidationError::new("invalid_username"));
     }
     Ok(())
@@ -50,64 +74,33 @@ impl crate::traits::IntoValidator<LoginInfoValidator> for LoginInfo {
     post,
     tag = constants::tags::AUTH,
     responses(
-        (status = 200, description = "Login User", headers(
-            ("authorization" = String, description = "Authorization Header")
+        (status = 200, description = "Login User", body = TokenResponse, headers(
+            ("authorization" = String, description = "Bearer access token, the same as access_token in the body")
+        )),
+        (status = ACCEPTED, description = "A second factor is required; complete the login with POST /auth/login/mfa", body = mfa::MfaChallengeResponse),
+        (status = NO_CONTENT, description = "Logged in with SESSION_AUTH_ENABLED: the tokens are kept in the session cookie", headers(
+            ("set-cookie" = String, description = "Session cookie, and the csrf_token cookie to send back in X-CSRF-Token")
         )),
         (status = UNAUTHORIZED),
-        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded"),
+        (status = FORBIDDEN, description = "Denied by the directory, or the account is deactivated"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded, or the account is locked after repeated failed logins (see Retry-After)"),
         (status = INTERNAL_SERVER_ERROR, description = "Login User Failed")
     ),
     request_body = LoginInfo
 )]
-#[post("/login")]
-#[tracing::instrument(skip(pool, info, limiter, req), fields(auth.username = %info.username, auth.ldap_bind = tracing::field::Empty, auth.user_search = tracing::field::Empty))]
+// Requirements: 11.2 - Rate limiting for login endpoint to prevent brute force attacks
+#[post("/login", wrap = "RateLimit::login()")]
+#[tracing::instrument(skip(pool, info, req), fields(auth.username = %info.username, auth.backend = tracing::field::Empty, auth.ldap_bind = tracing::field::Empty, auth.user_search = tracing::field::Empty))]
 pub async fn login(
     pool: web::Data<DbPool>,
     info: web::Json<LoginInfo>,
-    limiter: web::Data<Limiter>,
     req: actix_web::HttpRequest,
 ) -> actix_web::Result<impl Responder> {
-    // Requirements: 11.2 - Rate limiting for login endpoint to prevent brute force attacks
     let config = config::get_config().map_err(|e| {
         tracing::error!(error = ?e, "Failed to get configuration");
         error::ErrorInternalServerError(e)
     })?;
-    
-    if config.is_rate_limit_enabled() {
-        // Use client IP address for rate limiting
-        let client_ip = req
-            .connection_info()
-            .realip_remote_addr()
-            .unwrap_or("unknown")
-            .to_string();
-        
-        let rate_limit_key = format!("login:{}", client_ip);
-        
-        match limiter.count(&rate_limit_key).await {
-            Ok(status) => {
-                if status.remaining() == 0 {
-                    tracing::warn!(
-                        client_ip = %client_ip,
-                        username = %info.username,
-                        "Rate limit exceeded for login attempt"
-                    );
-                    return Err(error::ErrorTooManyRequests(
-                        "Too many login attempts. Please try again later."
-                    ));
-                }
-                tracing::debug!(
-                    client_ip = %client_ip,
-                    remaining = status.remaining(),
-                    "Rate limit check passed"
-                );
-            }
-            Err(e) => {
-                tracing::error!(error = ?e, "Rate limiter error");
-                // Continue even if rate limiter fails (fail open)
-            }
-        }
-    }
-    use ldap3::LdapConnAsync;
+
     use crate::traits::IntoValidator;
     
     // Validate login info
@@ -121,254 +114,483 @@ pub async fn login(
                 "details": e.field_errors()
             }))
         })?;
-    
-    println!("{:?}", &info);
+
+    // Locked accounts are rejected before the backend sees the password
+    let lockout_policy = LockoutPolicy::from_config(&config);
+    if lockout_policy.is_enabled()
+        && let Some(locked_until) = lockout::locked_until(pool.clone(), &info.username).await?
+    {
+        tracing::warn!(username = %info.username, locked_until = %locked_until, "Login rejected: account locked");
+        return Ok(locked_response(locked_until));
+    }
+
+    let backend = resolve_backend(&req, &pool, &config)?;
+    tracing::Span::current().record("auth.backend", backend.name());
+
+    let profile = match backend.authenticate(&info.username, &info.password).await {
+        Ok(profile) => profile,
+        Err(AuthError::InvalidCredentials) => {
+            tracing::warn!(username = %info.username, "Login failed: invalid credentials");
+            
+            // Requirements: 12.5 - Authentication metrics collection
+            AuthMetrics::record_attempt(false);
+            
+            if lockout_policy.is_enabled() {
+                lockout::record_failure(pool.clone(), &info.username, lockout_policy).await?;
+            }
+            
+            return Ok(HttpResponse::Unauthorized().finish());
+        }
+        Err(AuthError::Forbidden { reason }) => {
+            tracing::warn!(username = %info.username, reason = %reason, "Login denied");
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+        Err(AuthError::Unavailable { message }) => {
+            tracing::error!(error = %message, backend = backend.name(), "Authentication backend error");
+            // Requirements: 11.2 - Hide detailed error information in production
+            return Err(if config.is_production() {
+                error::ErrorInternalServerError("Authentication service unavailable")
+            } else {
+                error::ErrorInternalServerError(message)
+            });
+        }
+    };
+
+    // Requirements: 12.5 - Authentication metrics collection
+    AuthMetrics::record_attempt(true);
+
+    let (user, roles) = provision_user(pool.clone(), profile).await?;
+
+    tracing::info!(user_id = %user.id, username = %user.login_id, "Login successful");
+    login_response(&config, pool, &req, &user, &roles).await
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct RefreshRequest {
+    /// Omitted with SESSION_AUTH_ENABLED to refresh the tokens of the session
+    pub refresh_token: String,
+}
+
+/// Tokens issued by a login or refresh; the access token is in the Authorization header as well
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct TokenResponse {
+    pub access_token: String,
+    /// Always `Bearer`
+    pub token_type: String,
+    /// Lifetime of the access token in seconds
+    pub expires_in: i64,
+    /// Single-use token for `POST /auth/refresh`
+    pub refresh_token: String,
+    /// Lifetime of the refresh token in seconds
+    pub refresh_expires_in: i64,
+    /// The user the tokens were issued to
+    pub user: User,
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::AUTH,
+    context_path = "/auth",
+    request_body(content = Option<RefreshRequest>, description = "Required unless the tokens are kept in the session"),
+    responses(
+        (status = 200, description = "New access token and rotated refresh token", body = TokenResponse, headers(
+            ("authorization" = String, description = "Bearer access token, the same as access_token in the body")
+        )),
+        (status = NO_CONTENT, description = "Rotated the tokens of the session"),
+        (status = BAD_REQUEST, description = "No refresh token in the body or the session"),
+        (status = UNAUTHORIZED, description = "Refresh token is invalid, expired or reused"),
+        (status = FORBIDDEN, description = "The session is used without a valid X-CSRF-Token header"),
+        (status = INTERNAL_SERVER_ERROR, description = "Refresh Failed")
+    )
+)]
+#[post("/refresh")]
+#[tracing::instrument(skip(pool, req, body), fields(auth.user_id = tracing::field::Empty))]
+pub async fn refresh(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    body: Option<web::Json<RefreshRequest>>,
+) -> actix_web::Result<impl Responder> {
+    use crate::errors::ServiceError;
+    use crate::models::users::usecases::find_user;
+    use crate::models::roles::usecases::find_user_roles;
 
     let config = config::get_config().map_err(|e| {
         tracing::error!(error = ?e, "Failed to get configuration");
         error::ErrorInternalServerError(e)
     })?;
 
-    let (conn, mut ldap) = LdapConnAsync::new(&config.ldap_uri)
-    .await
-    .map_err(|e| {
-        tracing::error!(error = ?e, ldap_uri = %config.ldap_uri, "Failed to connect to LDAP server");
+    let presented = match body {
+        Some(body) => body.into_inner().refresh_token,
+        None if config.is_session_auth_enabled() => {
+            let session = req.get_session();
+            session::verify_csrf(&req, &session)?;
+            session::refresh_token(&session)
+                .ok_or_else(|| error::ErrorBadRequest("refresh_token is required"))?
+        }
+        None => return Err(error::ErrorBadRequest("refresh_token is required")),
+    };
+
+    let ttl_secs = config.get_refresh_token_ttl_secs();
+    let (user, roles, refresh_token) = web::block(move || -> Result<(User, Vec<String>, String), ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        match rotate_refresh_token(&mut conn, &presented, ttl_secs)? {
+            RotationOutcome::Rotated { user_id, token } => {
+                let user = find_user(&mut conn, user_id)
+                    .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+                if user.deactivated_at.is_some() {
+                    tracing::warn!(user_id = %user_id, "Refresh rejected: user is deactivated");
+                    return Err(ServiceError::AuthenticationError {
+                        message: "Account has been deactivated".to_string()
+                    });
+                }
+                let roles = find_user_roles(&mut conn, user_id)
+                    .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+                Ok((user, roles, token))
+            }
+            RotationOutcome::Invalid => Err(ServiceError::AuthenticationError {
+                message: "Unknown refresh token".to_string()
+            }),
+            RotationOutcome::Expired => Err(ServiceError::AuthenticationError {
+                message: "Refresh token expired".to_string()
+            }),
+            RotationOutcome::Reused { user_id, family_id } => {
+                tracing::warn!(user_id = %user_id, family_id = %family_id, "Refresh token reuse detected, token family revoked");
+                Err(ServiceError::AuthenticationError {
+                    message: "Refresh token already used".to_string()
+                })
+            }
+        }
+    })
+    .await??;
+
+    tracing::Span::current().record("auth.user_id", user.id);
+    tracing::info!(user_id = %user.id, "Refresh token rotated");
+    token_response(&config, &req, &user, &roles, refresh_token, false)
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct LogoutRequest {
+    /// Refresh token whose family should be revoked as well
+    pub refresh_token: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::AUTH,
+    context_path = "/auth",
+    request_body(content = Option<LogoutRequest>, description = "Optionally revoke the refresh token too"),
+    responses(
+        (status = NO_CONTENT, description = "Access token revoked; the session, if any, is ended"),
+        (status = UNAUTHORIZED, description = "invalid authorization token"),
+        (status = FORBIDDEN, description = "The session is used without a valid X-CSRF-Token header"),
+        (status = INTERNAL_SERVER_ERROR, description = "Logout Failed")
+    ),
+    security(
+        ("BearerAuth" = [])
+    )
+)]
+#[post("/logout")]
+#[tracing::instrument(skip(pool, req, credentials, body), fields(auth.user_id = tracing::field::Empty))]
+pub async fn logout(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    credentials: Option<BearerAuth>,
+    body: Option<web::Json<LogoutRequest>>,
+) -> actix_web::Result<impl Responder> {
+    use crate::errors::ServiceError;
+    use crate::revocation::RevocationStore;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    // Browsers in session mode log out with the session instead of a bearer token
+    let session = config.is_session_auth_enabled().then(|| req.get_session());
+    let (access_token, session_refresh_token) = match (credentials, &session) {
+        (Some(credentials), _) => (credentials.token().to_string(), None),
+        (None, Some(session)) => {
+            let access_token = session::access_token(session)
+                .ok_or_else(|| error::ErrorUnauthorized("invalid authorization token"))?;
+            session::verify_csrf(&req, session)?;
+            (access_token, session::refresh_token(session))
+        }
+        (None, None) => return Err(error::ErrorUnauthorized("invalid authorization token")),
+    };
+
+    let claims = jwt::decode_access_token(&config, &access_token)
+        .map_err(|message| ServiceError::AuthenticationError { message })?
+        .claims;
+    tracing::Span::current().record("auth.user_id", claims.id);
+
+    let refresh_token = body.and_then(|body| body.into_inner().refresh_token).or(session_refresh_token);
+    web::block(move || -> Result<(), ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        RevocationStore::revoke(&mut conn, &claims)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+
+        if let Some(refresh_token) = refresh_token {
+            revoke_refresh_token(&mut conn, &refresh_token)
+                .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+        }
+
+        Ok(())
+    })
+    .await??;
+
+    tracing::info!("Logout successful");
+    let mut response = HttpResponse::NoContent().finish();
+    if let Some(session) = session {
+        session::sign_out(&session);
+        response.add_removal_cookie(&session::csrf_cookie(&config, String::new()))?;
+    }
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::AUTH,
+    responses(
+        (status = 200, description = "Public keys for verifying access tokens (RFC 7517 JWK Set)", content_type = "application/json"),
+        (status = INTERNAL_SERVER_ERROR, description = "Signing keys are misconfigured")
+    )
+)]
+#[get("/.well-known/jwks.json")]
+#[tracing::instrument]
+pub async fn jwks() -> actix_web::Result<impl Responder> {
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    let keyring = jwt::keyring(&config).map_err(|e| {
+        tracing::error!(error = %e, "Failed to load signing keys");
         // Requirements: 11.2 - Hide detailed error information in production
         if config.is_production() {
-            error::ErrorInternalServerError("Authentication service unavailable")
+            error::ErrorInternalServerError("Authentication service error")
         } else {
-            error::ErrorInternalServerError(format!("LDAP connection failed: {}", e))
+            error::ErrorInternalServerError(e)
         }
     })?;
 
-    ldap3::drive!(conn);
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "public, max-age=300"))
+        .json(keyring.jwks()))
+}
 
-    let dn = format!("{}={}, {}", &config.ldap_uid_column, &info.username, &config.ldap_user_dn);
-    
-    // LDAP bind operation with tracing
-    let bind_span = tracing::info_span!("ldap_bind", auth.ldap_bind = tracing::field::Empty);
-    let result = async {
-        let result = ldap.simple_bind(&dn, &info.password)
-            .await
+/// Answers a login that passed the first factor: with a challenge if a second one is needed, otherwise with tokens
+async fn login_response(config: &config::Config, pool: web::Data<DbPool>, req: &HttpRequest, user: &User, roles: &[String]) -> actix_web::Result<HttpResponse> {
+    use crate::models::mfa::usecases::{create_mfa_challenge, find_user_mfa};
+
+    let policy = MfaPolicy::from_config(config);
+    let required = policy.is_required(roles);
+    let user_id = user.id;
+    let cloned_pool = pool.clone();
+    let challenge = web::block(move || -> Result<Option<mfa::MfaChallengeResponse>, crate::errors::ServiceError> {
+        let mut conn = cloned_pool.get()
             .map_err(|e| {
-                tracing::error!(error = ?e, dn = %dn, "LDAP bind failed");
-                // Requirements: 11.2 - Hide detailed error information in production
-                if config.is_production() {
-                    error::ErrorInternalServerError("Authentication failed")
-                } else {
-                    error::ErrorInternalServerError(format!("LDAP bind failed: {}", e))
-                }
+                tracing::error!(error = ?e, "Failed to get database connection");
+                crate::errors::ServiceError::InternalServerError
             })?;
-        Ok::<_, actix_web::Error>(result)
+
+        let enabled = find_user_mfa(&mut conn, user_id)
+            .map_err(|e| crate::errors::ServiceError::DatabaseError { message: e.to_string() })?
+            .is_some_and(|mfa| mfa.is_enabled());
+        if !enabled && !required {
+            return Ok(None);
+        }
+
+        let mfa_token = create_mfa_challenge(&mut conn, user_id, policy.challenge_ttl_secs)
+            .map_err(|e| crate::errors::ServiceError::DatabaseError { message: e.to_string() })?;
+        Ok(Some(mfa::MfaChallengeResponse {
+            mfa_token,
+            expires_in: policy.challenge_ttl_secs,
+            enrollment_required: !enabled,
+        }))
+    })
+    .await??;
+
+    if let Some(challenge) = challenge {
+        tracing::info!(user_id = %user.id, enrollment_required = challenge.enrollment_required, "Second factor required");
+        return Ok(HttpResponse::Accepted().json(challenge));
     }
-    .instrument(bind_span.clone())
-    .await?;
-
-    if let Ok(_status) = result.success() {
-        use crate::metrics::AuthMetrics;
-        
-        bind_span.record("auth.ldap_bind", "success");
-        tracing::Span::current().record("auth.ldap_bind", "success");
-        
-        // Requirements: 12.5 - Authentication metrics collection
-        AuthMetrics::record_attempt(true);
-        // partner should not be able to login
-        let guard_filter = "(&(cn=Partner)(objectCategory=CN=Group*))";
-        let guard_result = ldap.search(&config.ldap_user_dn, ldap3::Scope::OneLevel, &guard_filter, vec!["member"])
-            .await
-            .map_err(|e| {
-                tracing::error!(error = ?e, filter = %guard_filter, "LDAP group search failed");
-                // Requirements: 11.2 - Hide detailed error information in production
-                if config.is_production() {
-                    error::ErrorInternalServerError("Authentication service error")
-                } else {
-                    error::ErrorInternalServerError(format!("LDAP group search failed: {}", e))
-                }
-            })?
-            .0.first().unwrap().to_owned();
 
-        let guard_entry = ldap3::SearchEntry::construct(guard_result);
+    issue_tokens(config, pool, req, user, roles).await
+}
 
-        if let Some(members) = guard_entry.attrs.get("members") {
-            if members.iter().any(|member| member.contains(&info.username)) {
-                tracing::warn!(username = %info.username, "Login denied: user is in Partner group");
-                return Ok(HttpResponse::Forbidden().finish())
-            }
-        };
-
-        use crate::models::users::usecases::*;
-
-        // LDAP user search operation with tracing
-        let search_span = tracing::info_span!("ldap_user_search", auth.user_search = tracing::field::Empty);
-        let search_filter = format!("(&({}={}){})",&config.ldap_uid_column, &info.username, &config.ldap_filter);
-        let search_entry = async {
-            let result = ldap.search(&config.ldap_user_dn, ldap3::Scope::OneLevel, &search_filter, vec!["employeeNumber", "sn", "givenName", "mail", "gecos"])
-                .await
-                .map_err(|e| {
-                    tracing::error!(error = ?e, filter = %search_filter, "LDAP user search failed");
-                    // Requirements: 11.2 - Hide detailed error information in production
-                    if config.is_production() {
-                        error::ErrorInternalServerError("User information retrieval failed")
-                    } else {
-                        error::ErrorInternalServerError(format!("LDAP user search failed: {}", e))
-                    }
-                })?
-                .0.first().unwrap().to_owned();
-            Ok::<_, actix_web::Error>(result)
-        }
-        .instrument(search_span.clone())
-        .await?;
-        
-        search_span.record("auth.user_search", "success");
-        tracing::Span::current().record("auth.user_search", "success");
+/// Issues a refresh token to a user who just logged in, and builds the token response
+async fn issue_tokens(config: &config::Config, pool: web::Data<DbPool>, req: &HttpRequest, user: &User, roles: &[String]) -> actix_web::Result<HttpResponse> {
+    // Failures are only forgotten once every factor has passed
+    if LockoutPolicy::from_config(config).is_enabled()
+        && let Err(e) = lockout::record_success(pool.clone(), &user.login_id).await
+    {
+        tracing::warn!(error = ?e, username = %user.login_id, "Failed to clear login failures");
+    }
 
-        let user_info = ldap3::SearchEntry::construct(search_entry);
+    let ttl_secs = config.get_refresh_token_ttl_secs();
+    let user_id = user.id;
+    let refresh_token = web::block(move || -> Result<String, crate::errors::ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                crate::errors::ServiceError::InternalServerError
+            })?;
 
-        let cloned_pool = pool.clone();
-        let cloned_info = info.clone();
-        let users = web::block(move || -> Result<Vec<crate::models::users::User>, diesel::result::Error> {
-            let mut conn = cloned_pool.get()
-                .map_err(|_| diesel::result::Error::BrokenTransactionManager)?;
+        issue_refresh_token(&mut conn, user_id, ttl_secs)
+    })
+    .await?
+    .map_err(|e| {
+        tracing::error!(error = ?e, user_id = %user_id, "Failed to issue refresh token");
+        e
+    })?;
 
-            search_user(&mut conn, &cloned_info.username)
-        })
-        .await?
+    token_response(config, req, user, roles, refresh_token, true)
+}
+
+/// Builds the response carrying a new access token and refresh token.
+///
+/// With `SESSION_AUTH_ENABLED` the tokens go into the session instead; a
+/// login (`new_session`) also starts a new session with a new CSRF token.
+fn token_response(config: &config::Config, req: &HttpRequest, user: &User, roles: &[String], refresh_token: String, new_session: bool) -> actix_web::Result<HttpResponse> {
+    let token = jwt::issue_access_token(config, user.id, &user.login_id, roles)
         .map_err(|e| {
-            tracing::error!(error = ?e, username = %info.username, "Failed to search user in database");
-            error::ErrorInternalServerError(e)
+            tracing::error!(error = %e, "Failed to issue access token");
+            // Requirements: 11.2 - Hide detailed error information in production
+            if config.is_production() {
+                error::ErrorInternalServerError("Authentication service error")
+            } else {
+                error::ErrorInternalServerError(e)
+            }
         })?;
 
-        let employee_number = if let Some(v) = user_info.attrs.get("employeeNumber") {
-            if let Some(first) = v.first() {
-                let parsed = first.parse::<i32>();
-                if let Ok(num) = parsed {
-                    Some(num)
-                } else {
-                    None
-                }
-            }  else {
-                None
-            }
-        } else {
-            None
-        };
+    if config.is_session_auth_enabled() {
+        let session = req.get_session();
+        if !new_session {
+            session::update_tokens(&session, &token, &refresh_token)?;
+            return Ok(HttpResponse::NoContent().finish());
+        }
+        let csrf_token = session::sign_in(&session, &token, &refresh_token)?;
+        return Ok(HttpResponse::NoContent()
+            .cookie(session::csrf_cookie(config, csrf_token))
+            .finish());
+    }
 
-        let first_name = if let Some(v) = user_info.attrs.get("givenName") {
-            v.first().cloned()
-        } else {
-            None
-        };
+    // The header is kept for clients written before the body carried the token
+    Ok(HttpResponse::Ok()
+        .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+        .json(TokenResponse {
+            access_token: token,
+            token_type: "Bearer".to_string(),
+            expires_in: config.get_access_token_ttl_secs(),
+            refresh_token,
+            refresh_expires_in: config.get_refresh_token_ttl_secs(),
+            user: user.clone(),
+        }))
+}
 
-        let last_name = if let Some(v) = user_info.attrs.get("sn") {
-            v.first().cloned()
-        } else {
-            None
-        };
+/// Returns the injected backend, or builds the one selected by `AUTH_BACKEND`
+fn resolve_backend(
+    req: &actix_web::HttpRequest,
+    pool: &web::Data<DbPool>,
+    config: &config::Config,
+) -> actix_web::Result<web::Data<dyn AuthBackend>> {
+    if let Some(backend) = req.app_data::<web::Data<dyn AuthBackend>>() {
+        return Ok(backend.clone());
+    }
 
-        let email = if let Some(v) = user_info.attrs.get("mail") {
-            v.first().cloned()
-        } else {
-            None
-        };
+    backend::from_config(config, pool.get_ref().clone())
+        .map(web::Data::from)
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to create authentication backend");
+            error::ErrorInternalServerError(e)
+        })
+}
 
-        let gecos = if let Some(v) = user_info.attrs.get("gecos") {
-            v.first().cloned()
-        } else {
-            None
-        };
+/// Creates or refreshes the user from an authenticated profile, and returns their roles
+async fn provision_user(pool: web::Data<DbPool>, profile: DirectoryProfile) -> actix_web::Result<(User, Vec<String>)> {
+    use crate::models::users::usecases::upsert_login_user;
 
-        let user: Cow<'_, User> = match users.first() {
-            Some(user) => {
-                tracing::debug!(user_id = %user.id, "Existing user found");
-                Cow::Borrowed(user)
-            }
-            None => {
-                let username_for_log = info.username.clone();
-                tracing::info!(username = %username_for_log, "Creating new user");
-                let user = web::block(move || -> Result<crate::models::users::User, crate::errors::ServiceError> {
-                    let mut conn = pool.get()
-                        .map_err(|e| {
-                            tracing::error!(error = ?e, "Failed to get database connection");
-                            crate::errors::ServiceError::InternalServerError
-                        })?;
-                    insert_new_user(
-                        &mut conn,
-                        info.username.to_string(),
-                        employee_number,
-                        first_name,
-                        last_name,
-                        email,
-                        gecos
-                    )
-                })
-                .await?
-                .map_err(|e| {
-                    tracing::error!(error = ?e, username = %username_for_log, "Failed to insert new user");
-                    error::ErrorInternalServerError(format!("{:?}", e))
-                })?;
-                tracing::info!(user_id = %user.id, "New user created successfully");
-                Cow::Owned(user)
-            }
-        };
-
-        let user_id = user.id;
-        let username = user.login_id.clone();
-        
-        let claims = UserClaims{
-                id: user.id,
-                username: user.into_owned().login_id,
-                exp: (chrono::Utc::now() + chrono::Duration::days(7)).timestamp()
-            };
-
-        let jwt_config = crate::config::get_config()
-            .map_err(|e| {
-                tracing::error!(error = ?e, "Failed to get configuration for JWT");
-                // Requirements: 11.2 - Hide detailed error information in production
-                if config.is_production() {
-                    error::ErrorInternalServerError("Authentication service error")
-                } else {
-                    error::ErrorInternalServerError(format!("Configuration error: {}", e))
-                }
-            })?;
-        
-        let secret = jwt_config.jwt_secret.split(" ")
-            .map(|hex_str| u8::from_str_radix(hex_str, 16))
-            .collect::<Result<Vec<u8>, _>>()
+    let cloned_pool = pool.clone();
+    let username = profile.login_id.clone();
+    let directory_roles = profile.roles.clone();
+    let upsert = web::block(move || -> Result<_, crate::errors::ServiceError> {
+        let mut conn = cloned_pool.get()
             .map_err(|e| {
-                tracing::error!(error = ?e, "Failed to parse JWT secret");
-                // Requirements: 11.2 - Hide detailed error information in production
-                if config.is_production() {
-                    error::ErrorInternalServerError("Authentication service error")
-                } else {
-                    error::ErrorInternalServerError(format!("JWT secret configuration error: {}", e))
-                }
-            })?;
-        
-        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(&secret))
-            .map_err(|e| {
-                tracing::error!(error = ?e, "Failed to encode JWT token");
-                // Requirements: 11.2 - Hide detailed error information in production
-                if config.is_production() {
-                    error::ErrorInternalServerError("Authentication service error")
-                } else {
-                    error::ErrorInternalServerError(format!("Token generation error: {}", e))
-                }
+                tracing::error!(error = ?e, "Failed to get database connection");
+                crate::errors::ServiceError::InternalServerError
             })?;
 
-        tracing::info!(user_id = %user_id, username = %username, "Login successful");
-        let mut response = HttpResponse::Ok();
-        response.insert_header((header::AUTHORIZATION, format!("Bearer {}",token.to_string())));
-
-        Ok(response.finish())
-    } else {
-        use crate::metrics::AuthMetrics;
-        
-        tracing::Span::current().record("auth.ldap_bind", "failed");
-        tracing::warn!(username = %info.username, "Login failed: invalid credentials");
-        
-        // Requirements: 12.5 - Authentication metrics collection
-        AuthMetrics::record_attempt(false);
-        
-        Ok(HttpResponse::Unauthorized().finish())
+        upsert_login_user(
+            &mut conn,
+            profile.login_id,
+            profile.employee_number,
+            profile.first_name,
+            profile.last_name,
+            profile.email,
+            profile.gecos
+        )
+    })
+    .await?
+    .map_err(|e| match e {
+        // Directories may still accept users the sync removed, so no tokens are issued for them
+        crate::errors::ServiceError::Forbidden { .. } => error::Error::from(e),
+        e => {
+            tracing::error!(error = ?e, username = %username, "Failed to save user profile");
+            error::ErrorInternalServerError(format!("{:?}", e))
+        }
+    })?;
+
+    let user = upsert.user;
+    if upsert.created {
+        tracing::info!(user_id = %user.id, username = %username, "New user created successfully");
+    } else if !upsert.changed.is_empty() {
+        tracing::info!(user_id = %user.id, changed = ?upsert.changed, "User profile updated from directory");
     }
+
+    let roles = sync_roles(pool, user.id, directory_roles).await?;
+    Ok((user, roles))
+}
+
+/// Answers a login of a locked account with 429 until the lock ends
+fn locked_response(locked_until: chrono::NaiveDateTime) -> HttpResponse {
+    AuthMetrics::record_lockout_event("rejected");
+    let retry_after = (locked_until - chrono::Utc::now().naive_utc()).num_seconds().max(0) + 1;
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .body("Too many failed login attempts. Please try again later.")
+}
+
+/// Replaces the stored roles with the directory's if it manages them, and returns the user's roles
+async fn sync_roles(pool: web::Data<DbPool>, user_id: i32, directory_roles: Option<Vec<String>>) -> actix_web::Result<Vec<String>> {
+    use crate::models::roles::usecases::{find_user_roles, set_user_roles};
+
+    web::block(move || -> Result<Vec<String>, diesel::result::Error> {
+        let mut conn = pool.get()
+            .map_err(|_| diesel::result::Error::BrokenTransactionManager)?;
+
+        let roles = find_user_roles(&mut conn, user_id)?;
+        match directory_roles {
+            Some(directory_roles) if directory_roles != roles => {
+                tracing::info!(user_id = %user_id, old_roles = ?roles, new_roles = ?directory_roles, "Updating user roles from directory");
+                set_user_roles(&mut conn, user_id, &directory_roles)?;
+                find_user_roles(&mut conn, user_id)
+            }
+            _ => Ok(roles),
+        }
+    })
+    .await?
+    .map_err(|e| {
+        tracing::error!(error = ?e, user_id = %user_id, "Failed to update user roles");
+        error::ErrorInternalServerError(e)
+    })
 }
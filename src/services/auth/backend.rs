@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use derive_more::Display;
+use futures_util::future::LocalBoxFuture;
+use crate::{config::Config, DbPool};
+
+pub mod fake;
+pub mod ldap;
+pub mod local;
+
+/// User attributes returned by an authentication backend.
+///
+/// The login handler provisions the `users` row from this profile, so every
+/// backend must fill in at least `login_id`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DirectoryProfile {
+    pub login_id: String,
+    pub employee_number: Option<i32>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub gecos: Option<String>,
+    /// Roles granted by the directory, replacing the stored roles on login.
+    /// `None` if the backend does not manage roles.
+    pub roles: Option<Vec<String>>,
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum AuthError {
+    /// The username or password is wrong
+    #[display("Invalid credentials")]
+    InvalidCredentials,
+    /// The credentials are valid but the user is not allowed to log in
+    #[display("Login denied: {reason}")]
+    Forbidden { reason: String },
+    /// The backend could not be reached or returned an unexpected error
+    #[display("Authentication backend unavailable: {message}")]
+    Unavailable { message: String },
+}
+
+/// Verifies user credentials against a directory or credential store.
+///
+/// Implementations are registered as `web::Data<dyn AuthBackend>` so that
+/// tests can inject a [`fake::FakeBackend`] instead of a real directory.
+pub trait AuthBackend: Send + Sync {
+    /// Returns the backend name used in logs and traces
+    fn name(&self) -> &'static str;
+
+    /// Checks the credentials and returns the profile of the authenticated user
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DirectoryProfile, AuthError>>;
+}
+
+/// Builds the backend selected by `AUTH_BACKEND`
+pub fn from_config(config: &Config, pool: DbPool) -> Result<Arc<dyn AuthBackend>, String> {
+    match config.get_auth_backend().as_str() {
+        "ldap" => Ok(Arc::new(ldap::LdapBackend::from_config(config)?)),
+        "local" => Ok(Arc::new(local::LocalBackend::new(pool))),
+        other => Err(format!(
+            "Invalid AUTH_BACKEND: '{}'. Must be 'ldap' or 'local'",
+            other
+        )),
+    }
+}
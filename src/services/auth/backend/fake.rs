@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use futures_util::future::LocalBoxFuture;
+use super::{AuthBackend, AuthError, DirectoryProfile};
+
+/// In-process backend for tests.
+///
+/// ```ignore
+/// let backend = FakeBackend::new()
+///     .with_user(DirectoryProfile { login_id: "alice".into(), ..Default::default() }, "secret");
+/// App::new().app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend {
+    accounts: HashMap<String, FakeAccount>,
+    unavailable: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct FakeAccount {
+    password: String,
+    profile: DirectoryProfile,
+    denied: bool,
+}
+
+impl FakeBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a user that can log in with `password`
+    pub fn with_user(mut self, profile: DirectoryProfile, password: &str) -> Self {
+        self.accounts.insert(profile.login_id.clone(), FakeAccount {
+            password: password.to_string(),
+            profile,
+            denied: false,
+        });
+        self
+    }
+
+    /// Registers a user whose credentials are valid but who is not allowed to log in
+    pub fn with_denied_user(mut self, login_id: &str, password: &str) -> Self {
+        self.accounts.insert(login_id.to_string(), FakeAccount {
+            password: password.to_string(),
+            profile: DirectoryProfile { login_id: login_id.to_string(), ..Default::default() },
+            denied: true,
+        });
+        self
+    }
+
+    /// Makes every authentication fail as if the backend were down
+    pub fn unavailable(mut self, message: &str) -> Self {
+        self.unavailable = Some(message.to_string());
+        self
+    }
+}
+
+impl AuthBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DirectoryProfile, AuthError>> {
+        Box::pin(async move {
+            if let Some(message) = &self.unavailable {
+                return Err(AuthError::Unavailable { message: message.clone() });
+            }
+
+            let account = self.accounts.get(username)
+                .filter(|account| account.password == password)
+                .ok_or(AuthError::InvalidCredentials)?;
+
+            if account.denied {
+                return Err(AuthError::Forbidden { reason: "denied by fake backend".to_string() });
+            }
+
+            Ok(account.profile.clone())
+        })
+    }
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+use futures_util::future::LocalBoxFuture;
+use ldap3::{Ldap, Scope, SearchEntry};
+use tracing::Instrument;
+use crate::config::Config;
+use super::{AuthBackend, AuthError, DirectoryProfile};
+use connection::LdapConnector;
+use escape::{equality_filter, escape_filter_value, rdn};
+use groups::{GroupPolicy, GroupSource};
+use pool::{LdapPool, PooledConnection};
+
+pub mod connection;
+pub mod escape;
+pub mod groups;
+pub mod pool;
+pub mod sync;
+
+/// Attributes read into the [`DirectoryProfile`], plus the groups for [`GroupSource::MemberOf`]
+const PROFILE_ATTRS: [&str; 6] = ["employeeNumber", "sn", "givenName", "mail", "gecos", "memberOf"];
+
+/// How the DN to bind as is determined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindMode {
+    /// Bind as `{LDAP_UID_COLUMN}={username}, {LDAP_USER_DN}`
+    Direct,
+    /// Search the subtree under `LDAP_SEARCH_BASE` for the user's DN as the
+    /// service account, then bind as that DN
+    Search,
+}
+
+/// Authenticates users with a simple bind against an LDAP directory.
+///
+/// With a service account (`LDAP_BIND_DN`), searches run on pooled
+/// connections and the user's bind on a dedicated one that is unbound
+/// afterwards. Without one, everything runs on a single connection bound
+/// as the user.
+#[derive(Clone, Debug)]
+pub struct LdapBackend {
+    connector: LdapConnector,
+    user_dn: String,
+    uid_column: String,
+    filter: String,
+    bind_mode: BindMode,
+    search_base: String,
+    groups: GroupPolicy,
+    pool: Option<Arc<LdapPool>>,
+}
+
+impl LdapBackend {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let connector = LdapConnector::from_config(config)?;
+
+        let pool = match (&config.ldap_bind_dn, &config.ldap_bind_password) {
+            (Some(bind_dn), Some(bind_password)) => Some(Arc::new(
+                LdapPool::new(connector.clone(), bind_dn.clone(), bind_password.clone(), config)
+            )),
+            (None, None) => None,
+            _ => return Err("LDAP_BIND_DN and LDAP_BIND_PASSWORD must be set together".to_string()),
+        };
+
+        let bind_mode = match config.get_ldap_bind_mode().as_str() {
+            "direct" => BindMode::Direct,
+            "search" if pool.is_some() => BindMode::Search,
+            "search" => return Err(
+                "LDAP_BIND_DN and LDAP_BIND_PASSWORD are required when LDAP_BIND_MODE=search".to_string()
+            ),
+            other => return Err(format!(
+                "Invalid LDAP_BIND_MODE: '{}'. Must be 'direct' or 'search'",
+                other
+            )),
+        };
+
+        Ok(LdapBackend {
+            connector,
+            user_dn: config.ldap_user_dn.clone(),
+            uid_column: config.ldap_uid_column.clone(),
+            filter: config.ldap_filter.clone(),
+            bind_mode,
+            search_base: config.get_ldap_search_base(),
+            groups: GroupPolicy::from_config(config)?,
+            pool,
+        })
+    }
+
+    pub fn bind_mode(&self) -> BindMode {
+        self.bind_mode
+    }
+
+    pub fn connector(&self) -> &LdapConnector {
+        &self.connector
+    }
+
+    pub fn pool(&self) -> Option<&LdapPool> {
+        self.pool.as_deref()
+    }
+
+    pub fn groups(&self) -> &GroupPolicy {
+        &self.groups
+    }
+
+    /// Binds as the user; a rejected bind means invalid credentials
+    async fn bind(&self, ldap: &mut Ldap, dn: &str, password: &str) -> Result<(), AuthError> {
+        // LDAP bind operation with tracing
+        let bind_span = tracing::info_span!("ldap_bind", auth.ldap_bind = tracing::field::Empty);
+        let result = ldap.with_timeout(self.connector.operation_timeout())
+            .simple_bind(dn, password)
+            .instrument(bind_span.clone())
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, dn = %dn, "LDAP bind failed");
+                AuthError::Unavailable { message: format!("LDAP bind failed: {}", e) }
+            })?;
+
+        if result.success().is_err() {
+            bind_span.record("auth.ldap_bind", "failed");
+            tracing::Span::current().record("auth.ldap_bind", "failed");
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        bind_span.record("auth.ldap_bind", "success");
+        tracing::Span::current().record("auth.ldap_bind", "success");
+        Ok(())
+    }
+
+    /// Binds as the user on a dedicated connection, which is unbound afterwards
+    async fn bind_as_user(&self, dn: &str, password: &str) -> Result<(), AuthError> {
+        let mut ldap = self.connector.connect().await?;
+        let result = self.bind(&mut ldap, dn, password).await;
+        unbind(&mut ldap).await;
+        result
+    }
+
+    /// Direct bind without a service account: bind, profile search and group checks on one connection
+    async fn authenticate_unpooled(&self, ldap: &mut Ldap, username: &str, password: &str) -> Result<DirectoryProfile, AuthError> {
+        self.bind(ldap, &self.user_dn(username), password).await?;
+        let entry = self.search_profile(ldap, username).await?;
+        self.authorize(ldap, username, entry).await
+    }
+
+    /// Searches the subtree under `search_base` for the single entry of the user
+    async fn find_user(&self, ldap: &mut Ldap, username: &str) -> Result<SearchEntry, AuthError> {
+        let search_span = tracing::info_span!("ldap_user_search", auth.user_search = tracing::field::Empty);
+        let search_filter = self.user_filter(username);
+        let (entries, _) = ldap.with_timeout(self.connector.operation_timeout())
+            .search(&self.search_base, Scope::Subtree, &search_filter, self.profile_attrs())
+            .instrument(search_span.clone())
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| {
+                tracing::error!(error = ?e, filter = %search_filter, "LDAP user search failed");
+                AuthError::Unavailable { message: format!("LDAP user search failed: {}", e) }
+            })?;
+
+        let mut entries = entries.into_iter();
+        let (Some(entry), None) = (entries.next(), entries.next()) else {
+            // Unknown users and ambiguous matches look the same to the client as a wrong password
+            tracing::warn!(username = %username, filter = %search_filter, "Login failed: user not found or not unique under LDAP_SEARCH_BASE");
+            search_span.record("auth.user_search", "failed");
+            return Err(AuthError::InvalidCredentials);
+        };
+
+        search_span.record("auth.user_search", "success");
+        tracing::Span::current().record("auth.user_search", "success");
+        Ok(SearchEntry::construct(entry))
+    }
+
+    /// Returns the DN bound as in direct bind mode
+    pub fn user_dn(&self, username: &str) -> String {
+        format!("{}, {}", rdn(&self.uid_column, username), &self.user_dn)
+    }
+
+    /// Returns the filter matching the user's entry, restricted by `LDAP_FILTER`
+    pub fn user_filter(&self, username: &str) -> String {
+        format!("(&{}{})", equality_filter(&self.uid_column, username), &self.filter)
+    }
+
+    /// Returns the login id of the user's entry, as the directory sync sees it.
+    ///
+    /// Directories match the uid case-insensitively, so the username as typed
+    /// could differ from it.
+    pub fn login_id(&self, username: &str, entry: &SearchEntry) -> String {
+        first_attr(entry, &self.uid_column).unwrap_or_else(|| username.to_string())
+    }
+
+    /// Returns the attributes read from user entries
+    fn profile_attrs(&self) -> Vec<&str> {
+        let mut attrs = PROFILE_ATTRS.to_vec();
+        attrs.push(&self.uid_column);
+        attrs
+    }
+
+    /// Applies the deny groups and role mapping to the user's entry
+    async fn authorize(&self, ldap: &mut Ldap, username: &str, entry: SearchEntry) -> Result<DirectoryProfile, AuthError> {
+        let login_id = self.login_id(username, &entry);
+        if !self.groups.needs_groups() {
+            return Ok(profile_from_entry(&login_id, entry, None));
+        }
+
+        let groups = match self.groups.source() {
+            GroupSource::MemberOf => entry.attrs.get("memberOf").cloned().unwrap_or_default(),
+            GroupSource::Search => self.search_groups(ldap, &entry.dn).await?,
+        };
+
+        if let Some(group) = self.groups.denied_by(&groups) {
+            tracing::warn!(username = %username, group = %group, "Login denied: user is in a deny group");
+            return Err(AuthError::Forbidden { reason: format!("user is in group {}", group) });
+        }
+
+        let roles = self.groups.manages_roles().then(|| self.groups.roles_for(&groups));
+        Ok(profile_from_entry(&login_id, entry, roles))
+    }
+
+    /// Returns the DNs of the groups listing `user_dn` as a member
+    async fn search_groups(&self, ldap: &mut Ldap, user_dn: &str) -> Result<Vec<String>, AuthError> {
+        let group_filter = format!("({}={})", self.groups.member_attribute(), escape_filter_value(user_dn));
+        let (entries, _) = ldap.with_timeout(self.connector.operation_timeout())
+            .search(self.groups.search_base(), Scope::Subtree, &group_filter, vec!["1.1"])
+            .instrument(tracing::info_span!("ldap_group_search"))
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| {
+                tracing::error!(error = ?e, filter = %group_filter, "LDAP group search failed");
+                AuthError::Unavailable { message: format!("LDAP group search failed: {}", e) }
+            })?;
+
+        Ok(entries.into_iter().map(|entry| SearchEntry::construct(entry).dn).collect())
+    }
+
+    async fn search_profile(&self, ldap: &mut Ldap, username: &str) -> Result<SearchEntry, AuthError> {
+        // LDAP user search operation with tracing
+        let search_span = tracing::info_span!("ldap_user_search", auth.user_search = tracing::field::Empty);
+        let search_filter = self.user_filter(username);
+        let (entries, _) = ldap.with_timeout(self.connector.operation_timeout())
+            .search(&self.user_dn, Scope::OneLevel, &search_filter, self.profile_attrs())
+            .instrument(search_span.clone())
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| {
+                tracing::error!(error = ?e, filter = %search_filter, "LDAP user search failed");
+                AuthError::Unavailable { message: format!("LDAP user search failed: {}", e) }
+            })?;
+
+        let Some(search_entry) = entries.into_iter().next() else {
+            tracing::warn!(username = %username, filter = %search_filter, "Login denied: user does not match LDAP_FILTER");
+            return Err(AuthError::Forbidden { reason: "user does not match LDAP_FILTER".to_string() });
+        };
+
+        search_span.record("auth.user_search", "success");
+        tracing::Span::current().record("auth.user_search", "success");
+
+        Ok(SearchEntry::construct(search_entry))
+    }
+}
+
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DirectoryProfile, AuthError>> {
+        Box::pin(async move {
+            // An empty password would be an unauthenticated bind, which servers accept
+            if password.is_empty() {
+                return Err(AuthError::InvalidCredentials);
+            }
+
+            let Some(pool) = &self.pool else {
+                let mut ldap = self.connector.connect().await?;
+                let result = self.authenticate_unpooled(&mut ldap, username, password).await;
+                unbind(&mut ldap).await;
+                return result;
+            };
+
+            match self.bind_mode {
+                BindMode::Direct => {
+                    self.bind_as_user(&self.user_dn(username), password).await?;
+
+                    let mut conn = pool.get().await?;
+                    let result = self.search_profile(&mut conn, username).await;
+                    let entry = discard_on_failure(&mut conn, result)?;
+                    let result = self.authorize(&mut conn, username, entry).await;
+                    discard_on_failure(&mut conn, result)
+                }
+                BindMode::Search => {
+                    let mut conn = pool.get().await?;
+                    let result = self.find_user(&mut conn, username).await;
+                    let entry = discard_on_failure(&mut conn, result)?;
+
+                    self.bind_as_user(&entry.dn, password).await?;
+
+                    let result = self.authorize(&mut conn, username, entry).await;
+                    discard_on_failure(&mut conn, result)
+                }
+            }
+        })
+    }
+}
+
+/// Keeps a pooled connection out of the pool if the directory failed on it
+fn discard_on_failure<T>(conn: &mut PooledConnection<'_>, result: Result<T, AuthError>) -> Result<T, AuthError> {
+    if matches!(result, Err(AuthError::Unavailable { .. })) {
+        conn.mark_broken();
+    }
+    result
+}
+
+/// Ends the session of a per-user connection
+async fn unbind(ldap: &mut Ldap) {
+    if let Err(e) = ldap.unbind().await {
+        tracing::debug!(error = ?e, "LDAP unbind failed");
+    }
+}
+
+fn first_attr(entry: &SearchEntry, name: &str) -> Option<String> {
+    entry.attrs.get(name).and_then(|v| v.first()).cloned()
+}
+
+fn profile_from_entry(username: &str, entry: SearchEntry, roles: Option<Vec<String>>) -> DirectoryProfile {
+    DirectoryProfile {
+        login_id: username.to_string(),
+        employee_number: first_attr(&entry, "employeeNumber").and_then(|v| v.parse::<i32>().ok()),
+        first_name: first_attr(&entry, "givenName"),
+        last_name: first_attr(&entry, "sn"),
+        email: first_attr(&entry, "mail"),
+        gecos: first_attr(&entry, "gecos"),
+        roles,
+    }
+}
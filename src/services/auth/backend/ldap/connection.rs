@@ -0,0 +1,151 @@
+//! Transport settings for LDAP connections
+//!
+//! Binds send the user's password, so connections are encrypted with LDAPS or
+//! StartTLS unless cleartext is explicitly allowed with `LDAP_ALLOW_CLEARTEXT`.
+
+use std::fmt;
+use std::time::Duration;
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings};
+use native_tls::{Certificate, Identity, TlsConnector};
+use crate::config::Config;
+use super::super::AuthError;
+
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// How the connection to the directory is secured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TlsMode {
+    /// Plain `ldap://`, only allowed with `LDAP_ALLOW_CLEARTEXT=true`
+    None,
+    /// `ldap://` upgraded with the StartTLS extended operation
+    StartTls,
+    /// TLS from the start on `ldaps://`
+    Ldaps,
+}
+
+/// Opens LDAP connections with the configured TLS mode and timeouts
+#[derive(Clone)]
+pub struct LdapConnector {
+    uri: String,
+    tls_mode: TlsMode,
+    settings: LdapConnSettings,
+    operation_timeout: Duration,
+}
+
+impl fmt::Debug for LdapConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapConnector")
+            .field("uri", &self.uri)
+            .field("tls_mode", &self.tls_mode)
+            .field("operation_timeout", &self.operation_timeout)
+            .finish()
+    }
+}
+
+impl LdapConnector {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let uri = config.ldap_uri.clone();
+        let scheme = uri.split("://").next().unwrap_or_default().to_lowercase();
+
+        let tls_mode = match (config.get_ldap_tls_mode().as_str(), scheme.as_str()) {
+            ("ldaps", "ldaps") => TlsMode::Ldaps,
+            ("starttls", "ldap") => TlsMode::StartTls,
+            ("none", "ldap") if config.is_ldap_cleartext_allowed() => TlsMode::None,
+            ("none", "ldap") => return Err(
+                "LDAP_TLS_MODE=none sends passwords in cleartext; set LDAP_ALLOW_CLEARTEXT=true to allow it".to_string()
+            ),
+            (mode @ ("ldaps" | "starttls" | "none"), _) => return Err(format!(
+                "LDAP_TLS_MODE={} does not match the scheme of LDAP_URI '{}'",
+                mode, uri
+            )),
+            (other, _) => return Err(format!(
+                "Invalid LDAP_TLS_MODE: '{}'. Must be 'none', 'starttls' or 'ldaps'",
+                other
+            )),
+        };
+
+        let mut settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(config.get_ldap_connect_timeout_secs()))
+            .set_starttls(tls_mode == TlsMode::StartTls);
+
+        if tls_mode != TlsMode::None {
+            settings = settings.set_connector(tls_connector(config)?);
+        }
+
+        Ok(LdapConnector {
+            uri,
+            tls_mode,
+            settings,
+            operation_timeout: Duration::from_secs(config.get_ldap_operation_timeout_secs()),
+        })
+    }
+
+    pub fn tls_mode(&self) -> TlsMode {
+        self.tls_mode
+    }
+
+    /// Timeout to apply with `Ldap::with_timeout` before each operation
+    pub fn operation_timeout(&self) -> Duration {
+        self.operation_timeout
+    }
+
+    /// Opens a connection and drives it on the current runtime
+    pub async fn connect(&self) -> Result<Ldap, AuthError> {
+        let (conn, ldap) = LdapConnAsync::with_settings(self.settings.clone(), &self.uri)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, ldap_uri = %self.uri, tls_mode = ?self.tls_mode, "Failed to connect to LDAP server");
+                AuthError::Unavailable { message: format!("LDAP connection failed: {}", e) }
+            })?;
+
+        ldap3::drive!(conn);
+
+        Ok(ldap)
+    }
+}
+
+/// Builds the TLS connector trusting `LDAP_CA_FILE` and presenting the client certificate, if any
+fn tls_connector(config: &Config) -> Result<TlsConnector, String> {
+    let mut builder = TlsConnector::builder();
+
+    if let Some(path) = &config.ldap_ca_file {
+        for certificate in read_certificates(path)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&config.ldap_client_cert_file, &config.ldap_client_key_file) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = read_file(cert_path, "LDAP_CLIENT_CERT_FILE")?;
+            let key = read_file(key_path, "LDAP_CLIENT_KEY_FILE")?;
+            let identity = Identity::from_pkcs8(&cert, &key)
+                .map_err(|e| format!("Invalid LDAP client certificate: {}", e))?;
+            builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err("LDAP_CLIENT_CERT_FILE and LDAP_CLIENT_KEY_FILE must be set together".to_string()),
+    }
+
+    builder.build().map_err(|e| format!("Failed to build LDAP TLS connector: {}", e))
+}
+
+/// Reads every certificate of a PEM bundle
+fn read_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    let bundle = String::from_utf8(read_file(path, "LDAP_CA_FILE")?)
+        .map_err(|e| format!("Invalid LDAP_CA_FILE '{}': {}", path, e))?;
+
+    let certificates = bundle.split_inclusive(PEM_CERTIFICATE_END)
+        .filter(|block| block.contains(PEM_CERTIFICATE_END))
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid LDAP_CA_FILE '{}': {}", path, e))?;
+
+    if certificates.is_empty() {
+        return Err(format!("Invalid LDAP_CA_FILE '{}': no certificates found", path));
+    }
+    Ok(certificates)
+}
+
+fn read_file(path: &str, name: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {} '{}': {}", name, path, e))
+}
@@ -0,0 +1,27 @@
+//! Escaping of user input in LDAP distinguished names and search filters
+//!
+//! Every LDAP string that contains a value from a request must be built with
+//! these helpers, so that characters like `*`, `(` or `,` in a username are
+//! matched literally instead of changing the meaning of the DN or filter.
+
+use std::borrow::Cow;
+
+/// Escapes an attribute value for use in a DN (RFC 4514, section 2.4)
+pub fn escape_dn_value(value: &str) -> Cow<'_, str> {
+    ldap3::dn_escape(value)
+}
+
+/// Escapes an assertion value for use in a search filter (RFC 4515, section 3)
+pub fn escape_filter_value(value: &str) -> Cow<'_, str> {
+    ldap3::ldap_escape(value)
+}
+
+/// Builds the relative DN `{attr}={value}` with `value` escaped
+pub fn rdn(attr: &str, value: &str) -> String {
+    format!("{}={}", attr, escape_dn_value(value))
+}
+
+/// Builds the equality filter `({attr}={value})` with `value` escaped
+pub fn equality_filter(attr: &str, value: &str) -> String {
+    format!("({}={})", attr, escape_filter_value(value))
+}
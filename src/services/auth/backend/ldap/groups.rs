@@ -0,0 +1,147 @@
+//! Group-based login policy for the LDAP backend
+//!
+//! Groups are referenced either by full DN or by CN alone, so that
+//! `LDAP_DENY_GROUPS=Partner` matches `CN=Partner,OU=Groups,DC=example,DC=com`.
+
+use crate::config::Config;
+
+/// Where a user's group memberships are read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupSource {
+    /// The `memberOf` attribute of the user's entry (Active Directory, OpenLDAP memberof overlay)
+    MemberOf,
+    /// A subtree search for groups listing the user's DN in `LDAP_GROUP_MEMBER_ATTRIBUTE`
+    Search,
+}
+
+/// A group named in the configuration
+#[derive(Clone, Debug, PartialEq)]
+enum GroupRef {
+    /// Normalized full DN
+    Dn(String),
+    /// Lowercased CN, matching any group with that CN
+    Cn(String),
+}
+
+impl GroupRef {
+    fn parse(value: &str) -> Self {
+        if value.contains('=') {
+            GroupRef::Dn(normalize_dn(value))
+        } else {
+            GroupRef::Cn(value.to_lowercase())
+        }
+    }
+
+    fn matches(&self, group_dn: &str) -> bool {
+        match self {
+            GroupRef::Dn(dn) => *dn == normalize_dn(group_dn),
+            GroupRef::Cn(cn) => common_name(group_dn).is_some_and(|name| name.to_lowercase() == *cn),
+        }
+    }
+}
+
+/// Decides from a user's groups whether they may log in and which roles they get
+#[derive(Clone, Debug)]
+pub struct GroupPolicy {
+    source: GroupSource,
+    search_base: String,
+    member_attribute: String,
+    deny: Vec<GroupRef>,
+    roles: Vec<(GroupRef, String)>,
+}
+
+impl GroupPolicy {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let source = match config.get_ldap_group_source().as_str() {
+            "member_of" => GroupSource::MemberOf,
+            "search" => GroupSource::Search,
+            other => return Err(format!(
+                "Invalid LDAP_GROUP_SOURCE: '{}'. Must be 'member_of' or 'search'",
+                other
+            )),
+        };
+
+        let deny = entries(&config.get_ldap_deny_groups())
+            .map(GroupRef::parse)
+            .collect();
+
+        let roles = entries(&config.get_ldap_role_mapping())
+            .map(|entry| {
+                let (group, role) = entry.rsplit_once(':')
+                    .map(|(group, role)| (group.trim(), role.trim()))
+                    .filter(|(group, role)| !group.is_empty() && !role.is_empty())
+                    .ok_or_else(|| format!("Invalid LDAP_ROLE_MAPPING entry: '{}'. Expected 'group:role'", entry))?;
+                if !crate::rbac::is_known_role(role) {
+                    return Err(format!("Unknown role in LDAP_ROLE_MAPPING: '{}'", role));
+                }
+                Ok((GroupRef::parse(group), role.to_string()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(GroupPolicy {
+            source,
+            search_base: config.get_ldap_group_search_base(),
+            member_attribute: config.get_ldap_group_member_attribute(),
+            deny,
+            roles,
+        })
+    }
+
+    pub fn source(&self) -> GroupSource {
+        self.source
+    }
+
+    pub fn search_base(&self) -> &str {
+        &self.search_base
+    }
+
+    pub fn member_attribute(&self) -> &str {
+        &self.member_attribute
+    }
+
+    /// Returns true if logins need the user's groups at all
+    pub fn needs_groups(&self) -> bool {
+        !self.deny.is_empty() || self.manages_roles()
+    }
+
+    /// Returns true if roles come from the directory, so stored roles are replaced on login
+    pub fn manages_roles(&self) -> bool {
+        !self.roles.is_empty()
+    }
+
+    /// Returns the first group of `groups` that denies login
+    pub fn denied_by<'a>(&self, groups: &'a [String]) -> Option<&'a str> {
+        groups.iter()
+            .find(|group| self.deny.iter().any(|deny| deny.matches(group)))
+            .map(String::as_str)
+    }
+
+    /// Returns the roles granted by `groups`, sorted and without duplicates
+    pub fn roles_for(&self, groups: &[String]) -> Vec<String> {
+        let mut roles: Vec<String> = self.roles.iter()
+            .filter(|(group, _)| groups.iter().any(|dn| group.matches(dn)))
+            .map(|(_, role)| role.clone())
+            .collect();
+        roles.sort();
+        roles.dedup();
+        roles
+    }
+}
+
+fn entries(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').map(str::trim).filter(|entry| !entry.is_empty())
+}
+
+/// Lowercases a DN and drops the spaces around its RDNs
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.trim().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Returns the value of the leading `CN=` RDN of a DN
+fn common_name(dn: &str) -> Option<&str> {
+    let (attr, value) = dn.split(',').next()?.split_once('=')?;
+    attr.trim().eq_ignore_ascii_case("cn").then(|| value.trim())
+}
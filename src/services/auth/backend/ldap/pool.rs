@@ -0,0 +1,206 @@
+//! Bounded pool of LDAP connections bound as the service account
+//!
+//! Logins use pooled connections for their search steps, so that connection
+//! setup, TLS handshake and the service bind are not paid on every login.
+//! The user's own bind always happens on a separate connection.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ldap3::{exop::WhoAmI, Ldap};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::Instrument;
+use crate::config::Config;
+use crate::metrics::{DurationTimer, LdapPoolMetrics};
+use super::super::AuthError;
+use super::connection::LdapConnector;
+
+struct IdleConnection {
+    ldap: Ldap,
+    returned_at: Instant,
+}
+
+pub struct LdapPool {
+    connector: LdapConnector,
+    bind_dn: String,
+    bind_password: String,
+    idle: Mutex<Vec<IdleConnection>>,
+    permits: Semaphore,
+    max_size: usize,
+    acquire_timeout: Duration,
+    idle_timeout: Duration,
+    health_check_after: Duration,
+}
+
+impl fmt::Debug for LdapPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapPool")
+            .field("bind_dn", &self.bind_dn)
+            .field("bind_password", &"***")
+            .field("max_size", &self.max_size)
+            .field("idle", &self.idle_count())
+            .finish()
+    }
+}
+
+impl LdapPool {
+    pub fn new(connector: LdapConnector, bind_dn: String, bind_password: String, config: &Config) -> Self {
+        let max_size = config.get_ldap_pool_max_size().max(1);
+
+        LdapPool {
+            connector,
+            bind_dn,
+            bind_password,
+            idle: Mutex::new(Vec::with_capacity(max_size)),
+            permits: Semaphore::new(max_size),
+            max_size,
+            acquire_timeout: Duration::from_secs(config.get_ldap_pool_acquire_timeout_secs()),
+            idle_timeout: Duration::from_secs(config.get_ldap_pool_idle_timeout_secs()),
+            health_check_after: Duration::from_secs(config.get_ldap_pool_health_check_secs()),
+        }
+    }
+
+    pub fn bind_dn(&self) -> &str {
+        &self.bind_dn
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the number of idle connections
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().map(|idle| idle.len()).unwrap_or_default()
+    }
+
+    /// Checks out a connection, reusing an idle one if it is still healthy
+    pub async fn get(&self) -> Result<PooledConnection<'_>, AuthError> {
+        let timer = DurationTimer::new();
+
+        let permit = match actix_web::rt::time::timeout(self.acquire_timeout, self.permits.acquire()).await {
+            Ok(Ok(permit)) => permit,
+            Ok(Err(e)) => {
+                LdapPoolMetrics::record_acquire("error", timer.elapsed_secs());
+                return Err(AuthError::Unavailable { message: format!("LDAP connection pool closed: {}", e) });
+            }
+            Err(_) => {
+                tracing::warn!(max_size = self.max_size, "Timed out waiting for a pooled LDAP connection");
+                LdapPoolMetrics::record_acquire("timeout", timer.elapsed_secs());
+                return Err(AuthError::Unavailable { message: "LDAP connection pool exhausted".to_string() });
+            }
+        };
+
+        while let Some(IdleConnection { mut ldap, returned_at }) = self.take_idle() {
+            let idle_for = returned_at.elapsed();
+            if idle_for > self.idle_timeout {
+                LdapPoolMetrics::record_discarded("idle_timeout");
+                continue;
+            }
+            if ldap.is_closed() {
+                LdapPoolMetrics::record_discarded("broken");
+                continue;
+            }
+            if idle_for > self.health_check_after && !self.is_healthy(&mut ldap).await {
+                LdapPoolMetrics::record_discarded("health_check");
+                continue;
+            }
+
+            LdapPoolMetrics::record_acquire("reused", timer.elapsed_secs());
+            return Ok(PooledConnection::new(self, ldap, permit));
+        }
+
+        let ldap = self.open().await.inspect_err(|_| {
+            LdapPoolMetrics::record_acquire("error", timer.elapsed_secs());
+        })?;
+
+        LdapPoolMetrics::record_created();
+        LdapPoolMetrics::record_acquire("created", timer.elapsed_secs());
+        Ok(PooledConnection::new(self, ldap, permit))
+    }
+
+    /// Opens a new connection bound as the service account
+    async fn open(&self) -> Result<Ldap, AuthError> {
+        let mut ldap = self.connector.connect().await?;
+
+        ldap.with_timeout(self.connector.operation_timeout())
+            .simple_bind(&self.bind_dn, &self.bind_password)
+            .instrument(tracing::info_span!("ldap_service_bind"))
+            .await
+            .and_then(|result| result.success())
+            .map_err(|e| {
+                tracing::error!(error = ?e, bind_dn = %self.bind_dn, "LDAP service account bind failed");
+                AuthError::Unavailable { message: format!("LDAP service account bind failed: {}", e) }
+            })?;
+
+        Ok(ldap)
+    }
+
+    async fn is_healthy(&self, ldap: &mut Ldap) -> bool {
+        let result = ldap.with_timeout(self.connector.operation_timeout())
+            .extended(WhoAmI)
+            .await
+            .and_then(|result| result.success());
+
+        if let Err(e) = &result {
+            tracing::debug!(error = ?e, "Pooled LDAP connection failed health check");
+        }
+        result.is_ok()
+    }
+
+    fn take_idle(&self) -> Option<IdleConnection> {
+        self.idle.lock().ok()?.pop()
+    }
+
+    fn put_idle(&self, ldap: Ldap) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push(IdleConnection { ldap, returned_at: Instant::now() });
+            LdapPoolMetrics::record_idle(idle.len());
+        }
+    }
+}
+
+/// A connection checked out of an [`LdapPool`]; it returns to the pool when dropped
+pub struct PooledConnection<'a> {
+    pool: &'a LdapPool,
+    ldap: Option<Ldap>,
+    broken: bool,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<'a> PooledConnection<'a> {
+    fn new(pool: &'a LdapPool, ldap: Ldap, permit: SemaphorePermit<'a>) -> Self {
+        LdapPoolMetrics::increment_in_use();
+        PooledConnection { pool, ldap: Some(ldap), broken: false, _permit: permit }
+    }
+
+    /// Closes the connection instead of returning it to the pool
+    pub fn mark_broken(&mut self) {
+        self.broken = true;
+    }
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Ldap;
+
+    fn deref(&self) -> &Ldap {
+        self.ldap.as_ref().expect("pooled LDAP connection is present until dropped")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Ldap {
+        self.ldap.as_mut().expect("pooled LDAP connection is present until dropped")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        LdapPoolMetrics::decrement_in_use();
+
+        match self.ldap.take() {
+            Some(ldap) if !self.broken => self.pool.put_idle(ldap),
+            _ => LdapPoolMetrics::record_discarded("broken"),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Directory sync for the LDAP backend
+//!
+//! Pages through every entry matching `LDAP_FILTER`, creates or updates the
+//! matching `users` rows and deactivates the users that are no longer in the
+//! directory. Runs on a schedule with [`spawn_sync_task`] and on demand with
+//! the `sync_directory` binary.
+
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::web;
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::{Ldap, SearchEntry};
+use tracing::Instrument;
+use crate::DbPool;
+use crate::models::users::usecases::{deactivate_missing_users, sync_directory_user};
+use super::{first_attr, profile_from_entry, BindMode, LdapBackend};
+use super::super::DirectoryProfile;
+
+/// Counts of what a directory sync did
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// Entries returned by the directory
+    pub seen: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub reactivated: usize,
+    pub deactivated: usize,
+    /// Entries that could not be stored, e.g. because of invalid attributes
+    pub failed: usize,
+}
+
+impl LdapBackend {
+    /// Returns the base DN, scope and filter matching every user that may log in
+    pub fn sync_search(&self) -> (&str, ldap3::Scope, String) {
+        let filter = format!("(&({}=*){})", self.uid_column, self.filter);
+        match self.bind_mode {
+            BindMode::Direct => (&self.user_dn, ldap3::Scope::OneLevel, filter),
+            BindMode::Search => (&self.search_base, ldap3::Scope::Subtree, filter),
+        }
+    }
+
+    /// Pages through the directory as the service account and returns the profile of every user
+    async fn fetch_profiles(&self, ldap: &mut Ldap, page_size: i32) -> Result<Vec<DirectoryProfile>, String> {
+        let (base, scope, filter) = self.sync_search();
+        let attrs = self.profile_attrs();
+
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(page_size)),
+        ];
+        let mut search = ldap.with_timeout(self.connector.operation_timeout())
+            .streaming_search_with(adapters, base, scope, &filter, attrs)
+            .await
+            .map_err(|e| format!("LDAP sync search failed: {}", e))?;
+
+        let mut profiles = Vec::new();
+        while let Some(entry) = search.next().await.map_err(|e| format!("LDAP sync search failed: {}", e))? {
+            let entry = SearchEntry::construct(entry);
+            match first_attr(&entry, &self.uid_column) {
+                Some(uid) => profiles.push(profile_from_entry(&uid, entry, None)),
+                None => tracing::warn!(dn = %entry.dn, "Skipping directory entry without {}", self.uid_column),
+            }
+        }
+        search.finish().await
+            .success()
+            .map_err(|e| format!("LDAP sync search failed: {}", e))?;
+
+        Ok(profiles)
+    }
+
+    /// Syncs the `users` table with the directory.
+    ///
+    /// Users are only deactivated after a complete search; an empty result is
+    /// treated as a misconfiguration and deactivates nobody.
+    pub async fn sync_directory(&self, db_pool: DbPool, page_size: i32) -> Result<SyncReport, String> {
+        let pool = self.pool.as_ref()
+            .ok_or_else(|| "LDAP_BIND_DN and LDAP_BIND_PASSWORD are required for the directory sync".to_string())?;
+
+        let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+        let result = self.fetch_profiles(&mut conn, page_size)
+            .instrument(tracing::info_span!("ldap_directory_sync"))
+            .await;
+        if result.is_err() {
+            conn.mark_broken();
+        }
+        drop(conn);
+        let profiles = result?;
+
+        web::block(move || store_profiles(&db_pool, profiles))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+}
+
+/// Upserts the profiles and deactivates the users missing from them
+pub fn store_profiles(db_pool: &DbPool, profiles: Vec<DirectoryProfile>) -> Result<SyncReport, String> {
+    let mut conn = db_pool.get().map_err(|e| e.to_string())?;
+    let mut report = SyncReport { seen: profiles.len(), ..SyncReport::default() };
+    let mut present = Vec::with_capacity(profiles.len());
+
+    for profile in profiles {
+        // Users that fail to sync stay active rather than being deactivated by mistake
+        present.push(profile.login_id.clone());
+        match sync_directory_user(
+            &mut conn,
+            profile.login_id.clone(),
+            profile.employee_number,
+            profile.first_name,
+            profile.last_name,
+            profile.email,
+            profile.gecos,
+        ) {
+            Ok(upsert) => {
+                if upsert.created {
+                    report.created += 1;
+                } else if !upsert.changed.is_empty() {
+                    report.updated += 1;
+                }
+                if upsert.reactivated {
+                    report.reactivated += 1;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, login_id = %profile.login_id, "Failed to sync user from directory");
+                report.failed += 1;
+            }
+        }
+    }
+
+    if present.is_empty() {
+        tracing::warn!("Directory sync found no users; skipping deactivation");
+        return Ok(report);
+    }
+
+    let deactivated = deactivate_missing_users(&mut conn, &present).map_err(|e| e.to_string())?;
+    for login_id in &deactivated {
+        tracing::info!(login_id = %login_id, "User deactivated: no longer in the directory");
+    }
+    report.deactivated = deactivated.len();
+
+    Ok(report)
+}
+
+/// Runs [`LdapBackend::sync_directory`] every `period` on the current runtime
+pub fn spawn_sync_task(backend: Arc<LdapBackend>, db_pool: DbPool, period: Duration, page_size: i32) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(period);
+        loop {
+            interval.tick().await;
+
+            match backend.sync_directory(db_pool.clone(), page_size).await {
+                Ok(report) => tracing::info!(report = ?report, "Directory sync finished"),
+                Err(e) => tracing::error!(error = %e, "Directory sync failed"),
+            }
+        }
+    });
+}
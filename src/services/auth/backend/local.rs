@@ -0,0 +1,88 @@
+use actix_web::web;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use futures_util::future::LocalBoxFuture;
+use crate::{DbPool, models::users::usecases::find_local_credential};
+use super::{AuthBackend, AuthError, DirectoryProfile};
+
+/// Authenticates users against Argon2 password hashes in `local_credentials`.
+///
+/// Intended for development machines without a directory server. Passwords are
+/// set with the `set_local_password` binary.
+#[derive(Clone)]
+pub struct LocalBackend {
+    pool: DbPool,
+}
+
+impl LocalBackend {
+    pub fn new(pool: DbPool) -> Self {
+        LocalBackend { pool }
+    }
+}
+
+impl AuthBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        username: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<DirectoryProfile, AuthError>> {
+        let pool = self.pool.clone();
+        let username = username.to_string();
+        let password = password.to_string();
+
+        Box::pin(async move {
+            // Hash verification is CPU bound, so it runs on the blocking pool with the query
+            web::block(move || -> Result<DirectoryProfile, AuthError> {
+                let mut conn = pool.get()
+                    .map_err(|e| AuthError::Unavailable { message: format!("Failed to get database connection: {}", e) })?;
+
+                let (user, password_hash) = find_local_credential(&mut conn, &username)
+                    .map_err(|e| AuthError::Unavailable { message: format!("Failed to load local credential: {}", e) })?
+                    .ok_or(AuthError::InvalidCredentials)?;
+
+                if !verify_password(&password, &password_hash)? {
+                    return Err(AuthError::InvalidCredentials);
+                }
+                if user.deactivated_at.is_some() {
+                    return Err(AuthError::Forbidden { reason: "account deactivated".to_string() });
+                }
+
+                Ok(DirectoryProfile {
+                    login_id: user.login_id,
+                    employee_number: user.employee_number,
+                    first_name: user.first_name,
+                    last_name: user.last_name,
+                    email: user.email,
+                    gecos: user.gecos,
+                    roles: None,
+                })
+            })
+            .await
+            .map_err(|e| AuthError::Unavailable { message: format!("Blocking task failed: {}", e) })?
+        })
+    }
+}
+
+/// Hashes a password with Argon2id and a random salt
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> Result<bool, AuthError> {
+    let parsed = PasswordHash::new(password_hash).map_err(|e| {
+        tracing::error!(error = ?e, "Stored password hash is malformed");
+        AuthError::Unavailable { message: format!("Malformed password hash: {}", e) }
+    })?;
+
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
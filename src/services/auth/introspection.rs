@@ -0,0 +1,145 @@
+//! Token introspection (RFC 7662)
+//!
+//! `POST /auth/introspect` lets other services check an access token or API
+//! token they were given: whether it is still active (signed, not expired,
+//! not revoked, its user not deactivated) and what its claims are. Callers
+//! authenticate with a bearer token of their own, usually an API token, that
+//! grants `tokens:introspect`, so that the endpoint cannot be used to probe
+//! for tokens by anyone who merely holds a login.
+
+use actix_web::{post, web, HttpResponse, Responder, error};
+use actix_web_httpauth::middleware::HttpAuthentication;
+use diesel::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{DbPool, config, constants, errors::ServiceError, rbac};
+use crate::middleware::{validate_bearer_token, validator, Actor, ValidatedToken};
+use crate::rbac::{Authorized, TokensIntrospect};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(introspect);
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct IntrospectionRequest {
+    /// Access token or API token to check
+    pub token: String,
+    /// Ignored; both kinds of token are recognised by their format
+    pub token_type_hint: Option<String>,
+}
+
+/// What a token says about its user; only `active` is set for inactive tokens
+#[derive(Deserialize, Serialize, ToSchema, Debug, Default, PartialEq)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    /// Permissions the token grants, separated by spaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<i64>,
+    /// ID of the user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+    /// Admin acting as the user, for impersonation tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::AUTH,
+    context_path = "/auth",
+    request_body(content = IntrospectionRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "State and claims of the token", body = IntrospectionResponse),
+        (status = UNAUTHORIZED, description = "The caller's own bearer token is missing or invalid"),
+        (status = FORBIDDEN, description = "requires tokens:introspect"),
+        (status = SERVICE_UNAVAILABLE, description = "The revocation state of a token could not be checked"),
+        (status = INTERNAL_SERVER_ERROR, description = "Introspection Failed")
+    ),
+    security(
+        ("BearerAuth" = ["tokens:introspect"])
+    )
+)]
+#[post("/introspect", wrap = "HttpAuthentication::bearer(validator)")]
+#[tracing::instrument(skip(auth, pool, form), fields(auth.user_id = %auth.claims.id))]
+pub async fn introspect(
+    auth: Authorized<TokensIntrospect>,
+    pool: web::Data<DbPool>,
+    form: web::Form<IntrospectionRequest>,
+) -> actix_web::Result<impl Responder> {
+    use crate::models::users::usecases::find_user;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    let Some(token) = validate_bearer_token(Some(pool.clone()), &form.token).await? else {
+        return Ok(HttpResponse::Ok().json(IntrospectionResponse::default()));
+    };
+
+    // Access tokens stay valid until they expire, so deactivated users are checked here
+    let claims = token.claims().clone();
+    let user_ids: Vec<i32> = std::iter::once(claims.id).chain(claims.act.as_ref().map(|actor| actor.id)).collect();
+    let active = web::block(move || -> Result<bool, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        for user_id in user_ids {
+            let user = find_user(&mut conn, user_id)
+                .optional()
+                .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })?;
+            if user.is_none_or(|user| user.deactivated_at.is_some()) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    })
+    .await??;
+    if !active {
+        return Ok(HttpResponse::Ok().json(IntrospectionResponse::default()));
+    }
+
+    let scopes = match &token {
+        ValidatedToken::Api(api_token) => Some(api_token.scopes.as_slice()),
+        ValidatedToken::Access(_) => None,
+    };
+    let scope = rbac::granted_permissions(&config, &claims, scopes).join(" ");
+    tracing::debug!(subject_id = %claims.id, "Token introspected");
+
+    Ok(HttpResponse::Ok().json(IntrospectionResponse {
+        active: true,
+        scope: Some(scope),
+        username: Some(claims.username),
+        token_type: Some("Bearer".to_string()),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        nbf: Some(claims.nbf),
+        sub: Some(claims.id.to_string()),
+        aud: Some(claims.aud),
+        iss: Some(claims.iss),
+        jti: Some(claims.jti),
+        roles: Some(claims.roles),
+        act: claims.act,
+    }))
+}
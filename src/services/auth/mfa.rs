@@ -0,0 +1,227 @@
+//! Second step of a login for users with two-factor authentication
+//!
+//! A login that needs a second factor answers 202 with a challenge token
+//! instead of tokens. `POST /auth/login/mfa` exchanges the challenge token and
+//! a TOTP or recovery code for the tokens. Users whose roles require MFA but
+//! who have not enrolled yet start their enrolment with the challenge token at
+//! `POST /auth/login/mfa/enroll`; the first code then enables it.
+
+use actix_web::{post, web, HttpRequest, HttpResponse, error};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{DbPool, config, constants, errors::ServiceError, metrics::AuthMetrics};
+use crate::lockout::{self, LockoutPolicy};
+use crate::mfa::{self, MfaPolicy};
+use crate::models::mfa::usecases::MfaEnrollment;
+use crate::models::users::User;
+use crate::rate_limit::RateLimit;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(verify)
+        .service(enroll);
+}
+
+/// Answer to a login that needs a second factor
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct MfaChallengeResponse {
+    /// Token for `POST /auth/login/mfa`
+    pub mfa_token: String,
+    /// Lifetime of the challenge token in seconds
+    pub expires_in: i64,
+    /// The user's roles require MFA but they have not enrolled; start with `POST /auth/login/mfa/enroll`
+    pub enrollment_required: bool,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    /// 6-digit code from the authenticator app, or a recovery code
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Debug)]
+pub struct MfaEnrollRequest {
+    pub mfa_token: String,
+}
+
+/// What `POST /auth/login/mfa` found
+enum Verification {
+    /// The challenge token is invalid, or the enrolment has not started
+    Rejected,
+    /// The code is wrong; it counts towards the lockout of the user's login id
+    InvalidCode(String),
+    /// The account is locked, until the given time
+    Locked(chrono::NaiveDateTime),
+    Verified(User, Vec<String>),
+}
+
+fn database_error(e: diesel::result::Error) -> ServiceError {
+    ServiceError::DatabaseError { message: e.to_string() }
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::AUTH,
+    context_path = "/auth",
+    request_body = MfaLoginRequest,
+    responses(
+        (status = 200, description = "Login User", body = super::TokenResponse, headers(
+            ("authorization" = String, description = "Bearer access token, the same as access_token in the body")
+        )),
+        (status = NO_CONTENT, description = "Logged in with SESSION_AUTH_ENABLED: the tokens are kept in the session cookie"),
+        (status = UNAUTHORIZED, description = "The challenge token is invalid, expired or used up, or the code is wrong"),
+        (status = FORBIDDEN, description = "The account was deactivated after the password step"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded, or the account is locked after repeated failed logins (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "Login User Failed")
+    )
+)]
+// Requirements: 11.2 - Rate limiting for login endpoint to prevent brute force attacks
+#[post("/login/mfa", wrap = "RateLimit::login()")]
+#[tracing::instrument(skip(pool, req, body), fields(auth.user_id = tracing::field::Empty))]
+pub async fn verify(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    body: web::Json<MfaLoginRequest>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::models::login_failures::usecases::find_login_lock;
+    use crate::models::mfa::usecases::{complete_mfa_challenge, enable_mfa, find_mfa_challenge, find_user_mfa, record_mfa_challenge_failure};
+    use crate::models::roles::usecases::find_user_roles;
+    use crate::models::users::usecases::find_user;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+    let policy = MfaPolicy::from_config(&config);
+    let lockout_policy = LockoutPolicy::from_config(&config);
+    let body = body.into_inner();
+
+    let cloned_pool = pool.clone();
+    let verification = web::block(move || -> Result<Verification, ServiceError> {
+        let mut conn = cloned_pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(challenge) = find_mfa_challenge(&mut conn, &body.mfa_token, policy.max_attempts).map_err(database_error)? else {
+            tracing::warn!("MFA login rejected: invalid challenge token");
+            return Ok(Verification::Rejected);
+        };
+        tracing::Span::current().record("auth.user_id", challenge.user_id);
+        let Some(user_mfa) = find_user_mfa(&mut conn, challenge.user_id).map_err(database_error)? else {
+            tracing::warn!(user_id = %challenge.user_id, "MFA login rejected: enrolment not started");
+            return Ok(Verification::Rejected);
+        };
+
+        let user = find_user(&mut conn, challenge.user_id).map_err(database_error)?;
+        if user.deactivated_at.is_some() {
+            tracing::warn!(user_id = %user.id, "MFA login rejected: user is deactivated");
+            return Err(ServiceError::Forbidden { message: "Account has been deactivated".to_string() });
+        }
+
+        // Challenges issued before the account was locked do not outlast the lock
+        if lockout_policy.is_enabled()
+            && let Some(locked_until) = find_login_lock(&mut conn, &lockout::normalize_username(&user.login_id)).map_err(database_error)?
+        {
+            tracing::warn!(user_id = %user.id, locked_until = %locked_until, "MFA login rejected: account locked");
+            return Ok(Verification::Locked(locked_until));
+        }
+
+        if !mfa::verify_code(&mut conn, &user_mfa, &body.code).map_err(database_error)? {
+            tracing::warn!(user_id = %challenge.user_id, attempts = challenge.attempts + 1, "MFA login rejected: invalid code");
+            record_mfa_challenge_failure(&mut conn, &body.mfa_token).map_err(database_error)?;
+            return Ok(Verification::InvalidCode(user.login_id));
+        }
+        // A challenge completes one login, even if two requests raced with valid codes
+        if !complete_mfa_challenge(&mut conn, &body.mfa_token).map_err(database_error)? {
+            return Ok(Verification::Rejected);
+        }
+        if !user_mfa.is_enabled() && enable_mfa(&mut conn, user_mfa.user_id).map_err(database_error)? {
+            tracing::info!(user_id = %user_mfa.user_id, "MFA enabled during login");
+        }
+
+        let roles = find_user_roles(&mut conn, challenge.user_id).map_err(database_error)?;
+        Ok(Verification::Verified(user, roles))
+    })
+    .await??;
+
+    let (user, roles) = match verification {
+        Verification::Verified(user, roles) => (user, roles),
+        Verification::Locked(locked_until) => return Ok(super::locked_response(locked_until)),
+        Verification::InvalidCode(login_id) => {
+            // Guessing codes counts like guessing passwords
+            if lockout_policy.is_enabled() {
+                lockout::record_failure(pool.clone(), &login_id, lockout_policy).await?;
+            }
+
+            // Requirements: 12.5 - Authentication metrics collection
+            AuthMetrics::record_attempt(false);
+            return Ok(HttpResponse::Unauthorized().finish());
+        }
+        Verification::Rejected => {
+            // Requirements: 12.5 - Authentication metrics collection
+            AuthMetrics::record_attempt(false);
+            return Ok(HttpResponse::Unauthorized().finish());
+        }
+    };
+
+    tracing::info!(user_id = %user.id, username = %user.login_id, "MFA login successful");
+    super::issue_tokens(&config, pool, &req, &user, &roles).await
+}
+
+#[utoipa::path(
+    post,
+    tag = constants::tags::AUTH,
+    context_path = "/auth",
+    request_body = MfaEnrollRequest,
+    responses(
+        (status = 200, description = "Enrolment started. Confirm it by completing the login with POST /auth/login/mfa", body = MfaEnrollment),
+        (status = UNAUTHORIZED, description = "The challenge token is invalid, expired or used up"),
+        (status = CONFLICT, description = "MFA is already enabled"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "Enrolment Failed")
+    )
+)]
+#[post("/login/mfa/enroll", wrap = "RateLimit::login()")]
+#[tracing::instrument(skip(pool, body), fields(auth.user_id = tracing::field::Empty))]
+pub async fn enroll(
+    pool: web::Data<DbPool>,
+    body: web::Json<MfaEnrollRequest>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::models::mfa::usecases::{find_mfa_challenge, start_mfa_enrollment};
+    use crate::models::users::usecases::find_user;
+
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+    let policy = MfaPolicy::from_config(&config);
+
+    let enrollment = web::block(move || -> Result<Option<Option<MfaEnrollment>>, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        let Some(challenge) = find_mfa_challenge(&mut conn, &body.mfa_token, policy.max_attempts).map_err(database_error)? else {
+            return Ok(None);
+        };
+        tracing::Span::current().record("auth.user_id", challenge.user_id);
+        let user = find_user(&mut conn, challenge.user_id).map_err(database_error)?;
+        start_mfa_enrollment(&mut conn, user.id, &policy.issuer, &user.login_id)
+            .map(Some)
+            .map_err(database_error)
+    })
+    .await??;
+
+    match enrollment {
+        None => Ok(HttpResponse::Unauthorized().finish()),
+        Some(None) => Ok(HttpResponse::Conflict().finish()),
+        Some(Some(enrollment)) => {
+            tracing::info!("MFA enrolment started during login");
+            Ok(HttpResponse::Ok().json(enrollment))
+        }
+    }
+}
@@ -0,0 +1,721 @@
+//! OpenID Connect login with the authorization code flow and PKCE
+//!
+//! `GET /auth/oidc/login` stores a random `state`, a PKCE code verifier and a
+//! nonce in `oidc_logins` and redirects the browser to the provider, which
+//! sends it back to `GET /auth/oidc/callback` with a code. The code is
+//! exchanged for an ID token, whose signature is checked against the
+//! provider's JWKS. The identity, the issuer and the `sub` claim, logs in as
+//! the user it is linked to in `oidc_identities`, and the callback answers with
+//! the same tokens as `POST /login`.
+//!
+//! The first login of an identity creates a user from its claims. Existing
+//! users are never matched by a username claim: they link an identity with
+//! `POST /api/me/oidc/link`, whose login ends at the same callback.
+//!
+//! The provider's endpoints are read from its discovery document at
+//! `{OIDC_ISSUER_URL}/.well-known/openid-configuration`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{get, web, HttpRequest, HttpResponse, error, http::header};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use utoipa::IntoParams;
+use crate::{DbPool, config::{self, Config}, constants, metrics::AuthMetrics};
+use crate::lockout::{self, LockoutPolicy};
+use crate::models::refresh_tokens::usecases::generate_token;
+use crate::models::users::{User, SOURCE_OIDC};
+use crate::rate_limit::RateLimit;
+use super::backend::{AuthError, DirectoryProfile};
+
+/// Cookie tying the callback to the browser that started the login
+const STATE_COOKIE: &str = "oidc_state";
+
+/// How long the discovery document and the JWKS are cached
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Timeout of every request to the provider
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ID token signature algorithms; HMAC is not accepted since it would use the client secret as key
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/oidc")
+        .service(login)
+        .service(callback)
+    );
+}
+
+/// OIDC client registration and claim mapping
+#[derive(Clone, Debug)]
+pub struct OidcSettings {
+    /// Issuer URL without a trailing slash, which must match the `iss` of ID tokens
+    pub issuer: String,
+    pub client_id: String,
+    /// Sent with HTTP basic authentication; public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    /// URL of `/auth/oidc/callback` as registered with the provider
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub username_claim: String,
+    pub employee_number_claim: Option<String>,
+    pub groups_claim: String,
+    /// `(group, role)` pairs; roles are only managed by the provider if this is not empty
+    pub role_mapping: Vec<(String, String)>,
+    pub login_ttl_secs: i64,
+    pub leeway_secs: u64,
+}
+
+impl OidcSettings {
+    /// Returns None if `OIDC_ISSUER_URL` is not set
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let Some(issuer) = config.get_oidc_issuer_url() else {
+            return Ok(None);
+        };
+        let required = |value: &Option<String>, name: &str| value.clone()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| format!("{} is required when OIDC_ISSUER_URL is set", name));
+
+        let role_mapping = config.get_oidc_role_mapping()
+            .split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (group, role) = entry.rsplit_once(':')
+                    .map(|(group, role)| (group.trim(), role.trim()))
+                    .filter(|(group, role)| !group.is_empty() && !role.is_empty())
+                    .ok_or_else(|| format!("Invalid OIDC_ROLE_MAPPING entry: '{}'. Expected 'group:role'", entry))?;
+                if !crate::rbac::is_known_role(role) {
+                    return Err(format!("Unknown role in OIDC_ROLE_MAPPING: '{}'", role));
+                }
+                Ok((group.to_string(), role.to_string()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Some(OidcSettings {
+            issuer,
+            client_id: required(&config.oidc_client_id, "OIDC_CLIENT_ID")?,
+            client_secret: config.oidc_client_secret.clone().filter(|secret| !secret.is_empty()),
+            redirect_uri: required(&config.oidc_redirect_uri, "OIDC_REDIRECT_URI")?,
+            scopes: config.get_oidc_scopes(),
+            username_claim: config.get_oidc_username_claim(),
+            employee_number_claim: config.oidc_employee_number_claim.clone().filter(|claim| !claim.is_empty()),
+            groups_claim: config.get_oidc_groups_claim(),
+            role_mapping,
+            login_ttl_secs: config.get_oidc_login_ttl_secs(),
+            leeway_secs: config.get_jwt_leeway_secs(),
+        }))
+    }
+}
+
+/// The parts of the provider's discovery document used here
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+struct Cached<T> {
+    value: Arc<T>,
+    fetched_at: Instant,
+}
+
+impl<T> Cached<T> {
+    fn fresh(&self) -> Option<Arc<T>> {
+        (self.fetched_at.elapsed() < METADATA_TTL).then(|| self.value.clone())
+    }
+}
+
+/// Talks to the OIDC provider; registered as `web::Data<OidcClient>` when OIDC is configured
+pub struct OidcClient {
+    settings: OidcSettings,
+    http: reqwest::Client,
+    metadata: RwLock<Option<Cached<ProviderMetadata>>>,
+    jwks: RwLock<Option<Cached<JwkSet>>>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> Result<Self, String> {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Failed to create OIDC HTTP client: {}", e))?;
+
+        Ok(OidcClient { settings, http, metadata: RwLock::new(None), jwks: RwLock::new(None) })
+    }
+
+    /// Returns None if OIDC login is not configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        OidcSettings::from_config(config)?
+            .map(OidcClient::new)
+            .transpose()
+    }
+
+    pub fn settings(&self) -> &OidcSettings {
+        &self.settings
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AuthError> {
+        let unavailable = |e: reqwest::Error| AuthError::Unavailable { message: format!("OIDC request to {} failed: {}", url, e) };
+
+        self.http.get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(unavailable)?
+            .json::<T>()
+            .await
+            .map_err(unavailable)
+    }
+
+    /// Returns the discovery document, fetched at most once per hour
+    pub async fn metadata(&self) -> Result<Arc<ProviderMetadata>, AuthError> {
+        if let Some(metadata) = self.metadata.read().ok().and_then(|cached| cached.as_ref()?.fresh()) {
+            return Ok(metadata);
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.settings.issuer);
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.settings.issuer {
+            return Err(AuthError::Unavailable {
+                message: format!("OIDC discovery document is for issuer '{}', expected '{}'", metadata.issuer, self.settings.issuer),
+            });
+        }
+
+        let metadata = Arc::new(metadata);
+        if let Ok(mut cached) = self.metadata.write() {
+            *cached = Some(Cached { value: metadata.clone(), fetched_at: Instant::now() });
+        }
+        Ok(metadata)
+    }
+
+    /// Returns the provider's signing keys; `refresh` skips the cache, e.g. after a key rotation
+    async fn jwks(&self, refresh: bool) -> Result<Arc<JwkSet>, AuthError> {
+        if !refresh && let Some(jwks) = self.jwks.read().ok().and_then(|cached| cached.as_ref()?.fresh()) {
+            return Ok(jwks);
+        }
+
+        let metadata = self.metadata().await?;
+        let jwks = Arc::new(self.get_json::<JwkSet>(&metadata.jwks_uri).await?);
+        if let Ok(mut cached) = self.jwks.write() {
+            *cached = Some(Cached { value: jwks.clone(), fetched_at: Instant::now() });
+        }
+        Ok(jwks)
+    }
+
+    /// Returns the provider URL the browser is sent to
+    pub async fn authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> Result<String, AuthError> {
+        let metadata = self.metadata().await?;
+        let challenge = code_challenge(code_verifier);
+
+        reqwest::Url::parse_with_params(&metadata.authorization_endpoint, &[
+            ("response_type", "code"),
+            ("client_id", &self.settings.client_id),
+            ("redirect_uri", &self.settings.redirect_uri),
+            ("scope", &self.settings.scopes),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ])
+        .map(String::from)
+        .map_err(|e| AuthError::Unavailable { message: format!("Invalid OIDC authorization endpoint: {}", e) })
+    }
+
+    /// Exchanges an authorization code for an ID token
+    async fn exchange_code(&self, code: &str, code_verifier: &str) -> Result<String, AuthError> {
+        let metadata = self.metadata().await?;
+
+        let mut request = self.http.post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.settings.redirect_uri),
+                ("client_id", &self.settings.client_id),
+                ("code_verifier", code_verifier),
+            ]);
+        if let Some(secret) = &self.settings.client_secret {
+            request = request.basic_auth(&self.settings.client_id, Some(secret));
+        }
+
+        let response = request.send()
+            .await
+            .map_err(|e| AuthError::Unavailable { message: format!("OIDC token request failed: {}", e) })?;
+        let status = response.status();
+        if status.is_client_error() {
+            // e.g. invalid_grant for a reused code or a wrong code verifier
+            let body = response.text().await.unwrap_or_default();
+            tracing::warn!(status = %status, body = %body, "OIDC token request rejected");
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        response.error_for_status()
+            .map_err(|e| AuthError::Unavailable { message: format!("OIDC token request failed: {}", e) })?
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| AuthError::Unavailable { message: format!("Invalid OIDC token response: {}", e) })?
+            .id_token
+            .ok_or_else(|| AuthError::Unavailable { message: "OIDC token response has no id_token".to_string() })
+    }
+
+    /// Checks the signature and claims of an ID token and returns its claims
+    pub async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<HashMap<String, Value>, AuthError> {
+        let invalid = |reason: String| {
+            tracing::warn!(reason = %reason, "ID token rejected");
+            AuthError::InvalidCredentials
+        };
+
+        let header = decode_header(id_token).map_err(|e| invalid(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(format!("unsupported algorithm {:?}", header.alg)));
+        }
+
+        let jwk = match find_key(&*self.jwks(false).await?, header.kid.as_deref()) {
+            Some(jwk) => jwk,
+            // The provider may have rotated its keys since they were cached
+            None => find_key(&*self.jwks(true).await?, header.kid.as_deref())
+                .ok_or_else(|| invalid(format!("no key for kid {:?}", header.kid)))?,
+        };
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.settings.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = self.settings.leeway_secs;
+
+        let claims = decode::<HashMap<String, Value>>(id_token, &key, &validation)
+            .map_err(|e| invalid(e.to_string()))?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(invalid("nonce mismatch".to_string()));
+        }
+        // With several audiences the token must have been issued to this client
+        if let Some(azp) = claims.get("azp").and_then(Value::as_str)
+            && azp != self.settings.client_id
+        {
+            return Err(invalid(format!("issued to '{}'", azp)));
+        }
+
+        Ok(claims)
+    }
+
+    /// Maps ID token claims onto a profile, like a directory entry.
+    ///
+    /// The username claim only names users the login creates.
+    pub fn profile_from_claims(&self, claims: &HashMap<String, Value>) -> Result<DirectoryProfile, AuthError> {
+        let string = |name: &str| claims.get(name)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+
+        let login_id = string(&self.settings.username_claim).ok_or_else(|| AuthError::Forbidden {
+            reason: format!("ID token has no {} claim", self.settings.username_claim),
+        })?;
+
+        let employee_number = self.settings.employee_number_claim.as_ref()
+            .and_then(|name| claims.get(name))
+            .and_then(|value| match value {
+                Value::Number(number) => number.as_i64().and_then(|number| i32::try_from(number).ok()),
+                Value::String(number) => number.trim().parse().ok(),
+                _ => None,
+            });
+
+        // Addresses the provider has not verified could belong to someone else
+        let email = string("email").filter(|_| claims.get("email_verified") != Some(&Value::Bool(false)));
+
+        let roles = (!self.settings.role_mapping.is_empty()).then(|| {
+            let groups: Vec<&str> = match claims.get(&self.settings.groups_claim) {
+                Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+                Some(Value::String(group)) => vec![group.as_str()],
+                _ => Vec::new(),
+            };
+            let mut roles: Vec<String> = self.settings.role_mapping.iter()
+                .filter(|(group, _)| groups.contains(&group.as_str()))
+                .map(|(_, role)| role.clone())
+                .collect();
+            roles.sort();
+            roles.dedup();
+            roles
+        });
+
+        Ok(DirectoryProfile {
+            login_id,
+            employee_number,
+            first_name: string("given_name"),
+            last_name: string("family_name"),
+            email,
+            gecos: string("name"),
+            roles,
+        })
+    }
+
+    /// Completes a login: exchanges the code and returns the identity and profile from the verified ID token
+    pub async fn authenticate(&self, code: &str, code_verifier: &str, nonce: &str) -> Result<(OidcSubject, DirectoryProfile), AuthError> {
+        let id_token = self.exchange_code(code, code_verifier).await?;
+        let claims = self.verify_id_token(&id_token, nonce).await?;
+        let subject = claims.get("sub")
+            .and_then(Value::as_str)
+            .filter(|subject| !subject.is_empty())
+            .ok_or_else(|| AuthError::Forbidden { reason: "ID token has no sub claim".to_string() })?;
+        let subject = OidcSubject { issuer: self.settings.issuer.clone(), subject: subject.to_string() };
+        Ok((subject, self.profile_from_claims(&claims)?))
+    }
+}
+
+/// The identity an ID token was issued for
+#[derive(Clone, Debug, PartialEq)]
+pub struct OidcSubject {
+    pub issuer: String,
+    /// The `sub` claim, unique and never reassigned within the issuer
+    pub subject: String,
+}
+
+/// Returns the S256 PKCE code challenge of a code verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Returns the key with the given kid, or the only key if the token names none
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid).cloned(),
+        None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+        None => None,
+    }
+}
+
+pub(crate) fn oidc_client(req: &HttpRequest) -> actix_web::Result<web::Data<OidcClient>> {
+    req.app_data::<web::Data<OidcClient>>()
+        .cloned()
+        .ok_or_else(|| error::ErrorNotFound("OIDC login is not configured"))
+}
+
+fn state_cookie(state: String, config: &Config) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state)
+        .path(format!("{}/oidc", constants::AUTH_PREFIX))
+        .http_only(true)
+        .secure(config.is_cookie_secure())
+        // Lax, so that the cookie comes along on the provider's redirect back
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+pub(crate) fn auth_error_response(config: &Config, e: AuthError) -> actix_web::Result<HttpResponse> {
+    match e {
+        AuthError::InvalidCredentials => {
+            AuthMetrics::record_attempt(false);
+            Ok(HttpResponse::Unauthorized().finish())
+        }
+        AuthError::Forbidden { reason } => {
+            tracing::warn!(reason = %reason, "OIDC login denied");
+            Ok(HttpResponse::Forbidden().finish())
+        }
+        AuthError::Unavailable { message } => {
+            tracing::error!(error = %message, "OIDC provider error");
+            // Requirements: 11.2 - Hide detailed error information in production
+            Err(if config.is_production() {
+                error::ErrorInternalServerError("Authentication service unavailable")
+            } else {
+                error::ErrorInternalServerError(message)
+            })
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::AUTH,
+    context_path = "/auth/oidc",
+    responses(
+        (status = FOUND, description = "Redirect to the OIDC provider", headers(
+            ("location" = String, description = "Authorization endpoint of the provider"),
+            ("set-cookie" = String, description = "oidc_state cookie checked by the callback")
+        )),
+        (status = NOT_FOUND, description = "OIDC login is not configured"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "The provider is unavailable")
+    )
+)]
+#[get("/login", wrap = "RateLimit::login()")]
+#[tracing::instrument(skip(pool, req))]
+pub async fn login(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let client = oidc_client(&req)?;
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+
+    let (location, cookie) = match start_login(&client, &config, pool, None).await? {
+        Ok(started) => started,
+        Err(e) => return auth_error_response(&config, e),
+    };
+
+    tracing::debug!("Redirecting to the OIDC provider");
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .cookie(cookie)
+        .finish())
+}
+
+/// Stores a new login, linking the identity to `link_user_id` if set, and
+/// returns the provider's authorization URL with the state cookie
+pub(crate) async fn start_login(
+    client: &OidcClient,
+    config: &Config,
+    pool: web::Data<DbPool>,
+    link_user_id: Option<i32>,
+) -> actix_web::Result<Result<(String, Cookie<'static>), AuthError>> {
+    use crate::errors::ServiceError;
+    use crate::models::oidc_logins::usecases::insert_oidc_login;
+
+    let state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+    let location = match client.authorization_url(&state, &nonce, &code_verifier).await {
+        Ok(location) => location,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let ttl_secs = client.settings().login_ttl_secs;
+    let cloned_state = state.clone();
+    web::block(move || -> Result<(), ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        insert_oidc_login(&mut conn, &cloned_state, &code_verifier, &nonce, ttl_secs, link_user_id)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+
+    let mut cookie = state_cookie(state, config);
+    cookie.set_max_age(time::Duration::seconds(ttl_secs));
+    Ok(Ok((location, cookie)))
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by the provider instead of `code` if the login failed, e.g. `access_denied`
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    tag = constants::tags::AUTH,
+    context_path = "/auth/oidc",
+    params(CallbackQuery),
+    responses(
+        (status = 200, description = "Login User", body = super::TokenResponse, headers(
+            ("authorization" = String, description = "Bearer access token, the same as access_token in the body")
+        )),
+        (status = ACCEPTED, description = "A second factor is required; complete the login with POST /auth/login/mfa", body = super::mfa::MfaChallengeResponse),
+        (status = NO_CONTENT, description = "Logged in with SESSION_AUTH_ENABLED: the tokens are kept in the session cookie, or the identity was linked to the user who started the login"),
+        (status = BAD_REQUEST, description = "Unknown, expired or already used state, or the oidc_state cookie does not match"),
+        (status = UNAUTHORIZED, description = "The provider denied the login, or the code or ID token is invalid"),
+        (status = FORBIDDEN, description = "The ID token has no username claim, the account is deactivated, or a new identity's username belongs to another user"),
+        (status = NOT_FOUND, description = "OIDC login is not configured"),
+        (status = CONFLICT, description = "The identity to link is linked to another user"),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded, or the account is locked after repeated failed logins (see Retry-After)"),
+        (status = INTERNAL_SERVER_ERROR, description = "The provider is unavailable")
+    )
+)]
+#[get("/callback", wrap = "RateLimit::login()")]
+#[tracing::instrument(skip(pool, req, query), fields(auth.backend = "oidc", auth.user_id = tracing::field::Empty))]
+pub async fn callback(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    query: web::Query<CallbackQuery>,
+) -> actix_web::Result<HttpResponse> {
+    use crate::errors::ServiceError;
+    use crate::models::oidc_identities::usecases::find_oidc_user;
+    use crate::models::oidc_logins::usecases::take_oidc_login;
+
+    let client = oidc_client(&req)?;
+    let config = config::get_config().map_err(|e| {
+        tracing::error!(error = ?e, "Failed to get configuration");
+        error::ErrorInternalServerError(e)
+    })?;
+    let query = query.into_inner();
+
+    if let Some(error) = &query.error {
+        tracing::warn!(error = %error, description = ?query.error_description, "OIDC provider returned an error");
+        AuthMetrics::record_attempt(false);
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(error::ErrorBadRequest("code and state are required"));
+    };
+
+    // Without this check an attacker could complete their own login in the victim's browser
+    if req.cookie(STATE_COOKIE).is_none_or(|cookie| cookie.value() != state) {
+        tracing::warn!("OIDC callback rejected: state does not match the oidc_state cookie");
+        return Err(error::ErrorBadRequest("Invalid state"));
+    }
+
+    let cloned_pool = pool.clone();
+    let pending = web::block(move || -> Result<_, ServiceError> {
+        let mut conn = cloned_pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        take_oidc_login(&mut conn, &state)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+    let Some(pending) = pending else {
+        tracing::warn!("OIDC callback rejected: unknown, expired or already used state");
+        return Err(error::ErrorBadRequest("Invalid state"));
+    };
+
+    let (subject, profile) = match client.authenticate(&code, &pending.code_verifier, &pending.nonce).await {
+        Ok(authenticated) => authenticated,
+        Err(e) => return auth_error_response(&config, e),
+    };
+
+    if let Some(user_id) = pending.link_user_id {
+        let mut response = link_identity(pool, subject, user_id).await?;
+        response.add_removal_cookie(&state_cookie(String::new(), &config))?;
+        return Ok(response);
+    }
+
+    // Locked accounts are rejected like on `POST /login`
+    let lockout_policy = LockoutPolicy::from_config(&config);
+    if lockout_policy.is_enabled() {
+        let cloned_pool = pool.clone();
+        let cloned_subject = subject.clone();
+        let linked = web::block(move || -> Result<Option<User>, ServiceError> {
+            let mut conn = cloned_pool.get()
+                .map_err(|e| {
+                    tracing::error!(error = ?e, "Failed to get database connection");
+                    ServiceError::InternalServerError
+                })?;
+
+            find_oidc_user(&mut conn, &cloned_subject.issuer, &cloned_subject.subject)
+                .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+        })
+        .await??;
+
+        let username = linked.map_or_else(|| profile.login_id.clone(), |user| user.login_id);
+        if let Some(locked_until) = lockout::locked_until(pool.clone(), &username).await? {
+            tracing::warn!(username = %username, locked_until = %locked_until, "OIDC login rejected: account locked");
+            return Ok(super::locked_response(locked_until));
+        }
+    }
+
+    // Requirements: 12.5 - Authentication metrics collection
+    AuthMetrics::record_attempt(true);
+
+    let (user, roles) = provision_user(pool.clone(), subject, profile).await?;
+    tracing::Span::current().record("auth.user_id", user.id);
+    tracing::info!(user_id = %user.id, username = %user.login_id, "OIDC login successful");
+
+    let mut response = super::login_response(&config, pool, &req, &user, &roles).await?;
+    response.add_removal_cookie(&state_cookie(String::new(), &config))?;
+    Ok(response)
+}
+
+/// Logs in the user the identity is linked to, creating them on its first login, and returns the user's roles
+async fn provision_user(pool: web::Data<DbPool>, subject: OidcSubject, profile: DirectoryProfile) -> actix_web::Result<(User, Vec<String>)> {
+    use crate::errors::ServiceError;
+    use crate::models::users::usecases::upsert_oidc_user;
+
+    let cloned_pool = pool.clone();
+    let username = profile.login_id.clone();
+    let upsert = web::block(move || -> Result<_, ServiceError> {
+        let mut conn = cloned_pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        upsert_oidc_user(
+            &mut conn,
+            &subject.issuer,
+            &subject.subject,
+            profile.login_id,
+            profile.employee_number,
+            profile.first_name,
+            profile.last_name,
+            profile.email,
+            profile.gecos
+        )
+    })
+    .await?
+    .map_err(|e| match e {
+        ServiceError::Forbidden { .. } => error::Error::from(e),
+        e => {
+            tracing::error!(error = ?e, username = %username, "Failed to save user profile");
+            error::ErrorInternalServerError(format!("{:?}", e))
+        }
+    })?;
+
+    let user = upsert.user;
+    if upsert.created {
+        tracing::info!(user_id = %user.id, username = %username, "New user created successfully");
+    } else if !upsert.changed.is_empty() {
+        tracing::info!(user_id = %user.id, changed = ?upsert.changed, "User profile updated from OIDC claims");
+    }
+
+    // Users of the directory keep the roles the directory gives them
+    let roles = if user.source == SOURCE_OIDC { profile.roles } else { None };
+    let roles = super::sync_roles(pool, user.id, roles).await?;
+    Ok((user, roles))
+}
+
+/// Links the identity to the user who started the login, unless it is linked to another user
+async fn link_identity(pool: web::Data<DbPool>, subject: OidcSubject, user_id: i32) -> actix_web::Result<HttpResponse> {
+    use crate::errors::ServiceError;
+    use crate::models::oidc_identities::usecases::link_oidc_identity;
+
+    let linked_user_id = web::block(move || -> Result<i32, ServiceError> {
+        let mut conn = pool.get()
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get database connection");
+                ServiceError::InternalServerError
+            })?;
+
+        link_oidc_identity(&mut conn, &subject.issuer, &subject.subject, user_id)
+            .map_err(|e| ServiceError::DatabaseError { message: e.to_string() })
+    })
+    .await??;
+
+    tracing::Span::current().record("auth.user_id", user_id);
+    if linked_user_id != user_id {
+        tracing::warn!(user_id = %user_id, linked_user_id = %linked_user_id, "OIDC link rejected: identity is linked to another user");
+        return Ok(HttpResponse::Conflict().finish());
+    }
+    tracing::info!(user_id = %user_id, "OIDC identity linked");
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,156 @@
+//! Cookie-session authentication for browser clients
+//!
+//! With `SESSION_AUTH_ENABLED`, logins keep the access and refresh tokens in
+//! the encrypted session cookie instead of returning them, so that the SPA
+//! never holds a token in JavaScript. [`SessionAuth`] lets `/api` requests
+//! without an `Authorization` header use the session's access token, and
+//! `/auth/refresh` and `/auth/logout` fall back to the session the same way.
+//!
+//! Browsers send the cookie with every request, so requests with unsafe
+//! methods must prove they come from the SPA with a double-submit CSRF token:
+//! login sets it in the readable `csrf_token` cookie, and scripts send it back
+//! in the `X-CSRF-Token` header. It is compared with the copy in the session,
+//! which other sites can neither read nor forge.
+
+use std::future::{ready, Ready};
+use actix_session::{Session, SessionExt, SessionInsertError};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{error, Error, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use crate::config::{self, Config};
+use crate::models::refresh_tokens::usecases::generate_token;
+
+/// Readable cookie carrying the CSRF token to scripts
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+/// Header unsafe requests authenticated by the session must send the CSRF token in
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+const ACCESS_TOKEN_KEY: &str = "access_token";
+const REFRESH_TOKEN_KEY: &str = "refresh_token";
+const CSRF_TOKEN_KEY: &str = "csrf_token";
+
+/// Starts a new session for a user who just logged in, and returns its CSRF token
+pub fn sign_in(session: &Session, access_token: &str, refresh_token: &str) -> Result<String, SessionInsertError> {
+    // A new session key, so that a session planted before the login is not reused
+    session.renew();
+    let csrf_token = generate_token();
+    session.insert(CSRF_TOKEN_KEY, &csrf_token)?;
+    update_tokens(session, access_token, refresh_token)?;
+    Ok(csrf_token)
+}
+
+/// Replaces the tokens of the session after a refresh; the CSRF token stays the same
+pub fn update_tokens(session: &Session, access_token: &str, refresh_token: &str) -> Result<(), SessionInsertError> {
+    session.insert(ACCESS_TOKEN_KEY, access_token)?;
+    session.insert(REFRESH_TOKEN_KEY, refresh_token)
+}
+
+/// Ends the session; the browser is told to delete the cookie
+pub fn sign_out(session: &Session) {
+    session.purge();
+}
+
+/// Returns the access token of the session, if it has one
+pub fn access_token(session: &Session) -> Option<String> {
+    session.get(ACCESS_TOKEN_KEY).ok().flatten()
+}
+
+/// Returns the refresh token of the session, if it has one
+pub fn refresh_token(session: &Session) -> Option<String> {
+    session.get(REFRESH_TOKEN_KEY).ok().flatten()
+}
+
+/// Returns the cookie handing the CSRF token to scripts of the SPA
+pub fn csrf_cookie(config: &Config, csrf_token: String) -> Cookie<'static> {
+    Cookie::build(CSRF_COOKIE, csrf_token)
+        .path("/")
+        // Scripts must read it to send it back in the header
+        .http_only(false)
+        .secure(config.is_cookie_secure())
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+/// Rejects unsafe requests that do not send the session's CSRF token
+pub fn verify_csrf(req: &HttpRequest, session: &Session) -> Result<(), Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let expected = session.get::<String>(CSRF_TOKEN_KEY).ok().flatten();
+    let provided = req.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if expected == provided => Ok(()),
+        _ => {
+            tracing::warn!(method = %req.method(), path = %req.path(), "Rejected session request without a valid CSRF token");
+            Err(error::ErrorForbidden("Invalid CSRF token"))
+        }
+    }
+}
+
+/// Authenticates requests without an `Authorization` header with the session's access token.
+///
+/// Wrap it outside `HttpAuthentication::bearer`, which then checks the token
+/// as if it had been sent in the header. It does nothing unless
+/// `SESSION_AUTH_ENABLED` is set.
+pub struct SessionAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for SessionAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionAuthMiddleware { service }))
+    }
+}
+
+pub struct SessionAuthMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let enabled = config::get_config().is_ok_and(|config| config.is_session_auth_enabled());
+        // Clients sending a bearer token are not affected by the session
+        if !enabled || req.headers().contains_key(header::AUTHORIZATION) {
+            return Box::pin(self.service.call(req));
+        }
+
+        let session = req.get_session();
+        if let Some(access_token) = access_token(&session) {
+            if let Err(e) = verify_csrf(req.request(), &session) {
+                return Box::pin(async { Err(e) });
+            }
+            match header::HeaderValue::from_str(&format!("Bearer {}", access_token)) {
+                Ok(value) => {
+                    req.headers_mut().insert(header::AUTHORIZATION, value);
+                }
+                Err(e) => tracing::warn!(error = %e, "Ignoring session with a malformed access token"),
+            }
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
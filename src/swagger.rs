@@ -14,19 +14,58 @@ use crate::models::*;
 #[openapi(
     paths(
         api::users::index,
+        api::users::unlock,
         api::customers::categories,
         api::customers::insert_category,
         api::customers::update_category,
         api::customers::get_category,
         api::customers::delete_category,
-        auth::login
+        api::tokens::index,
+        api::tokens::create,
+        api::tokens::revoke,
+        api::mfa::status,
+        api::mfa::enroll,
+        api::mfa::confirm,
+        api::mfa::regenerate_recovery_codes,
+        api::mfa::disable,
+        api::admin::impersonate,
+        api::me::show,
+        api::me::link_oidc,
+        auth::login,
+        auth::refresh,
+        auth::logout,
+        auth::jwks,
+        auth::introspection::introspect,
+        auth::mfa::verify,
+        auth::mfa::enroll,
+        auth::oidc::login,
+        auth::oidc::callback
     ),
     components(schemas(
         users::usecases::NewUser,
         users::User,
         customers::usecases::NewCategoryBody,
         customers::CustomerCategory,
+        api_tokens::ApiToken,
+        api_tokens::usecases::NewApiTokenBody,
+        api_tokens::usecases::CreatedApiToken,
+        mfa::usecases::MfaEnrollment,
+        mfa::usecases::RecoveryCodes,
         auth::LoginInfo,
+        auth::RefreshRequest,
+        auth::TokenResponse,
+        auth::LogoutRequest,
+        auth::mfa::MfaChallengeResponse,
+        auth::mfa::MfaLoginRequest,
+        auth::mfa::MfaEnrollRequest,
+        api::mfa::MfaStatus,
+        api::mfa::MfaCodeBody,
+        api::admin::ImpersonationResponse,
+        api::me::CurrentUserResponse,
+        api::me::OidcLinkResponse,
+        auth::introspection::IntrospectionRequest,
+        auth::introspection::IntrospectionResponse,
+        crate::middleware::Actor,
     ))
 )]
 struct ApiDoc;
@@ -39,6 +78,7 @@ impl Modify for SecurityAddonn {
             security::HttpBuilder::new()
                 .scheme(security::HttpAuthScheme::Bearer)
                 .bearer_format("JWT")
+                .description(Some("Access token or API token (`pat_...`). The listed scopes are the permissions the operation requires, granted through the roles in the token's `roles` claim, and for API tokens also through the token's scopes."))
                 .build()
         );
 
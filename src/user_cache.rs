@@ -0,0 +1,240 @@
+//! Cache of the users requests act as
+//!
+//! `ReqDataCreator` resolves the user of every `/api` request. Users found in
+//! the database are cached by id for `USER_CACHE_TTL_SECS`, up to
+//! `USER_CACHE_MAX_ENTRIES` users, and entries are dropped whenever this
+//! instance updates the user. Updates made on another
+//! instance, such as a directory sync deactivating the user, go unnoticed for
+//! at most the TTL. Users that are not found are not cached.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::RwLock;
+use chrono::Utc;
+use diesel::OptionalExtension;
+use lazy_static::lazy_static;
+use crate::DbConnection;
+use crate::config::Config;
+use crate::models::users::User;
+use crate::models::users::usecases::{find_user, search_user};
+
+lazy_static! {
+    static ref CACHE: RwLock<Cache> = RwLock::new(Cache::default());
+}
+
+/// How long users are cached, and how many at most
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UserCachePolicy {
+    pub ttl_secs: i64,
+    pub max_entries: usize,
+}
+
+impl UserCachePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        UserCachePolicy {
+            ttl_secs: config.get_user_cache_ttl_secs(),
+            max_entries: config.get_user_cache_max_entries(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ttl_secs > 0 && self.max_entries > 0
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    user: User,
+    /// Unix timestamp after which the entry must be looked up again
+    valid_until: i64,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<i32, CacheEntry>,
+    /// `(valid_until, user id)` in the order users were cached, oldest first.
+    /// Records of entries dropped or cached again since are skipped.
+    order: VecDeque<(i64, i32)>,
+}
+
+impl Cache {
+    fn insert(&mut self, user: &User, policy: &UserCachePolicy, now: i64) {
+        // Expired entries are at the front, so they go a few at a time instead of in a sweep
+        while self.order.front().is_some_and(|(valid_until, _)| *valid_until <= now) {
+            self.pop_oldest();
+        }
+        while self.entries.len() >= policy.max_entries && !self.entries.contains_key(&user.id) {
+            if !self.pop_oldest() {
+                break;
+            }
+        }
+
+        let valid_until = now + policy.ttl_secs;
+        self.entries.insert(user.id, CacheEntry { user: user.clone(), valid_until });
+        self.order.push_back((valid_until, user.id));
+
+        // Skipped records pile up when users are invalidated or cached again; compacting
+        // only once they outnumber the cap keeps the cost of an insert constant on average
+        if self.order.len() > policy.max_entries.saturating_mul(2) {
+            let entries = &self.entries;
+            self.order.retain(|(valid_until, id)| entries.get(id).is_some_and(|entry| entry.valid_until == *valid_until));
+        }
+    }
+
+    /// Drops the oldest entry; false if there are no records left
+    fn pop_oldest(&mut self) -> bool {
+        let Some((valid_until, id)) = self.order.pop_front() else {
+            return false;
+        };
+        if self.entries.get(&id).is_some_and(|entry| entry.valid_until == valid_until) {
+            self.entries.remove(&id);
+        }
+        true
+    }
+}
+
+pub struct UserCache;
+
+impl UserCache {
+    /// Returns the cached user, if there is a fresh entry
+    pub fn cached(user_id: i32) -> Option<User> {
+        let now = Utc::now().timestamp();
+        CACHE.read()
+            .ok()?
+            .entries
+            .get(&user_id)
+            .filter(|entry| entry.valid_until > now)
+            .map(|entry| entry.user.clone())
+    }
+
+    /// Returns the cached user with the given id and login id, if there is a fresh entry
+    pub fn cached_login(user_id: i32, login_id: &str) -> Option<User> {
+        Self::cached(user_id).filter(|user| user.login_id == login_id)
+    }
+
+    /// Finds a user by id, consulting the cache before the database
+    pub fn find(conn: &mut DbConnection, user_id: i32, policy: &UserCachePolicy) -> diesel::QueryResult<Option<User>> {
+        if let Some(user) = Self::cached(user_id) {
+            return Ok(Some(user));
+        }
+
+        let user = find_user(conn, user_id).optional()?;
+        if let Some(user) = &user {
+            Self::remember(user, policy);
+        }
+        Ok(user)
+    }
+
+    /// Finds the user a token names, consulting the cache before the database.
+    ///
+    /// The login id decides, as tokens are issued for it; the id only keys the cache.
+    pub fn find_login(conn: &mut DbConnection, user_id: i32, login_id: &str, policy: &UserCachePolicy) -> diesel::QueryResult<Option<User>> {
+        if let Some(user) = Self::cached_login(user_id, login_id) {
+            return Ok(Some(user));
+        }
+
+        let user = search_user(conn, login_id)?.into_iter().next();
+        if let Some(user) = &user {
+            Self::remember(user, policy);
+        }
+        Ok(user)
+    }
+
+    /// Drops the entry of an updated user
+    pub fn invalidate(user_id: i32) {
+        if let Ok(mut cache) = CACHE.write() {
+            cache.entries.remove(&user_id);
+        }
+    }
+
+    /// Drops the entries of updated users known by their login ids
+    pub fn invalidate_login_ids(login_ids: &[String]) {
+        if login_ids.is_empty() {
+            return;
+        }
+        let login_ids: HashSet<&str> = login_ids.iter().map(String::as_str).collect();
+        if let Ok(mut cache) = CACHE.write() {
+            cache.entries.retain(|_, entry| !login_ids.contains(entry.user.login_id.as_str()));
+        }
+    }
+
+    fn remember(user: &User, policy: &UserCachePolicy) {
+        if !policy.is_enabled() {
+            return;
+        }
+        if let Ok(mut cache) = CACHE.write() {
+            cache.insert(user, policy, Utc::now().timestamp());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A user that is not in the database; negative ids keep clear of real ones
+    fn user(id: i32) -> User {
+        User {
+            id,
+            login_id: format!("cached_{}", -id),
+            employee_number: None,
+            first_name: None,
+            last_name: None,
+            email: None,
+            gecos: None,
+            last_login_at: None,
+            deactivated_at: None,
+            source: crate::models::users::SOURCE_DIRECTORY.to_string(),
+        }
+    }
+
+    #[test]
+    fn insert_keeps_the_cache_at_its_cap() {
+        let policy = UserCachePolicy { ttl_secs: 60, max_entries: 100 };
+        let mut cache = Cache::default();
+        for id in 1..=1_000 {
+            cache.insert(&user(-id), &policy, 0);
+            assert!(cache.entries.len() <= policy.max_entries);
+        }
+
+        // The users cached longest are dropped first
+        assert_eq!(cache.entries.len(), policy.max_entries);
+        assert!(cache.entries.contains_key(&-1_000));
+        assert!(!cache.entries.contains_key(&-900));
+        assert!(cache.order.len() <= policy.max_entries * 2);
+
+        // Caching a user again does not evict anyone
+        cache.insert(&user(-1_000), &policy, 1);
+        assert_eq!(cache.entries.len(), policy.max_entries);
+        assert!(cache.entries.contains_key(&-901));
+    }
+
+    #[test]
+    fn insert_drops_expired_entries() {
+        let policy = UserCachePolicy { ttl_secs: 60, max_entries: 100 };
+        let mut cache = Cache::default();
+        for id in 1..=10 {
+            cache.insert(&user(-id), &policy, 0);
+        }
+        cache.insert(&user(-1), &policy, 30);
+
+        cache.insert(&user(-11), &policy, 60);
+        assert_eq!(cache.entries.keys().copied().collect::<HashSet<_>>(), HashSet::from([-1, -11]));
+    }
+
+    #[test]
+    fn invalidate_drops_entries() {
+        let policy = UserCachePolicy { ttl_secs: 60, max_entries: 100 };
+        UserCache::remember(&user(-1), &policy);
+        UserCache::remember(&user(-2), &policy);
+        UserCache::remember(&user(-3), &policy);
+        assert_eq!(UserCache::cached(-1).map(|user| user.login_id), Some("cached_1".to_string()));
+
+        UserCache::invalidate(-1);
+        assert!(UserCache::cached(-1).is_none());
+
+        UserCache::invalidate_login_ids(&["cached_2".to_string()]);
+        assert!(UserCache::cached(-2).is_none());
+        assert!(UserCache::cached(-3).is_some());
+        UserCache::invalidate(-3);
+    }
+}
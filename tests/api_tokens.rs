@@ -0,0 +1,138 @@
+// Tests for API tokens and their use as bearer tokens
+mod tests {
+    use actix_web::{web, App, http::header};
+    use diesel::prelude::*;
+    use rust_api::models::customers::usecases::NewCategoryBody;
+    use rust_api::models::roles::usecases::set_user_roles;
+    use rust_api::models::users::User;
+    use rust_api::models::users::usecases::insert_new_user;
+    use rust_api::schema::users::dsl;
+    use serde_json::{json, Value};
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, chrono::Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn access_token(user: &User, roles: &[&str]) -> String {
+        let config = rust_api::config::get_config().unwrap();
+        rust_api::jwt::issue_access_token(
+            &config,
+            user.id,
+            &user.login_id,
+            &roles.iter().map(|role| role.to_string()).collect::<Vec<_>>(),
+        ).unwrap()
+    }
+
+    fn authorized(req: actix_web::test::TestRequest, token: &str) -> actix_web::test::TestRequest {
+        req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+    }
+
+    fn create_request(token: &str, body: Value) -> actix_web::test::TestRequest {
+        authorized(actix_web::test::TestRequest::post().uri("/api/tokens/"), token).set_json(body)
+    }
+
+    fn write_request(token: &str) -> actix_web::test::TestRequest {
+        authorized(actix_web::test::TestRequest::post().uri("/api/customers/categories"), token)
+            .set_json(NewCategoryBody { name: unique("api_token") })
+    }
+
+    #[actix_web::test]
+    async fn test_api_token_lifecycle() {
+        let pool = rust_api::create_test_connection_pool();
+        let user = {
+            let mut conn = pool.get().unwrap();
+            let user = insert_new_user(&mut conn, unique("tokenuser"), None, None, None, None, None).unwrap();
+            set_user_roles(&mut conn, user.id, &["manager".to_string()]).unwrap();
+            user
+        };
+        let jwt = access_token(&user, &["manager"]);
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        // Scopes must be known and granted by the user's roles
+        let resp = actix_web::test::call_service(&app, create_request(&jwt, json!({ "name": "batch", "scopes": ["customers:read", "tokens:manage"], "expires_in_days": 30 })).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        let resp = actix_web::test::call_service(&app, create_request(&jwt, json!({ "name": "batch", "scopes": ["users:unlock"], "expires_in_days": 30 })).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = actix_web::test::call_service(&app, create_request(&jwt, json!({ "name": "batch", "scopes": ["customers:read"], "expires_in_days": 100000 })).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let resp = actix_web::test::call_service(&app, create_request(&jwt, json!({ "name": "batch", "scopes": ["customers:read"], "expires_in_days": 30 })).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let created: Value = actix_web::test::read_body_json(resp).await;
+        let token = created["token"].as_str().unwrap().to_string();
+        let token_id = created["id"].as_i64().unwrap();
+        assert!(token.starts_with("pat_"));
+        assert!(created.get("token_hash").is_none());
+
+        // The token grants its scopes only, even though the user may write
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/customers/categories"), &token);
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, write_request(&token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = actix_web::test::call_service(&app, write_request(&jwt).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // Tokens cannot manage tokens
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/tokens/"), &token);
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/tokens/"), &jwt);
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let tokens: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(tokens.as_array().unwrap().len(), 1);
+        assert_eq!(tokens[0]["id"].as_i64().unwrap(), token_id);
+        assert!(tokens[0]["last_used_at"].is_string());
+        assert!(tokens[0].get("token").is_none());
+
+        let revoke = || authorized(actix_web::test::TestRequest::delete().uri(&format!("/api/tokens/{}", token_id)), &jwt);
+        let resp = actix_web::test::call_service(&app, revoke().to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let resp = actix_web::test::call_service(&app, revoke().to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/customers/categories"), &token);
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_api_token_of_deactivated_user_is_rejected() {
+        use rust_api::models::api_tokens::usecases::{create_api_token, NewApiTokenBody};
+
+        let pool = rust_api::create_test_connection_pool();
+        let token = {
+            let mut conn = pool.get().unwrap();
+            let user = insert_new_user(&mut conn, unique("tokengone"), None, None, None, None, None).unwrap();
+            let body = NewApiTokenBody { name: "batch".to_string(), scopes: vec!["customers:read".to_string()], expires_in_days: 1 };
+            let created = create_api_token(&mut conn, user.id, &body, 365).unwrap();
+            diesel::update(dsl::users.find(user.id))
+                .set(dsl::deactivated_at.eq(diesel::dsl::now))
+                .execute(&mut conn)
+                .unwrap();
+            created.token
+        };
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/customers/categories"), &token);
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+
+        // Unknown tokens are rejected the same way
+        let req = authorized(actix_web::test::TestRequest::get().uri("/api/customers/categories"), "pat_unknown");
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+}
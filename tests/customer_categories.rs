@@ -3,17 +3,15 @@ mod tests {
     use rust_api::models::customers::usecases::NewCategoryBody;
     use actix_web::{test, web, App, http::header};
     use actix_web_httpauth::middleware::HttpAuthentication;
-    use rust_api::middleware::{validator, UserClaims};
+    use rust_api::middleware::validator;
     use jsonwebtoken::{encode, Header, EncodingKey};
 
     fn create_valid_token() -> String {
-        let claims = UserClaims{
-            id: 1,
-            username: "testuser".into(),
-            exp: (chrono::Utc::now() + chrono::Duration::days(7)).timestamp()
-        };
+        let config = rust_api::config::get_config().unwrap();
+        let mut claims = rust_api::jwt::new_claims(&config, 1, "testuser");
+        claims.roles = vec!["manager".to_string()];
 
-        let secret = rust_api::config::get_config().unwrap().jwt_secret;
+        let secret = config.jwt_secret;
         let secret = secret.split(" ").map(|hex_str| u8::from_str_radix(hex_str, 16).unwrap()).collect::<Vec<u8>>();
         encode(&Header::default(), &claims, &EncodingKey::from_secret(&secret)).expect("Error creating JWT token")
     }
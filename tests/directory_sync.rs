@@ -0,0 +1,151 @@
+// Tests for the directory sync and for rejecting deactivated users
+mod tests {
+    use actix_web::{web, App, HttpResponse, Responder, http::header};
+    use diesel::prelude::*;
+    use rust_api::models::users::usecases::{deactivate_missing_users, search_user, sync_directory_user};
+    use rust_api::schema::users::dsl;
+    use rust_api::services::auth::backend::ldap::sync::store_profiles;
+
+    async fn dummy() -> impl Responder {
+        HttpResponse::Ok().body("Hey there!")
+    }
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, chrono::Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    #[test]
+    fn test_sync_updates_and_reactivates_users() {
+        let pool = rust_api::create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+        let username = unique("syncuser");
+
+        let upsert = sync_directory_user(&mut conn, username.clone(), Some(2001), None, None, Some("sync@example.com".to_string()), None).unwrap();
+        assert!(upsert.created);
+        assert!(upsert.user.last_login_at.is_none(), "a sync is not a login");
+
+        let upsert = sync_directory_user(&mut conn, username.clone(), Some(2002), None, None, Some("sync@example.com".to_string()), None).unwrap();
+        assert!(!upsert.created);
+        assert!(!upsert.reactivated);
+        assert_eq!(upsert.changed, vec!["employee_number"]);
+
+        diesel::update(dsl::users.find(upsert.user.id))
+            .set(dsl::deactivated_at.eq(diesel::dsl::now))
+            .execute(&mut conn)
+            .unwrap();
+
+        let upsert = sync_directory_user(&mut conn, username, Some(2002), None, None, Some("sync@example.com".to_string()), None).unwrap();
+        assert!(upsert.reactivated);
+        assert!(upsert.user.deactivated_at.is_none());
+
+        // Invalid directory attributes are rejected like on login
+        assert!(sync_directory_user(&mut conn, unique("syncuser"), None, None, None, Some("not-an-email".to_string()), None).is_err());
+    }
+
+    #[test]
+    fn test_deactivate_missing_users() {
+        let pool = rust_api::create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+        let present = unique("present");
+        let gone = unique("gone");
+
+        // Rolled back, so the other tests' users stay active
+        conn.test_transaction::<_, diesel::result::Error, _>(|conn| {
+            for login_id in [&present, &gone] {
+                sync_directory_user(conn, login_id.clone(), None, None, None, None, None).unwrap();
+            }
+
+            let deactivated = deactivate_missing_users(conn, std::slice::from_ref(&present))?;
+            assert!(deactivated.contains(&gone));
+            assert!(!deactivated.contains(&present));
+
+            let user = search_user(conn, &gone)?.remove(0);
+            assert!(user.deactivated_at.is_some());
+
+            // Already deactivated users are not reported again
+            let deactivated = deactivate_missing_users(conn, std::slice::from_ref(&present))?;
+            assert!(!deactivated.contains(&gone));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_store_profiles_without_entries_deactivates_nobody() {
+        let pool = rust_api::create_test_connection_pool();
+        let report = store_profiles(&pool, Vec::new()).unwrap();
+        assert_eq!(report.seen, 0);
+        assert_eq!(report.deactivated, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_req_data_creator_rejects_deactivated_user() {
+        use rust_api::middleware::ReqDataCreator;
+
+        let pool = rust_api::create_test_connection_pool();
+        let username = unique("deactivated");
+        let token = {
+            let mut conn = pool.get().unwrap();
+            let user = sync_directory_user(&mut conn, username, None, None, None, None, None).unwrap().user;
+            diesel::update(dsl::users.find(user.id))
+                .set(dsl::deactivated_at.eq(diesel::dsl::now))
+                .execute(&mut conn)
+                .unwrap();
+            let config = rust_api::config::get_config().unwrap();
+            rust_api::jwt::issue_access_token(&config, user.id, &user.login_id, &[]).unwrap()
+        };
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .wrap(ReqDataCreator)
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let req = actix_web::test::TestRequest::get()
+            .uri("/test")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = actix_web::test::try_call_service(&app, req).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_login_does_not_reactivate_deactivated_user() {
+        use rust_api::services::auth::LoginInfo;
+        use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+        use std::sync::Arc;
+
+        let pool = rust_api::create_test_connection_pool();
+        let username = unique("removed");
+        let user_id = {
+            let mut conn = pool.get().unwrap();
+            let user = sync_directory_user(&mut conn, username.clone(), None, None, None, None, None).unwrap().user;
+            diesel::update(dsl::users.find(user.id))
+                .set(dsl::deactivated_at.eq(diesel::dsl::now))
+                .execute(&mut conn)
+                .unwrap();
+            user.id
+        };
+
+        // The backend still accepts the credentials, as a local or stale directory account would
+        let backend = FakeBackend::new().with_user(DirectoryProfile { login_id: username.clone(), ..Default::default() }, "secret");
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+                .configure(rust_api::services::auth::config)
+        ).await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() })
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_none());
+
+        let user = search_user(&mut pool.get().unwrap(), &username).unwrap().remove(0);
+        assert_eq!(user.id, user_id);
+        assert!(user.deactivated_at.is_some());
+        assert!(user.last_login_at.is_none());
+    }
+}
@@ -0,0 +1,115 @@
+// Tests for admin impersonation and its audit trail
+mod tests {
+    use actix_web::{web, App, HttpResponse, Responder, http::header};
+    use chrono::Utc;
+    use rust_api::middleware::{Actor, ApiReqeustData, ReqDataCreator};
+    use rust_api::models::audit_events::{IMPERSONATED_REQUEST, IMPERSONATION_STARTED};
+    use rust_api::models::audit_events::usecases::find_user_audit_events;
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    async fn whoami(req_data: web::ReqData<ApiReqeustData>) -> impl Responder {
+        HttpResponse::Ok().json(json!({
+            "user": req_data.current_user().map(|user| user.login_id.clone()),
+            "impersonator": req_data.impersonator().map(|user| user.login_id.clone()),
+        }))
+    }
+
+    fn api_request(method: actix_web::http::Method, uri: &str, token: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .insert_header((header::AUTHORIZATION, token.to_string()))
+    }
+
+    #[actix_web::test]
+    async fn test_admin_impersonates_user_with_audit_trail() {
+        let admin = unique("impadmin");
+        let username = unique("impuser");
+        let backend = FakeBackend::new()
+            .with_user(DirectoryProfile { login_id: admin.clone(), roles: Some(vec!["admin".to_string()]), ..Default::default() }, "secret")
+            .with_user(DirectoryProfile { login_id: username.clone(), roles: Some(vec!["user".to_string()]), ..Default::default() }, "secret");
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+                .service(web::scope("/whoami").wrap(ReqDataCreator).route("", web::get().to(whoami)))
+        ).await;
+        let config = rust_api::config::get_config().unwrap();
+
+        let login = |username: &String| {
+            let req = actix_web::test::TestRequest::post()
+                .uri("/login")
+                .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() });
+            let app = &app;
+            async move {
+                let resp = actix_web::test::call_service(app, req.to_request()).await;
+                assert_eq!(resp.status().as_u16(), 200);
+                resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string()
+            }
+        };
+        let admin_token = login(&admin).await;
+        let user_token = login(&username).await;
+        let admin_id = rust_api::jwt::decode_access_token(&config, admin_token.trim_start_matches("Bearer ")).unwrap().claims.id;
+        let user_id = rust_api::jwt::decode_access_token(&config, user_token.trim_start_matches("Bearer ")).unwrap().claims.id;
+
+        let impersonate = |token: &String, user_id: i32| api_request(actix_web::http::Method::POST, &format!("/api/admin/impersonate/{}", user_id), token).to_request();
+        assert_eq!(actix_web::test::call_service(&app, impersonate(&user_token, admin_id)).await.status().as_u16(), 403);
+        assert_eq!(actix_web::test::call_service(&app, impersonate(&admin_token, admin_id)).await.status().as_u16(), 400);
+        assert_eq!(actix_web::test::call_service(&app, impersonate(&admin_token, i32::MAX)).await.status().as_u16(), 404);
+
+        let resp = actix_web::test::call_service(&app, impersonate(&admin_token, user_id)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(body, json!({ "user_id": user_id, "username": username, "impersonator_id": admin_id, "expires_in": 600 }));
+
+        let claims = rust_api::jwt::decode_access_token(&config, token.trim_start_matches("Bearer ")).unwrap().claims;
+        assert_eq!(claims.id, user_id);
+        assert_eq!(claims.roles, vec!["user".to_string()]);
+        assert_eq!(claims.act, Some(Actor { id: admin_id, username: admin.clone() }));
+        assert_eq!(claims.exp - claims.iat, 600);
+
+        // Requests act as the user and know the admin behind them
+        let resp = actix_web::test::call_service(&app, api_request(actix_web::http::Method::GET, "/whoami", &token).to_request()).await;
+        let whoami: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(whoami, json!({ "user": username, "impersonator": admin }));
+        let resp = actix_web::test::call_service(&app, api_request(actix_web::http::Method::GET, "/api/users/", &token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // The user's own permissions that could outlive the impersonation are withheld
+        let resp = actix_web::test::call_service(&app, api_request(actix_web::http::Method::GET, "/api/tokens/", &token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = actix_web::test::call_service(&app, api_request(actix_web::http::Method::GET, "/api/mfa/", &token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = actix_web::test::call_service(&app, api_request(actix_web::http::Method::GET, "/api/mfa/", &user_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let mut conn = pool.get().unwrap();
+        let events: Vec<_> = find_user_audit_events(&mut conn, user_id).unwrap()
+            .into_iter()
+            .map(|event| {
+                assert_eq!(event.impersonator_id, Some(admin_id));
+                assert_eq!(event.token_id.as_deref(), Some(claims.jti.as_str()));
+                (event.action, event.http_method, event.http_path, event.http_status)
+            })
+            .collect();
+        let request = |method: &str, path: &str, status: i32| (IMPERSONATED_REQUEST.to_string(), Some(method.to_string()), Some(path.to_string()), Some(status));
+        assert_eq!(events, vec![
+            (IMPERSONATION_STARTED.to_string(), None, None, None),
+            request("GET", "/whoami", 200),
+            request("GET", "/api/users/", 200),
+            request("GET", "/api/tokens/", 403),
+            request("GET", "/api/mfa/", 403),
+        ]);
+    }
+}
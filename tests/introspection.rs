@@ -0,0 +1,218 @@
+// Tests for GET /api/me and token introspection
+mod tests {
+    use actix_web::{web, App, http::header};
+    use chrono::Utc;
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn backend(username: &str) -> web::Data<dyn AuthBackend> {
+        let profile = DirectoryProfile { login_id: username.to_string(), roles: Some(vec!["readonly".to_string()]), ..Default::default() };
+        let service = DirectoryProfile { login_id: format!("{}_service", username), roles: Some(vec!["admin".to_string()]), ..Default::default() };
+        web::Data::from(Arc::new(FakeBackend::new().with_user(profile, "secret").with_user(service, "secret")) as Arc<dyn AuthBackend>)
+    }
+
+    fn login_request(username: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.to_string(), password: "secret".to_string() })
+    }
+
+    fn create_token_request(token: &str, scopes: &[&str]) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/api/tokens/")
+            .insert_header((header::AUTHORIZATION, token.to_string()))
+            .set_json(json!({ "name": "resource-server", "scopes": scopes, "expires_in_days": 1 }))
+    }
+
+    fn introspect_request(caller: Option<&str>, token: &str) -> actix_web::test::TestRequest {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/auth/introspect")
+            .set_form([("token", token), ("token_type_hint", "access_token")]);
+        match caller {
+            Some(caller) => req.insert_header((header::AUTHORIZATION, caller.to_string())),
+            None => req,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_me_returns_current_user() {
+        let username = unique("meuser");
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(backend(&username))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let config = rust_api::config::get_config().unwrap();
+        let claims = rust_api::jwt::decode_access_token(&config, token.trim_start_matches("Bearer ")).unwrap().claims;
+
+        let req = actix_web::test::TestRequest::get().uri("/api/me").insert_header((header::AUTHORIZATION, token));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let me: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(me["id"], json!(claims.id));
+        assert_eq!(me["login_id"], json!(username));
+        assert_eq!(me["roles"], json!(["readonly"]));
+        assert_eq!(me["token_expires_at"], json!(claims.exp));
+        assert!(me.get("impersonator_id").is_none());
+
+        let req = actix_web::test::TestRequest::get().uri("/api/me");
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_me_rejects_unknown_user() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        // A valid token whose user never logged in, so is not in the database
+        let config = rust_api::config::get_config().unwrap();
+        let token = rust_api::jwt::issue_access_token(&config, i32::MAX, &unique("ghost"), &["admin".to_string()]).unwrap();
+        let req = actix_web::test::TestRequest::get().uri("/api/me").insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_me_rejects_deactivated_user() {
+        use diesel::prelude::*;
+        use rust_api::models::users::usecases::sync_directory_user;
+        use rust_api::schema::users::dsl;
+
+        let pool = rust_api::create_test_connection_pool();
+        let token = {
+            let mut conn = pool.get().unwrap();
+            let user = sync_directory_user(&mut conn, unique("medeactivated"), None, None, None, None, None).unwrap().user;
+            diesel::update(dsl::users.find(user.id))
+                .set(dsl::deactivated_at.eq(diesel::dsl::now))
+                .execute(&mut conn)
+                .unwrap();
+            let config = rust_api::config::get_config().unwrap();
+            rust_api::jwt::issue_access_token(&config, user.id, &user.login_id, &[]).unwrap()
+        };
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/api/me").insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_introspection_reports_token_state() {
+        let username = unique("introuser");
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(backend(&username))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let access_token = token.trim_start_matches("Bearer ").to_string();
+
+        let resp = actix_web::test::call_service(&app, create_token_request(&token, &["customers:read"]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let created: Value = actix_web::test::read_body_json(resp).await;
+        let api_token = created["token"].as_str().unwrap().to_string();
+
+        // The resource server calls with an admin's API token scoped to introspection
+        let resp = actix_web::test::call_service(&app, login_request(&format!("{}_service", username)).to_request()).await;
+        let admin_token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let resp = actix_web::test::call_service(&app, create_token_request(&admin_token, &["tokens:introspect"]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let service_token: Value = actix_web::test::read_body_json(resp).await;
+        let service = format!("Bearer {}", service_token["token"].as_str().unwrap());
+
+        // Anonymous callers cannot probe tokens
+        let resp = actix_web::test::call_service(&app, introspect_request(None, &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = actix_web::test::call_service(&app, introspect_request(Some("Bearer invalid"), &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&service), &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let introspected: Value = actix_web::test::read_body_json(resp).await;
+        let config = rust_api::config::get_config().unwrap();
+        let claims = rust_api::jwt::decode_access_token(&config, &access_token).unwrap().claims;
+        assert_eq!(introspected["active"], json!(true));
+        assert_eq!(introspected["username"], json!(username));
+        assert_eq!(introspected["sub"], json!(claims.id.to_string()));
+        assert_eq!(introspected["jti"], json!(claims.jti));
+        assert_eq!(introspected["exp"], json!(claims.exp));
+        assert_eq!(introspected["token_type"], json!("Bearer"));
+        assert_eq!(introspected["roles"], json!(["readonly"]));
+        let scope = introspected["scope"].as_str().unwrap();
+        assert!(scope.split(' ').any(|permission| permission == "customers:read"));
+        assert!(!scope.split(' ').any(|permission| permission == "customers:write"));
+
+        // API tokens are limited to their scopes
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&service), &api_token).to_request()).await;
+        let introspected: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(introspected["active"], json!(true));
+        assert_eq!(introspected["scope"], json!("customers:read"));
+        assert_eq!(introspected["jti"], json!(format!("api-token-{}", created["id"])));
+
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&service), "garbage").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let introspected: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(introspected, json!({ "active": false }));
+
+        let req = actix_web::test::TestRequest::post().uri("/auth/logout").insert_header((header::AUTHORIZATION, token));
+        assert_eq!(actix_web::test::call_service(&app, req.to_request()).await.status().as_u16(), 204);
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&service), &access_token).to_request()).await;
+        let introspected: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(introspected, json!({ "active": false }));
+    }
+
+    #[actix_web::test]
+    async fn test_introspection_requires_permission() {
+        let username = unique("introdenied");
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(backend(&username))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let access_token = token.trim_start_matches("Bearer ").to_string();
+
+        // Ordinary users cannot introspect, not even their own tokens
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&token), &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        // An admin's API token needs the scope as well
+        let resp = actix_web::test::call_service(&app, login_request(&format!("{}_service", username)).to_request()).await;
+        let admin_token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let resp = actix_web::test::call_service(&app, create_token_request(&admin_token, &["customers:read"]).to_request()).await;
+        let api_token: Value = actix_web::test::read_body_json(resp).await;
+        let caller = format!("Bearer {}", api_token["token"].as_str().unwrap());
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&caller), &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let resp = actix_web::test::call_service(&app, introspect_request(Some(&admin_token), &access_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+}
@@ -0,0 +1,80 @@
+mod tests {
+    use rust_api::{config::Config, jwt::{ClaimsPolicy, Keyring}, middleware::UserClaims, services::auth};
+    use actix_web::App;
+    use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+
+    fn config_with(algorithm: &str, key_name: &str) -> Config {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt");
+        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+        vars.push(("JWT_ALGORITHM".into(), algorithm.into()));
+        vars.push(("JWT_PRIVATE_KEY_FILE".into(), format!("{}/{}_private.pem", fixtures, key_name)));
+        vars.push(("JWT_PUBLIC_KEY_FILE".into(), format!("{}/{}_public.pem", fixtures, key_name)));
+        envy::from_iter(vars).expect("Failed to build config")
+    }
+
+    fn claims() -> UserClaims {
+        rust_api::jwt::new_claims(&rust_api::config::get_config().unwrap(), 1, "testuser")
+    }
+
+    fn assert_verifiable_with_jwks(algorithm: &str, key_name: &str) {
+        let config = config_with(algorithm, key_name);
+        let keys = Keyring::from_config(&config).unwrap();
+        let token = keys.encode(&claims()).unwrap();
+        assert_eq!(keys.decode(&token, &ClaimsPolicy::from_config(&config)).unwrap().claims.username, "testuser");
+
+        // A downstream service only needs the published JWK to verify the token
+        let jwks = keys.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        let header = decode_header(&token).unwrap();
+        let jwk = jwks.find(header.kid.as_deref().unwrap()).expect("kid should match the published key");
+        let decoding = DecodingKey::from_jwk(jwk).unwrap();
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[config.get_jwt_audience()]);
+        let data = decode::<UserClaims>(&token, &decoding, &validation).unwrap();
+        assert_eq!(data.claims.id, 1);
+    }
+
+    #[test]
+    fn test_rs256_tokens_verify_with_jwks() {
+        assert_verifiable_with_jwks("RS256", "rs256");
+    }
+
+    #[test]
+    fn test_es256_tokens_verify_with_jwks() {
+        assert_verifiable_with_jwks("ES256", "es256");
+    }
+
+    #[test]
+    fn test_eddsa_tokens_verify_with_jwks() {
+        assert_verifiable_with_jwks("EdDSA", "ed25519");
+    }
+
+    #[test]
+    fn test_hs256_secret_is_not_published() {
+        let keys = Keyring::from_config(&config_with("HS256", "rs256")).unwrap();
+        assert!(keys.jwks().keys.is_empty());
+    }
+
+    #[test]
+    fn test_asymmetric_algorithm_requires_matching_keys() {
+        let mut config = config_with("RS256", "rs256");
+        config.jwt_private_key_file = None;
+        assert!(Keyring::from_config(&config).is_err());
+
+        // An EC key cannot be used for RS256
+        assert!(Keyring::from_config(&config_with("RS256", "es256")).is_err());
+        assert!(Keyring::from_config(&config_with("HS512", "rs256")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_jwks_endpoint() {
+        let app = actix_web::test::init_service(App::new().service(auth::jwks)).await;
+
+        let req = actix_web::test::TestRequest::get().uri("/.well-known/jwks.json").to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+        assert!(body["keys"].is_array());
+    }
+}
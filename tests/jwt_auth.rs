@@ -1,5 +1,5 @@
 mod tests {
-    use rust_api::middleware::{validator, UserClaims, ReqDataCreator, TracingMiddleware};
+    use rust_api::middleware::{validator, ReqDataCreator, TracingMiddleware};
     use actix_web::{test, web, App, http::header::ContentType, Responder, HttpResponse, http::header};
     use actix_web_httpauth::middleware::HttpAuthentication;
     use jsonwebtoken::{encode, Header, EncodingKey};
@@ -9,25 +9,22 @@ mod tests {
     }
 
     fn create_valid_token() -> String {
-        let claims = UserClaims{
-            id: 1,
-            username: "testuser".into(),
-            exp: (chrono::Utc::now() + chrono::Duration::days(7)).timestamp()
-        };
+        let config = rust_api::config::get_config().unwrap();
+        let claims = rust_api::jwt::new_claims(&config, 1, "testuser");
 
-        let secret = rust_api::config::get_config().unwrap().jwt_secret;
+        let secret = config.jwt_secret;
         let secret = secret.split(" ").map(|hex_str| u8::from_str_radix(hex_str, 16).unwrap()).collect::<Vec<u8>>();
         encode(&Header::default(), &claims, &EncodingKey::from_secret(&secret)).expect("Error creating JWT token")
     }
 
     fn create_expired_token() -> String {
-        let claims = UserClaims{
-            id: 1,
-            username: "testuser".into(),
-            exp: (chrono::Utc::now() - chrono::Duration::days(1)).timestamp()
-        };
+        let config = rust_api::config::get_config().unwrap();
+        let mut claims = rust_api::jwt::new_claims(&config, 1, "testuser");
+        claims.iat -= 2 * 24 * 60 * 60;
+        claims.nbf = claims.iat;
+        claims.exp = (chrono::Utc::now() - chrono::Duration::days(1)).timestamp();
 
-        let secret = rust_api::config::get_config().unwrap().jwt_secret;
+        let secret = config.jwt_secret;
         let secret = secret.split(" ").map(|hex_str| u8::from_str_radix(hex_str, 16).unwrap()).collect::<Vec<u8>>();
         encode(&Header::default(), &claims, &EncodingKey::from_secret(&secret)).expect("Error creating JWT token")
     }
@@ -0,0 +1,124 @@
+// Tests for iss/aud/iat/nbf validation of access tokens
+mod tests {
+    use rust_api::{config::{get_config, Config}, jwt::{self, ClaimsPolicy, Keyring}};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn config_for(issuer: &str) -> Config {
+        let mut config = get_config().unwrap();
+        config.jwt_issuer = Some(issuer.to_string());
+        config
+    }
+
+    fn decode(config: &Config, token: &str) -> Result<rust_api::middleware::UserClaims, String> {
+        Keyring::from_config(config)?
+            .decode(token, &ClaimsPolicy::from_config(config))
+            .map(|data| data.claims)
+    }
+
+    #[test]
+    fn test_issued_claims() {
+        let config = config_for("https://api.example.com");
+        let claims = jwt::new_claims(&config, 1, "testuser");
+
+        assert_eq!(claims.iss, "https://api.example.com");
+        assert_eq!(claims.aud, config.get_jwt_audience());
+        assert_eq!(claims.iat, claims.nbf);
+        assert_eq!(claims.exp - claims.iat, config.get_access_token_ttl_secs());
+    }
+
+    #[test]
+    fn test_token_from_other_environment_is_rejected() {
+        let staging = config_for("https://staging.example.com");
+        let production = config_for("https://api.example.com");
+
+        let token = Keyring::from_config(&staging).unwrap()
+            .encode(&jwt::new_claims(&staging, 1, "testuser"))
+            .unwrap();
+
+        assert!(decode(&staging, &token).is_ok());
+        assert!(decode(&production, &token).is_err());
+    }
+
+    #[test]
+    fn test_wrong_audience_is_rejected() {
+        let config = get_config().unwrap();
+        let mut claims = jwt::new_claims(&config, 1, "testuser");
+        claims.aud = "other-service".to_string();
+        let token = Keyring::from_config(&config).unwrap().encode(&claims).unwrap();
+
+        assert!(decode(&config, &token).is_err());
+    }
+
+    #[test]
+    fn test_default_claims_differ_per_environment() {
+        let config_in = |environment: &str| {
+            let mut config = get_config().unwrap();
+            config.environment = Some(environment.to_string());
+            config.jwt_issuer = None;
+            config.jwt_audience = None;
+            config
+        };
+        let staging = config_in("staging");
+        let development = config_in("development");
+        assert_eq!(staging.get_jwt_audience(), "rust-api-staging");
+
+        // A token issued for another audience is rejected with the default configuration
+        let mut claims = jwt::new_claims(&development, 1, "testuser");
+        claims.aud = staging.get_jwt_audience();
+        let token = Keyring::from_config(&development).unwrap().encode(&claims).unwrap();
+        assert!(decode(&development, &token).is_err());
+
+        let token = Keyring::from_config(&staging).unwrap()
+            .encode(&jwt::new_claims(&staging, 1, "testuser"))
+            .unwrap();
+        assert!(decode(&staging, &token).is_ok());
+        assert!(decode(&development, &token).is_err());
+    }
+
+    #[test]
+    fn test_production_requires_issuer_and_audience() {
+        let mut config = get_config().unwrap();
+        config.environment = Some("production".to_string());
+        config.jwt_issuer = None;
+        config.jwt_audience = Some("https://api.example.com".to_string());
+        assert!(config.validate_jwt_claims_config().is_err());
+
+        config.jwt_issuer = Some("https://api.example.com".to_string());
+        assert!(config.validate_jwt_claims_config().is_ok());
+    }
+
+    #[test]
+    fn test_not_yet_valid_tokens_are_rejected() {
+        let config = get_config().unwrap();
+        let keyring = Keyring::from_config(&config).unwrap();
+        let leeway = config.get_jwt_leeway_secs() as i64;
+
+        let mut claims = jwt::new_claims(&config, 1, "testuser");
+        claims.nbf += leeway + 60;
+        assert!(decode(&config, &keyring.encode(&claims).unwrap()).is_err());
+
+        let mut claims = jwt::new_claims(&config, 1, "testuser");
+        claims.iat += leeway + 60;
+        assert!(decode(&config, &keyring.encode(&claims).unwrap()).is_err());
+
+        // Clock skew within the leeway is tolerated
+        let mut claims = jwt::new_claims(&config, 1, "testuser");
+        claims.nbf += leeway / 2;
+        claims.exp = claims.iat - leeway / 2;
+        assert!(decode(&config, &keyring.encode(&claims).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_tokens_without_standard_claims_are_rejected() {
+        let config = get_config().unwrap();
+        let legacy_claims = serde_json::json!({
+            "id": 1,
+            "username": "testuser",
+            "exp": (chrono::Utc::now() + chrono::Duration::days(7)).timestamp(),
+            "jti": uuid::Uuid::new_v4().to_string(),
+        });
+        let token = encode(&Header::default(), &legacy_claims, &EncodingKey::from_secret(&jwt::secret(&config).unwrap())).unwrap();
+
+        assert!(decode(&config, &token).is_err());
+    }
+}
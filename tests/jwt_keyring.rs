@@ -0,0 +1,149 @@
+mod tests {
+    use rust_api::{config::Config, jwt::{self, ClaimsPolicy, Keyring}, middleware::UserClaims};
+    use std::path::PathBuf;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt");
+
+    fn config() -> Config {
+        envy::from_iter(std::env::vars()).expect("Failed to build config")
+    }
+
+    fn write_keyring(json: serde_json::Value) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("jwt_keyring_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, json.to_string()).unwrap();
+        path
+    }
+
+    fn keyring_config(json: serde_json::Value) -> Config {
+        let mut config = config();
+        config.jwt_keyring_file = Some(write_keyring(json).to_string_lossy().into_owned());
+        config
+    }
+
+    fn private_key(kid: &str, algorithm: &str, name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "kid": kid,
+            "algorithm": algorithm,
+            "private_key_file": format!("{}/{}_private.pem", FIXTURES, name),
+            "public_key_file": format!("{}/{}_public.pem", FIXTURES, name),
+        })
+    }
+
+    fn public_key(kid: &str, algorithm: &str, name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "kid": kid,
+            "algorithm": algorithm,
+            "public_key_file": format!("{}/{}_public.pem", FIXTURES, name),
+        })
+    }
+
+    fn policy() -> ClaimsPolicy {
+        ClaimsPolicy::from_config(&config())
+    }
+
+    fn claims() -> UserClaims {
+        rust_api::jwt::new_claims(&config(), 1, "testuser")
+    }
+
+    #[test]
+    fn test_verify_only_keys_accept_tokens_from_previous_key() {
+        let previous = Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-07",
+            "keys": [private_key("2026-07", "RS256", "rs256")]
+        }))).unwrap();
+        let old_token = previous.encode(&claims()).unwrap();
+
+        // After rotation the old key is kept for verification only
+        let current = Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-10",
+            "keys": [private_key("2026-10", "EdDSA", "ed25519"), public_key("2026-07", "RS256", "rs256")]
+        }))).unwrap();
+        assert_eq!(current.active_key_id(), Some("2026-10"));
+        assert!(current.decode(&old_token, &policy()).is_ok());
+
+        let new_token = current.encode(&claims()).unwrap();
+        assert_eq!(jsonwebtoken::decode_header(&new_token).unwrap().kid.as_deref(), Some("2026-10"));
+        assert!(current.decode(&new_token, &policy()).is_ok());
+        assert!(previous.decode(&new_token, &policy()).is_err());
+
+        let kids: Vec<_> = current.jwks().keys.into_iter().filter_map(|jwk| jwk.common.key_id).collect();
+        assert_eq!(kids, vec!["2026-10", "2026-07"]);
+    }
+
+    #[test]
+    fn test_legacy_hs256_tokens_without_kid() {
+        let config = config();
+        let legacy_token = Keyring::from_config(&config).unwrap().encode(&claims()).unwrap();
+        assert!(jsonwebtoken::decode_header(&legacy_token).unwrap().kid.is_none());
+
+        let keyring = Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-10",
+            "keys": [
+                private_key("2026-10", "ES256", "es256"),
+                { "kid": "legacy", "algorithm": "HS256", "secret": config.jwt_secret }
+            ]
+        }))).unwrap();
+        assert!(keyring.decode(&legacy_token, &policy()).is_ok());
+
+        // The shared secret is never published
+        assert_eq!(keyring.jwks().keys.len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_kid_is_rejected() {
+        let other = Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "other",
+            "keys": [private_key("other", "ES256", "es256")]
+        }))).unwrap();
+        let token = other.encode(&claims()).unwrap();
+
+        let keyring = Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-10",
+            "keys": [private_key("2026-10", "ES256", "es256")]
+        }))).unwrap();
+        assert!(keyring.decode(&token, &policy()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_keyrings() {
+        // The active key must be able to sign
+        assert!(Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-10",
+            "keys": [public_key("2026-10", "EdDSA", "ed25519")]
+        }))).is_err());
+
+        assert!(Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "missing",
+            "keys": [private_key("2026-10", "EdDSA", "ed25519")]
+        }))).is_err());
+
+        assert!(Keyring::from_config(&keyring_config(serde_json::json!({
+            "active": "2026-10",
+            "keys": [private_key("2026-10", "EdDSA", "ed25519"), public_key("2026-10", "ES256", "es256")]
+        }))).is_err());
+    }
+
+    #[test]
+    fn test_reload_keyring() {
+        let mut config = keyring_config(serde_json::json!({
+            "active": "2026-07",
+            "keys": [private_key("2026-07", "ES256", "es256")]
+        }));
+        jwt::reload_keyring(&config).unwrap();
+        let token = jwt::issue_access_token(&config, 1, "testuser", &[]).unwrap();
+        assert_eq!(jwt::keyring(&config).unwrap().active_key_id(), Some("2026-07"));
+
+        config.jwt_keyring_file = Some(write_keyring(serde_json::json!({
+            "active": "2026-10",
+            "keys": [private_key("2026-10", "EdDSA", "ed25519"), public_key("2026-07", "ES256", "es256")]
+        })).to_string_lossy().into_owned());
+        jwt::reload_keyring(&config).unwrap();
+        assert_eq!(jwt::keyring(&config).unwrap().active_key_id(), Some("2026-10"));
+        assert!(jwt::decode_access_token(&config, &token).is_ok());
+
+        // A broken keyring keeps the current keys in use
+        config.jwt_keyring_file = Some("/nonexistent/keyring.json".to_string());
+        assert!(jwt::reload_keyring(&config).is_err());
+        assert_eq!(jwt::keyring(&config).unwrap().active_key_id(), Some("2026-10"));
+    }
+}
@@ -0,0 +1,350 @@
+// Tests for LDAP backend configuration that do not need a directory server
+mod tests {
+    use rust_api::config::{get_config, Config};
+    use futures_util::future::LocalBoxFuture;
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, AuthError, DirectoryProfile, fake::FakeBackend, ldap::{BindMode, LdapBackend}};
+    use rust_api::services::auth::backend::ldap::connection::{LdapConnector, TlsMode};
+    use rust_api::services::auth::backend::ldap::escape::{escape_dn_value, escape_filter_value};
+
+    use std::sync::{Arc, Mutex};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ldap");
+
+    fn search_config() -> Config {
+        let mut config = get_config().unwrap();
+        config.ldap_bind_mode = Some("search".to_string());
+        config.ldap_bind_dn = Some("cn=svc-api,ou=services,dc=example,dc=com".to_string());
+        config.ldap_bind_password = Some("service-password".to_string());
+        config
+    }
+
+    #[test]
+    fn test_direct_bind_is_default() {
+        let mut config = get_config().unwrap();
+        config.ldap_bind_mode = None;
+
+        let backend = LdapBackend::from_config(&config).unwrap();
+        assert_eq!(backend.bind_mode(), BindMode::Direct);
+        assert!(backend.pool().is_none());
+    }
+
+    #[test]
+    fn test_search_bind_configuration() {
+        let config = search_config();
+        assert_eq!(config.get_ldap_search_base(), config.ldap_user_dn);
+
+        let backend = LdapBackend::from_config(&config).unwrap();
+        assert_eq!(backend.bind_mode(), BindMode::Search);
+        assert_eq!(backend.pool().unwrap().bind_dn(), "cn=svc-api,ou=services,dc=example,dc=com");
+
+        // The service account password must not end up in logs
+        assert!(!format!("{:?}", backend).contains("service-password"));
+    }
+
+    #[test]
+    fn test_search_bind_requires_service_account() {
+        let mut config = search_config();
+        config.ldap_bind_password = None;
+        assert!(LdapBackend::from_config(&config).is_err());
+
+        let mut config = search_config();
+        config.ldap_bind_dn = None;
+        assert!(LdapBackend::from_config(&config).is_err());
+
+        let mut config = search_config();
+        config.ldap_bind_mode = Some("anonymous".to_string());
+        assert!(LdapBackend::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_pool_configuration() {
+        let mut config = search_config();
+        config.ldap_pool_max_size = Some(3);
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let pool = backend.pool().unwrap();
+        assert_eq!(pool.max_size(), 3);
+        assert_eq!(pool.idle_count(), 0);
+
+        // Direct mode still uses the pool for the guard and profile searches
+        config.ldap_bind_mode = Some("direct".to_string());
+        assert!(LdapBackend::from_config(&config).unwrap().pool().is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_pool_does_not_keep_failed_connections() {
+        let mut config = search_config();
+        config.ldap_uri = "ldap://127.0.0.1:1".to_string();
+        config.ldap_pool_max_size = Some(1);
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let pool = backend.pool().unwrap();
+
+        for _ in 0..2 {
+            assert!(matches!(pool.get().await, Err(AuthError::Unavailable { .. })));
+        }
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn test_group_configuration() {
+        use rust_api::services::auth::backend::ldap::groups::GroupSource;
+
+        let mut config = get_config().unwrap();
+        config.ldap_group_source = None;
+        config.ldap_role_mapping = None;
+        let backend = LdapBackend::from_config(&config).unwrap();
+        assert_eq!(backend.groups().source(), GroupSource::MemberOf);
+        assert!(!backend.groups().manages_roles());
+
+        config.ldap_group_source = Some("search".to_string());
+        config.ldap_group_search_base = None;
+        let backend = LdapBackend::from_config(&config).unwrap();
+        assert_eq!(backend.groups().search_base(), config.get_ldap_search_base());
+        assert_eq!(backend.groups().member_attribute(), "member");
+
+        // An empty deny list turns group lookups off entirely
+        config.ldap_deny_groups = Some(String::new());
+        assert!(!LdapBackend::from_config(&config).unwrap().groups().needs_groups());
+
+        config.ldap_group_source = Some("nested".to_string());
+        assert!(LdapBackend::from_config(&config).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_empty_password_is_rejected_without_binding() {
+        let mut config = search_config();
+        // Nothing listens here; the backend must not even try to connect
+        config.ldap_uri = "ldap://127.0.0.1:1".to_string();
+        let backend = LdapBackend::from_config(&config).unwrap();
+
+        assert_eq!(backend.authenticate("alice", "").await, Err(AuthError::InvalidCredentials));
+        assert!(matches!(backend.authenticate("alice", "secret").await, Err(AuthError::Unavailable { .. })));
+    }
+
+    #[test]
+    fn test_filter_escaping() {
+        assert_eq!(escape_filter_value("alice"), "alice");
+        assert_eq!(escape_filter_value("*"), "\\2a");
+        assert_eq!(escape_filter_value("a\\b"), "a\\5cb");
+        assert_eq!(escape_filter_value("nul\0"), "nul\\00");
+        assert_eq!(escape_filter_value("*)(uid=*))(|(uid=*"), "\\2a\\29\\28uid=\\2a\\29\\29\\28|\\28uid=\\2a");
+    }
+
+    #[test]
+    fn test_dn_escaping() {
+        assert_eq!(escape_dn_value("alice"), "alice");
+        assert_eq!(escape_dn_value("admin,dc=example"), "admin\\2cdc\\3dexample");
+        assert_eq!(escape_dn_value("#admin "), "\\23admin\\20");
+        assert_eq!(escape_dn_value("a+b;c<d>e\"f\\g"), "a\\2bb\\3bc\\3cd\\3ee\\22f\\5cg");
+    }
+
+    #[test]
+    fn test_filter_injection_payloads_are_matched_literally() {
+        let config = get_config().unwrap();
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let restriction = &config.ldap_filter;
+
+        for payload in ["*", "*)(cn=*", "admin)(|(cn=*", "*))(|(objectClass=*", "alice)(!(memberOf=cn=Partner", "x\0"] {
+            let filter = backend.user_filter(payload);
+
+            // The payload cannot open or close filter components or add wildcards
+            let value = filter
+                .strip_prefix(&format!("(&({}=", config.ldap_uid_column))
+                .and_then(|rest| rest.strip_suffix(&format!("){})", restriction)))
+                .unwrap_or_else(|| panic!("unexpected filter structure: {}", filter));
+            assert!(!value.contains(['(', ')', '*', '\0']), "payload {:?} leaked into {}", payload, filter);
+            assert!(ldap3::parse_filter(&filter).is_ok(), "filter {} should parse", filter);
+        }
+    }
+
+    #[test]
+    fn test_dn_injection_payloads_stay_in_one_rdn() {
+        let config = get_config().unwrap();
+        let backend = LdapBackend::from_config(&config).unwrap();
+
+        for payload in ["admin,cn=users,dc=example,dc=com", "alice+cn=admin", "cn=admin", " admin", "#admin"] {
+            let dn = backend.user_dn(payload);
+            let rdn = dn.strip_suffix(&format!(", {}", config.ldap_user_dn)).unwrap();
+            assert!(!rdn[config.ldap_uid_column.len() + 1..].contains([',', '+', '=']), "payload {:?} leaked into {}", payload, dn);
+            assert!(!rdn.ends_with(' ') && !rdn.contains("=#") && !rdn.contains("= "));
+        }
+    }
+
+    #[test]
+    fn test_tls_mode_follows_uri_scheme() {
+        let mut config = get_config().unwrap();
+        config.ldap_tls_mode = None;
+
+        config.ldap_uri = "ldap://ad.example.com".to_string();
+        assert_eq!(LdapConnector::from_config(&config).unwrap().tls_mode(), TlsMode::StartTls);
+
+        config.ldap_uri = "ldaps://ad.example.com".to_string();
+        assert_eq!(LdapConnector::from_config(&config).unwrap().tls_mode(), TlsMode::Ldaps);
+
+        // StartTLS over ldaps:// or LDAPS over ldap:// is a configuration mistake
+        config.ldap_tls_mode = Some("starttls".to_string());
+        assert!(LdapConnector::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_cleartext_requires_opt_in() {
+        let mut config = get_config().unwrap();
+        config.ldap_uri = "ldap://ad.example.com".to_string();
+        config.ldap_tls_mode = Some("none".to_string());
+        config.ldap_allow_cleartext = None;
+        assert!(LdapConnector::from_config(&config).is_err());
+
+        config.ldap_allow_cleartext = Some(true);
+        assert_eq!(LdapConnector::from_config(&config).unwrap().tls_mode(), TlsMode::None);
+    }
+
+    #[test]
+    fn test_tls_certificates() {
+        let mut config = get_config().unwrap();
+        config.ldap_uri = "ldaps://ad.example.com".to_string();
+        config.ldap_tls_mode = None;
+        config.ldap_connect_timeout_secs = Some(2);
+        config.ldap_operation_timeout_secs = Some(3);
+
+        config.ldap_ca_file = Some(format!("{}/bundle.pem", FIXTURES));
+        config.ldap_client_cert_file = Some(format!("{}/client.pem", FIXTURES));
+        config.ldap_client_key_file = Some(format!("{}/client.key", FIXTURES));
+        let connector = LdapConnector::from_config(&config).unwrap();
+        assert_eq!(connector.operation_timeout(), std::time::Duration::from_secs(3));
+
+        config.ldap_client_key_file = None;
+        assert!(LdapConnector::from_config(&config).is_err(), "client certificate without key");
+
+        config.ldap_client_cert_file = None;
+        config.ldap_ca_file = Some(format!("{}/client.key", FIXTURES));
+        assert!(LdapConnector::from_config(&config).is_err(), "CA file without certificates");
+
+        config.ldap_ca_file = Some(format!("{}/missing.pem", FIXTURES));
+        assert!(LdapConnector::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_directory_sync_configuration() {
+        let mut config = get_config().unwrap();
+        config.ldap_sync_interval_secs = None;
+        config.ldap_sync_page_size = None;
+        assert_eq!(config.get_ldap_sync_interval_secs(), None);
+        assert_eq!(config.get_ldap_sync_page_size(), 500);
+
+        config.ldap_sync_interval_secs = Some(0);
+        assert_eq!(config.get_ldap_sync_interval_secs(), None);
+        config.ldap_sync_interval_secs = Some(3600);
+        assert_eq!(config.get_ldap_sync_interval_secs(), Some(3600));
+
+        // The sync searches where logins look for users, restricted by LDAP_FILTER
+        config.ldap_bind_mode = None;
+        config.ldap_uid_column = "uid".to_string();
+        config.ldap_filter = "(objectClass=person)".to_string();
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let (base, scope, filter) = backend.sync_search();
+        assert_eq!(base, config.ldap_user_dn);
+        assert_eq!(scope, ldap3::Scope::OneLevel);
+        assert_eq!(filter, "(&(uid=*)(objectClass=person))");
+
+        let mut config = search_config();
+        config.ldap_search_base = Some("dc=example,dc=com".to_string());
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let (base, scope, _) = backend.sync_search();
+        assert_eq!(base, "dc=example,dc=com");
+        assert_eq!(scope, ldap3::Scope::Subtree);
+    }
+
+    #[actix_web::test]
+    async fn test_directory_sync_requires_service_account() {
+        let mut config = get_config().unwrap();
+        config.ldap_bind_mode = None;
+        config.ldap_bind_dn = None;
+        config.ldap_bind_password = None;
+
+        let backend = LdapBackend::from_config(&config).unwrap();
+        let result = backend.sync_directory(rust_api::create_test_connection_pool(), 100).await;
+        assert!(result.unwrap_err().contains("LDAP_BIND_DN"));
+    }
+
+    /// Records what the LDAP backend would bind and search with, and authenticates like the fake backend
+    struct EscapingBackend {
+        ldap: LdapBackend,
+        fake: FakeBackend,
+        requests: Mutex<Vec<(String, String)>>,
+    }
+
+    impl AuthBackend for EscapingBackend {
+        fn name(&self) -> &'static str {
+            "escaping"
+        }
+
+        fn authenticate<'a>(&'a self, username: &'a str, password: &'a str) -> LocalBoxFuture<'a, Result<DirectoryProfile, AuthError>> {
+            self.requests.lock().unwrap().push((self.ldap.user_dn(username), self.ldap.user_filter(username)));
+            self.fake.authenticate(username, password)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_special_characters_in_username() {
+        use actix_web::{web, App};
+
+        let mut config = get_config().unwrap();
+        config.ldap_uid_column = "cn".to_string();
+        config.ldap_user_dn = "cn=users,dc=example,dc=com".to_string();
+        config.ldap_filter = "(objectClass=person)".to_string();
+        let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+        let username = format!("Doe, John (ops)+{}@example.com\\*", suffix);
+
+        let backend = Arc::new(EscapingBackend {
+            ldap: LdapBackend::from_config(&config).unwrap(),
+            fake: FakeBackend::new().with_user(DirectoryProfile { login_id: username.clone(), ..Default::default() }, "secret"),
+            requests: Mutex::new(Vec::new()),
+        });
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(web::Data::from(backend.clone() as Arc<dyn AuthBackend>))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() });
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let requests = backend.requests.lock().unwrap().clone();
+        assert_eq!(requests, vec![(
+            format!("cn=Doe\\2c John (ops)\\2b{}@example.com\\5c*, cn=users,dc=example,dc=com", suffix),
+            format!("(&(cn=Doe, John \\28ops\\29+{}@example.com\\5c\\2a)(objectClass=person))", suffix),
+        )]);
+        assert!(ldap3::parse_filter(&requests[0].1).is_ok());
+
+        // Control characters are still refused before reaching the backend
+        let req = actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: "alice\0".to_string(), password: "secret".to_string() });
+        assert_eq!(actix_web::test::call_service(&app, req.to_request()).await.status().as_u16(), 400);
+        assert_eq!(backend.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_login_id_comes_from_the_entry() {
+        use std::collections::HashMap;
+
+        let mut config = get_config().unwrap();
+        config.ldap_uid_column = "uid".to_string();
+        let backend = LdapBackend::from_config(&config).unwrap();
+
+        // Logging in as "Alice" yields the same login id as the sync
+        let entry = ldap3::SearchEntry {
+            dn: "uid=alice,ou=people,dc=example,dc=com".to_string(),
+            attrs: HashMap::from([("uid".to_string(), vec!["alice".to_string()])]),
+            bin_attrs: HashMap::new(),
+        };
+        assert_eq!(backend.login_id("Alice", &entry), "alice");
+
+        let entry = ldap3::SearchEntry { attrs: HashMap::new(), ..entry };
+        assert_eq!(backend.login_id("Alice", &entry), "Alice");
+    }
+}
@@ -0,0 +1,71 @@
+// Tests for the local database authentication backend
+mod tests {
+    use rust_api::models::users::usecases::{insert_new_user, set_local_password_hash};
+    use rust_api::services::auth::backend::{AuthBackend, AuthError, local::{hash_password, LocalBackend}};
+
+    fn create_local_user(pool: &rust_api::DbPool, password: &str) -> String {
+        let username = format!("localuser_{}", chrono::Utc::now().timestamp_nanos_opt().unwrap());
+        let mut conn = pool.get().unwrap();
+        let user = insert_new_user(
+            &mut conn,
+            username.clone(),
+            Some(777),
+            Some("Local".to_string()),
+            Some("User".to_string()),
+            None,
+            None
+        ).unwrap();
+        set_local_password_hash(&mut conn, user.id, &hash_password(password).unwrap()).unwrap();
+        username
+    }
+
+    #[actix_web::test]
+    async fn test_local_backend_accepts_correct_password() {
+        let pool = rust_api::create_test_connection_pool();
+        let username = create_local_user(&pool, "correct horse");
+        let backend = LocalBackend::new(pool);
+
+        let profile = backend.authenticate(&username, "correct horse").await.unwrap();
+        assert_eq!(profile.login_id, username);
+        assert_eq!(profile.employee_number, Some(777));
+    }
+
+    #[actix_web::test]
+    async fn test_local_backend_rejects_wrong_password() {
+        let pool = rust_api::create_test_connection_pool();
+        let username = create_local_user(&pool, "correct horse");
+        let backend = LocalBackend::new(pool);
+
+        let result = backend.authenticate(&username, "battery staple").await;
+        assert_eq!(result, Err(AuthError::InvalidCredentials));
+    }
+
+    #[actix_web::test]
+    async fn test_local_backend_rejects_unknown_user() {
+        let pool = rust_api::create_test_connection_pool();
+        let backend = LocalBackend::new(pool);
+
+        let result = backend.authenticate("no_such_local_user", "password").await;
+        assert_eq!(result, Err(AuthError::InvalidCredentials));
+    }
+
+    #[actix_web::test]
+    async fn test_local_backend_rejects_deactivated_user() {
+        use diesel::prelude::*;
+        use rust_api::schema::users::dsl;
+
+        let pool = rust_api::create_test_connection_pool();
+        let username = create_local_user(&pool, "correct horse");
+        diesel::update(dsl::users.filter(dsl::login_id.eq(&username)))
+            .set(dsl::deactivated_at.eq(diesel::dsl::now))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        let backend = LocalBackend::new(pool);
+
+        let result = backend.authenticate(&username, "correct horse").await;
+        assert!(matches!(result, Err(AuthError::Forbidden { .. })));
+        // The password is still checked first, so the state of the account does not leak
+        let result = backend.authenticate(&username, "battery staple").await;
+        assert_eq!(result, Err(AuthError::InvalidCredentials));
+    }
+}
@@ -0,0 +1,194 @@
+// Tests for the per-account login lockout
+mod tests {
+    use actix_web::{web, App, http::header};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use actix_limitation::Limiter;
+    use chrono::{TimeDelta, Utc};
+    use diesel::prelude::*;
+    use rust_api::lockout::LockoutPolicy;
+    use rust_api::middleware::validator;
+    use rust_api::models::login_failures::usecases::{clear_login_failures, find_login_lock, record_login_failure};
+    use rust_api::schema::login_failures::dsl;
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const POLICY: LockoutPolicy = LockoutPolicy { threshold: 3, window_secs: 900, base_secs: 60, max_secs: 300 };
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn token_with_roles(roles: &[&str]) -> String {
+        let config = rust_api::config::get_config().unwrap();
+        rust_api::jwt::issue_access_token(
+            &config,
+            1,
+            "admin",
+            &roles.iter().map(|role| role.to_string()).collect::<Vec<_>>(),
+        ).unwrap()
+    }
+
+    fn login_request(username: &str, password: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+    }
+
+    fn unlock_request(username: &str, token: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::delete()
+            .uri(&format!("/users/{}/lockout", username))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+    }
+
+    #[test]
+    fn test_lock_duration_doubles_up_to_max() {
+        assert_eq!(POLICY.lock_secs(0), 60);
+        assert_eq!(POLICY.lock_secs(1), 120);
+        assert_eq!(POLICY.lock_secs(2), 240);
+        assert_eq!(POLICY.lock_secs(3), 300);
+        assert_eq!(POLICY.lock_secs(1000), 300);
+
+        let mut config = rust_api::config::get_config().unwrap();
+        config.login_lockout_threshold = Some(0);
+        assert!(!LockoutPolicy::from_config(&config).is_enabled());
+    }
+
+    #[test]
+    fn test_failures_lock_with_backoff() {
+        let pool = rust_api::create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+        let username = unique("lockuser");
+
+        for _ in 1..POLICY.threshold {
+            let failure = record_login_failure(&mut conn, &username, &POLICY).unwrap();
+            assert!(failure.locked_until.is_none());
+        }
+        assert!(find_login_lock(&mut conn, &username).unwrap().is_none());
+
+        let failure = record_login_failure(&mut conn, &username, &POLICY).unwrap();
+        assert_eq!(failure.lockout_count, 1);
+        assert_eq!(failure.failure_count, 0);
+        let locked_until = find_login_lock(&mut conn, &username).unwrap().unwrap();
+        assert!(locked_until > Utc::now().naive_utc() + TimeDelta::seconds(50));
+
+        // Once the lock expires, the next lockout lasts twice as long
+        diesel::update(dsl::login_failures.find(&username))
+            .set(dsl::locked_until.eq(Utc::now().naive_utc() - TimeDelta::seconds(1)))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(find_login_lock(&mut conn, &username).unwrap().is_none());
+        for _ in 0..POLICY.threshold {
+            record_login_failure(&mut conn, &username, &POLICY).unwrap();
+        }
+        let locked_until = find_login_lock(&mut conn, &username).unwrap().unwrap();
+        assert!(locked_until > Utc::now().naive_utc() + TimeDelta::seconds(110));
+
+        // Failures outside the window are forgotten, and so is the backoff
+        let stale = Utc::now().naive_utc() - TimeDelta::seconds(POLICY.window_secs + 1);
+        diesel::update(dsl::login_failures.find(&username))
+            .set((dsl::last_failed_at.eq(stale), dsl::locked_until.eq(stale)))
+            .execute(&mut conn)
+            .unwrap();
+        let failure = record_login_failure(&mut conn, &username, &POLICY).unwrap();
+        assert_eq!(failure.failure_count, 1);
+        assert_eq!(failure.lockout_count, 0);
+
+        assert!(clear_login_failures(&mut conn, &username).unwrap());
+        assert!(!clear_login_failures(&mut conn, &username).unwrap());
+    }
+
+    #[test]
+    fn test_lock_does_not_depend_on_session_time_zone() {
+        use rust_api::models::login_failures::usecases::count_locked_accounts;
+
+        // A connection of its own, so the time zone does not leak into the pool
+        rust_api::create_test_connection_pool();
+        let config = rust_api::config::get_config().unwrap();
+        let mut conn = rust_api::DbConnection::establish(&config.test_database_url).unwrap();
+        let username = unique("tzuser");
+
+        for time_zone in ["Asia/Tokyo", "America/Los_Angeles"] {
+            diesel::sql_query(format!("SET TIME ZONE '{}'", time_zone)).execute(&mut conn).unwrap();
+            for _ in 0..POLICY.threshold {
+                record_login_failure(&mut conn, &username, &POLICY).unwrap();
+            }
+            assert!(find_login_lock(&mut conn, &username).unwrap().is_some(), "lock expired early in {}", time_zone);
+            assert!(count_locked_accounts(&mut conn).unwrap() >= 1);
+
+            diesel::update(dsl::login_failures.find(&username))
+                .set(dsl::locked_until.eq(Utc::now().naive_utc() - TimeDelta::seconds(1)))
+                .execute(&mut conn)
+                .unwrap();
+            assert!(find_login_lock(&mut conn, &username).unwrap().is_none(), "lock outlived its end in {}", time_zone);
+            clear_login_failures(&mut conn, &username).unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_locked_account_is_rejected_until_unlocked() {
+        let config = rust_api::config::get_config().unwrap();
+        let policy = LockoutPolicy::from_config(&config);
+        assert!(policy.is_enabled(), "tests expect the default lockout policy");
+
+        let pool = web::Data::new(rust_api::create_test_connection_pool());
+        let username = unique("lockeduser");
+        let backend = FakeBackend::new().with_user(
+            DirectoryProfile { login_id: username.clone(), ..Default::default() },
+            "secret",
+        );
+        let limiter = web::Data::new(
+            Limiter::builder("redis://127.0.0.1:6379")
+                .limit(1000)
+                .period(Duration::from_secs(60))
+                .build()
+                .expect("Failed to create limiter")
+        );
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(pool.clone())
+                .app_data(limiter)
+                .app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+                .configure(rust_api::services::auth::config)
+                .service(
+                    web::scope("")
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .configure(rust_api::services::api::users::config)
+                )
+        ).await;
+
+        // A success clears earlier failures
+        for _ in 1..policy.threshold {
+            let resp = actix_web::test::call_service(&app, login_request(&username, "wrong").to_request()).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+        let resp = actix_web::test::call_service(&app, login_request(&username, "secret").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // Case variations count against the same account
+        for attempt in 0..policy.threshold {
+            let name = if attempt % 2 == 0 { username.to_uppercase() } else { username.clone() };
+            let resp = actix_web::test::call_service(&app, login_request(&name, "wrong").to_request()).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+
+        // Even the right password is rejected while locked
+        let resp = actix_web::test::call_service(&app, login_request(&username, "secret").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        let retry_after: i64 = resp.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= policy.base_secs + 1);
+
+        // Only admins may unlock
+        let resp = actix_web::test::call_service(&app, unlock_request(&username, &token_with_roles(&["manager"])).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = actix_web::test::call_service(&app, unlock_request(&username.to_uppercase(), &token_with_roles(&["admin"])).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+
+        let resp = actix_web::test::call_service(&app, login_request(&username, "secret").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+}
@@ -0,0 +1,249 @@
+// Tests for TOTP two-factor authentication
+mod tests {
+    use actix_web::{web, App, http::header};
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use rust_api::lockout::LockoutPolicy;
+    use rust_api::models::login_failures::usecases::{clear_login_failures, record_login_failure};
+    use rust_api::mfa::{match_code, provisioning_uri, time_step, totp, MfaPolicy};
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    /// Secret of the RFC 6238 test vectors for SHA-1
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn code(secret: &str, step: i64) -> String {
+        let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+        format!("{:06}", totp(&secret, step))
+    }
+
+    fn login_request(username: &str) -> actix_web::test::TestRequest {
+        login_request_with(username, "secret")
+    }
+
+    fn login_request_with(username: &str, password: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.to_string(), password: password.to_string() })
+    }
+
+    fn mfa_login_request(mfa_token: &str, code: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/auth/login/mfa")
+            .set_json(json!({ "mfa_token": mfa_token, "code": code }))
+    }
+
+    fn api_request(method: actix_web::http::Method, uri: &str, token: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .insert_header((header::AUTHORIZATION, token.to_string()))
+    }
+
+    fn backend(profile: DirectoryProfile) -> web::Data<dyn AuthBackend> {
+        web::Data::from(Arc::new(FakeBackend::new().with_user(profile, "secret")) as Arc<dyn AuthBackend>)
+    }
+
+    #[test]
+    fn test_totp_matches_rfc_6238_vectors() {
+        // The 8-digit codes of RFC 6238 appendix B, truncated to 6 digits
+        for (unix_secs, expected) in [(59, 287082), (1111111109, 81804), (1234567890, 5924), (2000000000, 279037)] {
+            assert_eq!(totp(RFC_SECRET, time_step(unix_secs)), expected);
+        }
+
+        let secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, RFC_SECRET);
+        assert_eq!(match_code(&secret, "287082", 59), Some(1));
+        // One step of clock skew is accepted either way
+        assert_eq!(match_code(&secret, "287082", 89), Some(1));
+        assert_eq!(match_code(&secret, "287082", 29), Some(1));
+        assert_eq!(match_code(&secret, "287082", 119), None);
+        assert_eq!(match_code(&secret, "28708", 59), None);
+        assert_eq!(match_code(&secret, "abcdef", 59), None);
+
+        assert_eq!(
+            provisioning_uri("rust-api", "alice", &secret),
+            format!("otpauth://totp/rust-api:alice?secret={}&issuer=rust-api&algorithm=SHA1&digits=6&period=30", secret)
+        );
+
+        let mut config = rust_api::config::get_config().unwrap();
+        config.mfa_required_roles = Some("admin; manager".to_string());
+        let policy = MfaPolicy::from_config(&config);
+        assert!(policy.is_required(&["user".to_string(), "manager".to_string()]));
+        assert!(!policy.is_required(&["user".to_string()]));
+    }
+
+    #[actix_web::test]
+    async fn test_mfa_enrolment_and_two_step_login() {
+        let username = unique("mfauser");
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(backend(DirectoryProfile { login_id: username.clone(), ..Default::default() }))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        // Without MFA the login answers with tokens right away
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+
+        let req = api_request(actix_web::http::Method::POST, "/api/mfa/enroll", &token);
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let enrollment: Value = actix_web::test::read_body_json(resp).await;
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        assert!(enrollment["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/"));
+
+        // A pending enrolment does not change the login yet
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let step = time_step(Utc::now().timestamp());
+        let confirm = |code: &str| api_request(actix_web::http::Method::POST, "/api/mfa/confirm", &token).set_json(json!({ "code": code }));
+        let resp = actix_web::test::call_service(&app, confirm("000000").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        let resp = actix_web::test::call_service(&app, confirm(&code(&secret, step)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        let recovery_codes: Vec<String> = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+        assert_eq!(recovery_codes.len(), 10);
+
+        let login = || async {
+            let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 202);
+            assert!(resp.headers().get(header::AUTHORIZATION).is_none());
+            let challenge: Value = actix_web::test::read_body_json(resp).await;
+            assert_eq!(challenge["enrollment_required"], json!(false));
+            challenge["mfa_token"].as_str().unwrap().to_string()
+        };
+
+        // The code used for the confirmation cannot be replayed, the next one works
+        let mfa_token = login().await;
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &code(&secret, step)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &code(&secret, step + 1)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_some());
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &recovery_codes[0]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Recovery codes work once, in any case and with or without the dash
+        let mfa_token = login().await;
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &recovery_codes[0].to_uppercase().replace('-', "")).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let mfa_token = login().await;
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &recovery_codes[0]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Too many wrong codes invalidate the challenge
+        for _ in 1..5 {
+            let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, "000000").to_request()).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &recovery_codes[1]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let req = api_request(actix_web::http::Method::GET, "/api/mfa/", &token);
+        let status: Value = actix_web::test::read_body_json(actix_web::test::call_service(&app, req.to_request()).await).await;
+        assert_eq!(status, json!({ "enabled": true, "required": false, "recovery_codes_remaining": 9 }));
+
+        let req = api_request(actix_web::http::Method::POST, "/api/mfa/disable", &token).set_json(json!({ "code": recovery_codes[1] }));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+
+        // The wrong codes also locked the account; once unlocked, logins take one step again
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        clear_login_failures(&mut pool.get().unwrap(), &username).unwrap();
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_wrong_codes_count_towards_lockout() {
+        use rust_api::schema::login_failures::dsl;
+
+        let username = unique("mfalock");
+        let pool = rust_api::create_test_connection_pool();
+        let config = rust_api::config::get_config().unwrap();
+        let lockout_policy = LockoutPolicy::from_config(&config);
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(backend(DirectoryProfile { login_id: username.clone(), ..Default::default() }))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+        let mut conn = pool.get().unwrap();
+        let failures = |conn: &mut rust_api::DbConnection| dsl::login_failures
+            .find(&username)
+            .select(dsl::failure_count)
+            .first::<i32>(conn)
+            .optional()
+            .unwrap()
+            .unwrap_or(0);
+
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let req = api_request(actix_web::http::Method::POST, "/api/mfa/enroll", &token);
+        let enrollment: Value = actix_web::test::read_body_json(actix_web::test::call_service(&app, req.to_request()).await).await;
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        let step = time_step(Utc::now().timestamp());
+        let req = api_request(actix_web::http::Method::POST, "/api/mfa/confirm", &token).set_json(json!({ "code": code(&secret, step) }));
+        assert_eq!(actix_web::test::call_service(&app, req.to_request()).await.status().as_u16(), 200);
+
+        let login = || async {
+            let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 202);
+            let challenge: Value = actix_web::test::read_body_json(resp).await;
+            challenge["mfa_token"].as_str().unwrap().to_string()
+        };
+
+        // Passing the password alone does not forget earlier failures
+        let resp = actix_web::test::call_service(&app, login_request_with(&username, "wrong").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let mfa_token = login().await;
+        assert_eq!(failures(&mut conn), 1);
+
+        // Wrong codes and recovery codes count like wrong passwords
+        for code in ["000000", "aaaa-bbbb"] {
+            let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, code).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+        assert_eq!(failures(&mut conn), 3);
+
+        // Completing the second factor forgets them
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &code(&secret, step + 1)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(failures(&mut conn), 0);
+
+        // A pending challenge does not outlast a lock
+        let mfa_token = login().await;
+        for _ in 0..lockout_policy.threshold {
+            record_login_failure(&mut conn, &username, &lockout_policy).unwrap();
+        }
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &code(&secret, step + 2)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        assert!(resp.headers().contains_key(header::RETRY_AFTER));
+
+        // Nor does it outlast a deactivation
+        {
+            use rust_api::schema::users::dsl as users;
+            clear_login_failures(&mut conn, &username).unwrap();
+            diesel::update(users::users.filter(users::login_id.eq(&username)))
+                .set(users::deactivated_at.eq(diesel::dsl::now))
+                .execute(&mut conn)
+                .unwrap();
+        }
+        let resp = actix_web::test::call_service(&app, mfa_login_request(&mfa_token, &code(&secret, step + 2)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+    }
+}
@@ -0,0 +1,81 @@
+// Tests for MFA_REQUIRED_ROLES, in a binary of their own since they set it in the environment
+mod tests {
+    use actix_web::{web, App, http::header};
+    use chrono::Utc;
+    use rust_api::mfa::{time_step, totp};
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn code(secret: &str, step: i64) -> String {
+        let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+        format!("{:06}", totp(&secret, step))
+    }
+
+    fn login_request(username: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.to_string(), password: "secret".to_string() })
+    }
+
+    fn mfa_login_request(mfa_token: &str, code: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/auth/login/mfa")
+            .set_json(json!({ "mfa_token": mfa_token, "code": code }))
+    }
+
+    #[actix_web::test]
+    async fn test_required_role_enrols_at_login() {
+        // SAFETY: this is the only test of its binary, and no other thread has started yet
+        unsafe { std::env::set_var("MFA_REQUIRED_ROLES", "admin") };
+
+        let username = unique("mfaadmin");
+        let profile = DirectoryProfile { login_id: username.clone(), roles: Some(vec!["admin".to_string()]), ..Default::default() };
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(web::Data::from(Arc::new(FakeBackend::new().with_user(profile, "secret")) as Arc<dyn AuthBackend>))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let challenge: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(challenge["enrollment_required"], json!(true));
+        let mfa_token = challenge["mfa_token"].as_str().unwrap();
+
+        // No code is valid before the enrolment started
+        let resp = actix_web::test::call_service(&app, mfa_login_request(mfa_token, "000000").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let enroll = || actix_web::test::TestRequest::post().uri("/auth/login/mfa/enroll").set_json(json!({ "mfa_token": mfa_token }));
+        let resp = actix_web::test::call_service(&app, enroll().to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let enrollment: Value = actix_web::test::read_body_json(resp).await;
+        let secret = enrollment["secret"].as_str().unwrap();
+
+        // The first code enables MFA and completes the login
+        let current = code(secret, time_step(Utc::now().timestamp()));
+        let resp = actix_web::test::call_service(&app, mfa_login_request(mfa_token, &current).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let token = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+
+        let req = actix_web::test::TestRequest::get().uri("/api/mfa/").insert_header((header::AUTHORIZATION, token));
+        let status: Value = actix_web::test::read_body_json(actix_web::test::call_service(&app, req.to_request()).await).await;
+        assert_eq!(status, json!({ "enabled": true, "required": true, "recovery_codes_remaining": 0 }));
+
+        // Challenges of enrolled users cannot replace their secret
+        let resp = actix_web::test::call_service(&app, login_request(&username).to_request()).await;
+        let challenge: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(challenge["enrollment_required"], json!(false));
+        let req = actix_web::test::TestRequest::post().uri("/auth/login/mfa/enroll").set_json(json!({ "mfa_token": challenge["mfa_token"] }));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 409);
+    }
+}
@@ -0,0 +1,455 @@
+// Tests for OpenID Connect login against a local stub issuer
+mod tests {
+    use actix_web::{web, App, HttpResponse, HttpServer, cookie::Cookie, http::header};
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use jsonwebtoken::jwk::{Jwk, JwkSet};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use rust_api::lockout::LockoutPolicy;
+    use rust_api::models::login_failures::usecases::record_login_failure;
+    use rust_api::models::oidc_identities::usecases::find_oidc_user;
+    use rust_api::models::roles::usecases::find_user_roles;
+    use rust_api::models::users::User;
+    use rust_api::models::users::usecases::{deactivate_missing_users, sync_directory_user, upsert_login_user};
+    use rust_api::schema::users::dsl;
+    use rust_api::services::auth::oidc::{code_challenge, OidcClient, OidcSettings};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    const CLIENT_ID: &str = "rust-api";
+    const REDIRECT_URI: &str = "http://localhost:8080/auth/oidc/callback";
+    const KID: &str = "stub";
+
+    fn signing_key() -> EncodingKey {
+        let pem = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt/rs256_private.pem")).unwrap();
+        EncodingKey::from_rsa_pem(&pem).unwrap()
+    }
+
+    /// A code the stub issuer will exchange, with the PKCE challenge it was issued for
+    struct Grant {
+        code_challenge: String,
+        claims: Value,
+    }
+
+    type Grants = Arc<Mutex<HashMap<String, Grant>>>;
+
+    async fn discovery(issuer: web::Data<String>) -> HttpResponse {
+        let issuer = issuer.as_str();
+        HttpResponse::Ok().json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        }))
+    }
+
+    async fn jwks() -> HttpResponse {
+        let mut jwk = Jwk::from_encoding_key(&signing_key(), Algorithm::RS256).unwrap();
+        jwk.common.key_id = Some(KID.to_string());
+        HttpResponse::Ok().json(JwkSet { keys: vec![jwk] })
+    }
+
+    async fn token(grants: web::Data<Grants>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+        let invalid_grant = HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+        if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+            || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+            || form.get("redirect_uri").map(String::as_str) != Some(REDIRECT_URI)
+        {
+            return invalid_grant;
+        }
+        // Codes are single-use, as with a real provider
+        let Some(grant) = form.get("code").and_then(|code| grants.lock().unwrap().remove(code)) else {
+            return invalid_grant;
+        };
+        if form.get("code_verifier").map(|verifier| code_challenge(verifier)) != Some(grant.code_challenge) {
+            return invalid_grant;
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(KID.to_string());
+        let id_token = jsonwebtoken::encode(&header, &grant.claims, &signing_key()).unwrap();
+        HttpResponse::Ok().json(json!({ "access_token": "opaque", "token_type": "Bearer", "id_token": id_token }))
+    }
+
+    /// Starts a stub issuer on a free local port and returns its URL
+    fn start_issuer(grants: Grants) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let cloned_issuer = issuer.clone();
+
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(cloned_issuer.clone()))
+                .app_data(web::Data::new(grants.clone()))
+                .route("/.well-known/openid-configuration", web::get().to(discovery))
+                .route("/jwks", web::get().to(jwks))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+
+        issuer
+    }
+
+    fn settings(issuer: &str) -> OidcSettings {
+        OidcSettings {
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: Some("secret".to_string()),
+            redirect_uri: REDIRECT_URI.to_string(),
+            scopes: "openid profile email groups".to_string(),
+            username_claim: "preferred_username".to_string(),
+            employee_number_claim: Some("employee_id".to_string()),
+            groups_claim: "groups".to_string(),
+            role_mapping: vec![("app-admins".to_string(), "admin".to_string()), ("app-staff".to_string(), "user".to_string())],
+            login_ttl_secs: 600,
+            leeway_secs: 0,
+        }
+    }
+
+    fn claims(issuer: &str, nonce: &str, username: &str) -> Value {
+        let now = Utc::now().timestamp();
+        json!({
+            "iss": issuer,
+            "sub": format!("sub-{}", username),
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": nonce,
+            "preferred_username": username,
+            "given_name": "Taro",
+            "family_name": "Yamada",
+            "name": "Taro Yamada",
+            "email": format!("{}@example.com", username),
+            "employee_id": "4242",
+            "groups": ["app-admins", "app-staff", "other"],
+        })
+    }
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    /// A login started with `GET /auth/oidc/login`, as the provider would see it
+    struct PendingLogin {
+        state: String,
+        nonce: String,
+        code_challenge: String,
+        cookie: String,
+    }
+
+    fn login_request() -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::get().uri("/auth/oidc/login")
+    }
+
+    /// Checks the redirect of `GET /auth/oidc/login` and reads the login from it
+    fn pending_login(resp: actix_web::dev::ServiceResponse, issuer: &str) -> PendingLogin {
+        assert_eq!(resp.status().as_u16(), 302);
+
+        let location = resp.headers().get(header::LOCATION).unwrap().to_str().unwrap();
+        let cookie = resp.response().cookies().find(|cookie| cookie.name() == "oidc_state").unwrap();
+        started_login(location, &cookie, issuer)
+    }
+
+    /// Checks the answer of `POST /api/me/oidc/link` and reads the login from it
+    async fn pending_link(resp: actix_web::dev::ServiceResponse, issuer: &str) -> PendingLogin {
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let cookie = resp.response().cookies().find(|cookie| cookie.name() == "oidc_state").unwrap().into_owned();
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        started_login(body["authorization_url"].as_str().unwrap(), &cookie, issuer)
+    }
+
+    /// Reads a login from the provider URL it redirects to and its state cookie
+    fn started_login(location: &str, cookie: &Cookie, issuer: &str) -> PendingLogin {
+        let url = reqwest::Url::parse(location).unwrap();
+        assert!(location.starts_with(&format!("{}/authorize?", issuer)));
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["redirect_uri"], REDIRECT_URI);
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        assert_eq!(cookie.value(), params["state"]);
+        assert!(cookie.http_only().unwrap_or(false));
+
+        PendingLogin {
+            state: params["state"].clone(),
+            nonce: params["nonce"].clone(),
+            code_challenge: params["code_challenge"].clone(),
+            cookie: cookie.value().to_string(),
+        }
+    }
+
+    fn callback_request(code: &str, state: &str, cookie: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::get()
+            .uri(&format!("/auth/oidc/callback?code={}&state={}", code, state))
+            .insert_header((header::COOKIE, format!("oidc_state={}", cookie)))
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_login_provisions_user_and_issues_tokens() {
+        let grants = Grants::default();
+        let issuer = start_issuer(grants.clone());
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(OidcClient::new(settings(&issuer)).unwrap()))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        let username = unique("oidcuser");
+        grants.lock().unwrap().insert("code-1".to_string(), Grant {
+            code_challenge: login.code_challenge.clone(),
+            claims: claims(&issuer, &login.nonce, &username),
+        });
+
+        let resp = actix_web::test::call_service(&app, callback_request("code-1", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().starts_with("Bearer "));
+        // The state cookie is no longer needed
+        let removal = resp.response().cookies().find(|cookie| cookie.name() == "oidc_state").unwrap();
+        assert_eq!(removal.value(), "");
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        assert!(body["refresh_token"].is_string());
+
+        let mut conn = pool.get().unwrap();
+        let user = dsl::users.filter(dsl::login_id.eq(&username)).first::<User>(&mut conn).unwrap();
+        assert_eq!(user.employee_number, Some(4242));
+        assert_eq!(user.first_name.as_deref(), Some("Taro"));
+        assert_eq!(user.last_name.as_deref(), Some("Yamada"));
+        assert_eq!(user.email, Some(format!("{}@example.com", username)));
+        assert_eq!(find_user_roles(&mut conn, user.id).unwrap(), vec!["admin".to_string(), "user".to_string()]);
+
+        // The state was consumed by the first callback
+        let resp = actix_web::test::call_service(&app, callback_request("code-1", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_callback_rejects_invalid_logins() {
+        let grants = Grants::default();
+        let issuer = start_issuer(grants.clone());
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(web::Data::new(OidcClient::new(settings(&issuer)).unwrap()))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        // A state cookie from another browser
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        let resp = actix_web::test::call_service(&app, callback_request("code", &login.state, "other").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        // An ID token for another login
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        grants.lock().unwrap().insert("code-nonce".to_string(), Grant {
+            code_challenge: login.code_challenge.clone(),
+            claims: claims(&issuer, "replayed-nonce", &unique("oidcnonce")),
+        });
+        let resp = actix_web::test::call_service(&app, callback_request("code-nonce", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // A code issued for another code verifier
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        grants.lock().unwrap().insert("code-pkce".to_string(), Grant {
+            code_challenge: code_challenge("another-verifier"),
+            claims: claims(&issuer, &login.nonce, &unique("oidcpkce")),
+        });
+        let resp = actix_web::test::call_service(&app, callback_request("code-pkce", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // An ID token for another client
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        let mut foreign = claims(&issuer, &login.nonce, &unique("oidcaud"));
+        foreign["aud"] = json!("another-client");
+        grants.lock().unwrap().insert("code-aud".to_string(), Grant { code_challenge: login.code_challenge.clone(), claims: foreign });
+        let resp = actix_web::test::call_service(&app, callback_request("code-aud", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // The provider refused the login
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/auth/oidc/callback?error=access_denied&state={}", login.state))
+            .insert_header((header::COOKIE, format!("oidc_state={}", login.cookie)));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_endpoints_without_configuration() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, login_request().to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_login_does_not_take_over_existing_users() {
+        let grants = Grants::default();
+        let issuer = start_issuer(grants.clone());
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(OidcClient::new(settings(&issuer)).unwrap()))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        // A user of the directory with the username the provider claims
+        let username = unique("oidctaken");
+        let mut conn = pool.get().unwrap();
+        let user = sync_directory_user(&mut conn, username.clone(), None, None, None, Some("ldap@example.com".to_string()), None).unwrap().user;
+
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        grants.lock().unwrap().insert("code-taken".to_string(), Grant {
+            code_challenge: login.code_challenge.clone(),
+            claims: claims(&issuer, &login.nonce, &username),
+        });
+        let resp = actix_web::test::call_service(&app, callback_request("code-taken", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_none());
+
+        let unchanged = dsl::users.find(user.id).first::<User>(&mut conn).unwrap();
+        assert_eq!(unchanged.email.as_deref(), Some("ldap@example.com"));
+        assert!(unchanged.last_login_at.is_none());
+        assert!(find_oidc_user(&mut conn, &issuer, &format!("sub-{}", username)).unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_identity_is_linked_by_logged_in_user() {
+        let grants = Grants::default();
+        let issuer = start_issuer(grants.clone());
+        let pool = rust_api::create_test_connection_pool();
+        let config = rust_api::config::get_config().unwrap();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(OidcClient::new(settings(&issuer)).unwrap()))
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+
+        let mut conn = pool.get().unwrap();
+        let username = unique("oidclinker");
+        let user = sync_directory_user(&mut conn, username.clone(), None, None, None, None, None).unwrap().user;
+        let token = rust_api::jwt::issue_access_token(&config, user.id, &username, &["user".to_string()]).unwrap();
+        let link_request = |token: &str| actix_web::test::TestRequest::post()
+            .uri("/api/me/oidc/link")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+
+        // The provider knows the user by another name
+        let identity = claims(&issuer, "", &unique("oidcname"));
+        let login = pending_link(actix_web::test::call_service(&app, link_request(&token).to_request()).await, &issuer).await;
+        let mut linked = identity.clone();
+        linked["nonce"] = json!(login.nonce);
+        grants.lock().unwrap().insert("code-link".to_string(), Grant { code_challenge: login.code_challenge.clone(), claims: linked });
+        let resp = actix_web::test::call_service(&app, callback_request("code-link", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_none());
+
+        // The identity now logs in as the user, who keeps the directory's profile and roles
+        let login = pending_login(actix_web::test::call_service(&app, login_request().to_request()).await, &issuer);
+        let mut linked = identity.clone();
+        linked["nonce"] = json!(login.nonce);
+        grants.lock().unwrap().insert("code-linked".to_string(), Grant { code_challenge: login.code_challenge.clone(), claims: linked });
+        let resp = actix_web::test::call_service(&app, callback_request("code-linked", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(body["user"]["id"], json!(user.id));
+        assert_eq!(body["user"]["login_id"], json!(username));
+        assert_eq!(body["user"]["source"], json!("directory"));
+        assert!(body["user"]["email"].is_null());
+        assert!(find_user_roles(&mut conn, user.id).unwrap().is_empty());
+
+        // Another user cannot link the same identity
+        let other_name = unique("oidcother");
+        let other = sync_directory_user(&mut conn, other_name.clone(), None, None, None, None, None).unwrap().user;
+        let other_token = rust_api::jwt::issue_access_token(&config, other.id, &other_name, &["user".to_string()]).unwrap();
+        let login = pending_link(actix_web::test::call_service(&app, link_request(&other_token).to_request()).await, &issuer).await;
+        let mut linked = identity.clone();
+        linked["nonce"] = json!(login.nonce);
+        grants.lock().unwrap().insert("code-relink".to_string(), Grant { code_challenge: login.code_challenge.clone(), claims: linked });
+        let resp = actix_web::test::call_service(&app, callback_request("code-relink", &login.state, &login.cookie).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        assert_eq!(find_oidc_user(&mut conn, &issuer, identity["sub"].as_str().unwrap()).unwrap().map(|user| user.id), Some(user.id));
+
+        // Links need a token of the user themselves
+        let resp = actix_web::test::call_service(&app, actix_web::test::TestRequest::post().uri("/api/me/oidc/link").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_login_rejects_deactivated_and_locked_users() {
+        let grants = Grants::default();
+        let issuer = start_issuer(grants.clone());
+        let pool = rust_api::create_test_connection_pool();
+        let config = rust_api::config::get_config().unwrap();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(OidcClient::new(settings(&issuer)).unwrap()))
+                .configure(rust_api::services::auth::config)
+        ).await;
+        let username = unique("oidcinactive");
+        let oidc_login = |code: &'static str| {
+            let app = &app;
+            let grants = grants.clone();
+            let issuer = issuer.clone();
+            let username = username.clone();
+            async move {
+                let login = pending_login(actix_web::test::call_service(app, login_request().to_request()).await, &issuer);
+                grants.lock().unwrap().insert(code.to_string(), Grant {
+                    code_challenge: login.code_challenge.clone(),
+                    claims: claims(&issuer, &login.nonce, &username),
+                });
+                actix_web::test::call_service(app, callback_request(code, &login.state, &login.cookie).to_request()).await.status().as_u16()
+            }
+        };
+
+        assert_eq!(oidc_login("code-first").await, 200);
+        let mut conn = pool.get().unwrap();
+        let user = dsl::users.filter(dsl::login_id.eq(&username)).first::<User>(&mut conn).unwrap();
+        assert_eq!(user.source, "oidc");
+
+        // The directory neither syncs, logs in nor deactivates users it did not provision
+        assert!(sync_directory_user(&mut conn, username.clone(), None, None, None, None, None).is_err());
+        assert!(upsert_login_user(&mut conn, username.clone(), None, None, None, None, None).is_err());
+        conn.test_transaction::<_, diesel::result::Error, _>(|conn| {
+            assert!(!deactivate_missing_users(conn, &[unique("present")])?.contains(&username));
+            Ok(())
+        });
+
+        diesel::update(dsl::users.find(user.id))
+            .set(dsl::deactivated_at.eq(diesel::dsl::now))
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(oidc_login("code-deactivated").await, 403);
+        let deactivated = dsl::users.find(user.id).first::<User>(&mut conn).unwrap();
+        assert!(deactivated.deactivated_at.is_some());
+        assert_eq!(deactivated.last_login_at, user.last_login_at);
+
+        diesel::update(dsl::users.find(user.id))
+            .set(dsl::deactivated_at.eq(None::<chrono::NaiveDateTime>))
+            .execute(&mut conn)
+            .unwrap();
+        let policy = LockoutPolicy::from_config(&config);
+        for _ in 0..policy.threshold {
+            record_login_failure(&mut conn, &username, &policy).unwrap();
+        }
+        assert_eq!(oidc_login("code-locked").await, 429);
+    }
+}
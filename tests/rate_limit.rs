@@ -0,0 +1,244 @@
+// Tests for the rate-limit middleware
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use actix_web::{web, App, HttpResponse, Responder, http::header};
+    use rust_api::rate_limit::{KeyBy, RateLimit, RateLimitPolicy, RateLimiter, API_KEY_HEADER};
+
+    async fn dummy() -> impl Responder {
+        HttpResponse::Ok().body("Hey there!")
+    }
+
+    fn policy(key_by: KeyBy, limit: usize) -> RateLimitPolicy {
+        RateLimitPolicy { name: "test", key_by, limit, period: Duration::from_secs(60) }
+    }
+
+    /// Builds the middleware independently of `RATE_LIMIT_ENABLED` and `RATE_LIMIT_TRUST_PROXY`
+    fn limit(key_by: KeyBy, limit: usize) -> RateLimit {
+        RateLimit::new(policy(key_by, limit)).enabled(true).trust_proxy(false)
+    }
+
+    fn token(user_id: i32) -> String {
+        let config = rust_api::config::get_config().unwrap();
+        rust_api::jwt::issue_access_token(&config, user_id, "ratelimited", &[]).unwrap()
+    }
+
+    fn get(ip: &str) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::get()
+            .uri("/test")
+            .peer_addr(format!("{}:12345", ip).parse().unwrap())
+    }
+
+    #[test]
+    fn test_key_by_parse() {
+        assert_eq!(KeyBy::parse("ip").unwrap(), KeyBy::Ip);
+        assert_eq!(KeyBy::parse("user").unwrap(), KeyBy::User);
+        assert_eq!(KeyBy::parse("api_key").unwrap(), KeyBy::ApiKey);
+        assert!(KeyBy::parse("session").is_err());
+    }
+
+    #[test]
+    fn test_from_config_validates_settings() {
+        let mut config = rust_api::config::get_config().unwrap();
+        config.rate_limit_backend_url = Some("memory://".to_string());
+        assert!(RateLimiter::from_config(&config).is_ok());
+
+        // Building a Redis limiter does not connect yet
+        config.rate_limit_backend_url = Some("redis://127.0.0.1:6379".to_string());
+        assert!(RateLimiter::from_config(&config).is_ok());
+
+        config.rate_limit_backend_url = Some("http://127.0.0.1:6379".to_string());
+        assert!(RateLimiter::from_config(&config).is_err());
+
+        config.rate_limit_backend_url = Some("memory://".to_string());
+        config.rate_limit_api_key_by = Some("session".to_string());
+        assert!(RateLimiter::from_config(&config).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_check_reports_unreachable_backend() {
+        let mut config = rust_api::config::get_config().unwrap();
+        config.rate_limit_backend_url = None;
+        assert!(RateLimiter::from_config(&config).unwrap().check().await.is_ok());
+
+        // Nothing listens on port 1
+        config.rate_limit_backend_url = Some("redis://127.0.0.1:1".to_string());
+        assert!(RateLimiter::from_config(&config).unwrap().check().await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_requests_over_limit_are_rejected() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .wrap(limit(KeyBy::Ip, 2))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get("ratelimit-limit").unwrap(), "2");
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "1");
+        assert_eq!(resp.headers().get("ratelimit-policy").unwrap(), "2;w=60");
+        assert!(resp.headers().contains_key("ratelimit-reset"));
+
+        let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "0");
+
+        let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        let retry_after: u64 = resp.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+
+        // Other clients have their own count
+        let resp = actix_web::test::call_service(&app, get("10.0.0.2").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // Forwarding headers are ignored unless the proxy is trusted
+        let req = get("10.0.0.1").insert_header(("x-forwarded-for", "10.0.0.3")).to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    #[actix_web::test]
+    async fn test_requests_behind_trusted_proxy_are_counted_per_forwarded_ip() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .wrap(limit(KeyBy::Ip, 1).trust_proxy(true))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let forwarded = |ip: &str| get("10.0.0.1").insert_header(("x-forwarded-for", ip.to_string())).to_request();
+
+        let resp = actix_web::test::call_service(&app, forwarded("10.0.0.3")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, forwarded("10.0.0.4")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, forwarded("10.0.0.3")).await;
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    #[actix_web::test]
+    async fn test_passes_when_disabled() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .wrap(limit(KeyBy::Ip, 1).enabled(false))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        for _ in 0..3 {
+            let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+            assert_eq!(resp.status().as_u16(), 200);
+            assert!(!resp.headers().contains_key("ratelimit-limit"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_requests_are_counted_per_user() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .wrap(limit(KeyBy::User, 1))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let with_token = |user_id| get("10.0.0.1")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token(user_id))))
+            .to_request();
+
+        let resp = actix_web::test::call_service(&app, with_token(1)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, with_token(2)).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, with_token(1)).await;
+        assert_eq!(resp.status().as_u16(), 429);
+
+        // Requests without a valid token fall back to the client IP
+        let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    /// Counts the access tokens decoded while it is the default subscriber
+    struct DecodeCounter(Arc<AtomicUsize>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for DecodeCounter {
+        fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, _: &tracing::span::Id, _: tracing_subscriber::layer::Context<'_, S>) {
+            if attrs.metadata().name() == "decode_access_token" {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_token_is_decoded_once_per_request() {
+        use actix_web_httpauth::middleware::HttpAuthentication;
+        use rust_api::middleware::{validator, ReqDataCreator};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let decodes = Arc::new(AtomicUsize::new(0));
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(DecodeCounter(decodes.clone())));
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .service(
+                    web::scope("")
+                        .wrap(ReqDataCreator)
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .wrap(limit(KeyBy::User, 10))
+                        .route("/test", web::get().to(dummy))
+                )
+        ).await;
+
+        // The rate limit and the authentication share the claims
+        let req = get("10.0.0.1").insert_header((header::AUTHORIZATION, format!("Bearer {}", token(i32::MAX))));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "9");
+        assert_eq!(decodes.load(Ordering::SeqCst), 1);
+
+        // Invalid tokens are not decoded twice either
+        let req = get("10.0.0.1").insert_header((header::AUTHORIZATION, "Bearer invalid"));
+        let resp = actix_web::test::try_call_service(&app, req.to_request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+        assert_eq!(decodes.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_requests_are_counted_per_api_key() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::memory()))
+                .wrap(limit(KeyBy::ApiKey, 1))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let with_key = |key: &str| get("10.0.0.1").insert_header((API_KEY_HEADER, key.to_string())).to_request();
+
+        let resp = actix_web::test::call_service(&app, with_key("key-a")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, with_key("key-b")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let resp = actix_web::test::call_service(&app, with_key("key-a")).await;
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    #[actix_web::test]
+    async fn test_passes_without_limiter() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(limit(KeyBy::Ip, 1))
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        for _ in 0..3 {
+            let resp = actix_web::test::call_service(&app, get("10.0.0.1").to_request()).await;
+            assert_eq!(resp.status().as_u16(), 200);
+            assert!(!resp.headers().contains_key("ratelimit-limit"));
+        }
+    }
+}
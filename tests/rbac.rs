@@ -0,0 +1,81 @@
+// Tests for role-based access control on API routes
+mod tests {
+    use actix_web::{web, App, http::header};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use rust_api::config::get_config;
+    use rust_api::middleware::validator;
+    use rust_api::models::customers::usecases::NewCategoryBody;
+    use rust_api::rbac;
+
+    fn token_with_roles(roles: &[&str]) -> String {
+        let config = get_config().unwrap();
+        let claims = rust_api::jwt::new_claims(&config, 1, "testuser");
+        rust_api::jwt::issue_access_token(
+            &config,
+            claims.id,
+            &claims.username,
+            &roles.iter().map(|role| role.to_string()).collect::<Vec<_>>(),
+        ).unwrap()
+    }
+
+    async fn create_category(token: &str) -> u16 {
+        let pool = rust_api::create_test_connection_pool();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .wrap(HttpAuthentication::bearer(validator))
+                .configure(rust_api::services::api::customers::config)
+        ).await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/customers/categories")
+            .set_json(NewCategoryBody { name: format!("rbac_{}", uuid::Uuid::new_v4()) })
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        actix_web::test::call_service(&app, req).await.status().as_u16()
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        let mut config = get_config().unwrap();
+        config.rbac_default_role = None;
+        let roles = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        // Every user can read through the default role, only managers and admins can write
+        assert!(rbac::has_permission(&config, &[], "customers:read"));
+        assert!(!rbac::has_permission(&config, &[], "customers:write"));
+        assert!(!rbac::has_permission(&config, &roles(&["readonly"]), "customers:write"));
+        assert!(rbac::has_permission(&config, &roles(&["manager"]), "customers:write"));
+        assert!(rbac::has_permission(&config, &roles(&["admin"]), "customers:write"));
+        assert!(!rbac::has_permission(&config, &roles(&["unknown"]), "customers:write"));
+
+        config.rbac_default_role = Some(String::new());
+        assert!(!rbac::has_permission(&config, &[], "customers:read"));
+        assert!(rbac::has_permission(&config, &roles(&["readonly"]), "customers:read"));
+    }
+
+    #[actix_web::test]
+    async fn test_write_requires_permission() {
+        assert_eq!(create_category(&token_with_roles(&[])).await, 403);
+        assert_eq!(create_category(&token_with_roles(&["readonly"])).await, 403);
+        assert_eq!(create_category(&token_with_roles(&["manager"])).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn test_user_roles_are_stored() {
+        use rust_api::models::roles::usecases::{find_user_roles, set_user_roles};
+        use rust_api::models::users::usecases::insert_new_user;
+
+        let pool = rust_api::create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+        let user = insert_new_user(&mut conn, format!("rbac_{}", chrono::Utc::now().timestamp_millis()), None, None, None, None, None).unwrap();
+        assert!(find_user_roles(&mut conn, user.id).unwrap().is_empty());
+
+        // Names without a row in `roles` are ignored
+        set_user_roles(&mut conn, user.id, &["manager".to_string(), "admin".to_string(), "superuser".to_string()]).unwrap();
+        assert_eq!(find_user_roles(&mut conn, user.id).unwrap(), vec!["admin", "manager"]);
+
+        set_user_roles(&mut conn, user.id, &[]).unwrap();
+        assert!(find_user_roles(&mut conn, user.id).unwrap().is_empty());
+    }
+}
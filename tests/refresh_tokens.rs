@@ -0,0 +1,107 @@
+// Tests for refresh token rotation and reuse detection
+mod tests {
+    use actix_web::{test, web, App, http::header};
+    use actix_limitation::Limiter;
+    use rust_api::services::auth::{LoginInfo, RefreshRequest, TokenResponse};
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn create_test_app(username: String) -> App<
+        impl actix_web::dev::ServiceFactory<
+            actix_web::dev::ServiceRequest,
+            Config = (),
+            Response = actix_web::dev::ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let limiter = web::Data::new(
+            Limiter::builder("redis://127.0.0.1:6379")
+                .limit(100)
+                .period(Duration::from_secs(60))
+                .build()
+                .unwrap_or_else(|_| {
+                    Limiter::builder("memory://")
+                        .limit(100)
+                        .period(Duration::from_secs(60))
+                        .build()
+                        .expect("Failed to create limiter")
+                })
+        );
+        let backend = FakeBackend::new().with_user(
+            DirectoryProfile { login_id: username, ..Default::default() },
+            "secret",
+        );
+
+        App::new()
+            .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+            .app_data(limiter)
+            .app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+            .configure(rust_api::services::auth::config)
+    }
+
+    fn refresh_request(refresh_token: &str) -> actix_web::test::TestRequest {
+        test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(RefreshRequest { refresh_token: refresh_token.to_string() })
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_rotates_token() {
+        let username = format!("refresh_{}", chrono::Utc::now().timestamp_millis());
+        let app = test::init_service(create_test_app(username.clone())).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() })
+            .to_request();
+        let login: TokenResponse = test::call_and_read_body_json(&app, req).await;
+
+        let resp = test::call_service(&app, refresh_request(&login.refresh_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_some());
+
+        let rotated: TokenResponse = test::read_body_json(resp).await;
+        assert_ne!(rotated.refresh_token, login.refresh_token);
+
+        // Replaying the old token revokes the family, so the rotated token stops working too
+        let resp = test::call_service(&app, refresh_request(&login.refresh_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = test::call_service(&app, refresh_request(&rotated.refresh_token).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_login_returns_tokens_in_body() {
+        let username = format!("login_body_{}", chrono::Utc::now().timestamp_millis());
+        let app = test::init_service(create_test_app(username.clone())).await;
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let header = resp.headers().get(header::AUTHORIZATION).unwrap().to_str().unwrap().to_string();
+        let login: TokenResponse = test::read_body_json(resp).await;
+
+        // The header stays for older clients and carries the same token
+        assert_eq!(header, format!("Bearer {}", login.access_token));
+        assert_eq!(login.token_type, "Bearer");
+        let config = rust_api::config::get_config().unwrap();
+        assert_eq!(login.expires_in, config.get_access_token_ttl_secs());
+        let claims = rust_api::jwt::decode_access_token(&config, &login.access_token).unwrap().claims;
+        assert_eq!(login.user.id, claims.id);
+        assert_eq!(login.user.login_id, username);
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_unknown_token() {
+        let app = test::init_service(create_test_app("refresh_unknown".to_string())).await;
+
+        let resp = test::call_service(&app, refresh_request("not-a-real-token").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+}
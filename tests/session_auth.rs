@@ -0,0 +1,100 @@
+// Tests for SESSION_AUTH_ENABLED, in a binary of their own since they set it in the environment
+mod tests {
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::cookie::{Cookie, Key};
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{web, App, http::header};
+    use chrono::Utc;
+    use rust_api::services::auth::LoginInfo;
+    use rust_api::services::auth::backend::{AuthBackend, DirectoryProfile, fake::FakeBackend};
+    use rust_api::session::{CSRF_COOKIE, CSRF_HEADER};
+    use std::sync::Arc;
+
+    const SESSION_COOKIE: &str = "rust-api-session";
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    fn cookie(resp: &ServiceResponse, name: &str) -> Option<Cookie<'static>> {
+        resp.response().cookies().find(|cookie| cookie.name() == name).map(|cookie| cookie.into_owned())
+    }
+
+    fn request(method: actix_web::http::Method, uri: &str, session: &Cookie<'static>) -> actix_web::test::TestRequest {
+        actix_web::test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .cookie(session.clone())
+    }
+
+    #[actix_web::test]
+    async fn test_session_login_with_csrf_protection() {
+        // SAFETY: this is the only test of its binary, and no other thread has started yet
+        unsafe { std::env::set_var("SESSION_AUTH_ENABLED", "true") };
+
+        let username = unique("sessionuser");
+        let backend = FakeBackend::new().with_user(DirectoryProfile { login_id: username.clone(), ..Default::default() }, "secret");
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(rust_api::create_test_connection_pool()))
+                .app_data(web::Data::from(Arc::new(backend) as Arc<dyn AuthBackend>))
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                        .cookie_name(SESSION_COOKIE.to_string())
+                        .cookie_secure(false)
+                        .build()
+                )
+                .configure(rust_api::services::auth::config)
+                .configure(rust_api::services::api::config)
+        ).await;
+        let status = |req: actix_web::test::TestRequest| {
+            let app = &app;
+            async move {
+                let resp = actix_web::test::try_call_service(app, req.to_request()).await;
+                resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16()
+            }
+        };
+
+        // The tokens stay in the session cookie, the CSRF token comes in a readable cookie
+        let req = actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(LoginInfo { username: username.clone(), password: "secret".to_string() });
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert!(resp.headers().get(header::AUTHORIZATION).is_none());
+        let session = cookie(&resp, SESSION_COOKIE).unwrap();
+        assert_eq!(session.http_only(), Some(true));
+        let csrf_token = cookie(&resp, CSRF_COOKIE).unwrap();
+        assert_ne!(csrf_token.http_only(), Some(true));
+        let csrf_token = csrf_token.value().to_string();
+
+        // Safe methods need the cookie only, unsafe ones the CSRF token as well
+        assert_eq!(status(request(actix_web::http::Method::GET, "/api/mfa/", &session)).await, 200);
+        assert_eq!(status(actix_web::test::TestRequest::get().uri("/api/mfa/")).await, 401);
+        assert_eq!(status(request(actix_web::http::Method::POST, "/api/mfa/enroll", &session)).await, 403);
+        let req = request(actix_web::http::Method::POST, "/api/mfa/enroll", &session).insert_header((CSRF_HEADER, "forged"));
+        assert_eq!(status(req).await, 403);
+        let req = request(actix_web::http::Method::POST, "/api/mfa/enroll", &session).insert_header((CSRF_HEADER, csrf_token.clone()));
+        assert_eq!(status(req).await, 200);
+
+        // A refresh replaces the tokens in the session and keeps the CSRF token
+        assert_eq!(status(request(actix_web::http::Method::POST, "/auth/refresh", &session)).await, 403);
+        let req = request(actix_web::http::Method::POST, "/auth/refresh", &session).insert_header((CSRF_HEADER, csrf_token.clone()));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let refreshed = cookie(&resp, SESSION_COOKIE).unwrap();
+        // The rotated refresh token of the old cookie can no longer be used
+        let req = request(actix_web::http::Method::POST, "/auth/refresh", &session).insert_header((CSRF_HEADER, csrf_token.clone()));
+        assert_eq!(status(req).await, 401);
+        let session = refreshed;
+
+        let req = request(actix_web::http::Method::POST, "/auth/logout", &session).insert_header((CSRF_HEADER, csrf_token.clone()));
+        let resp = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(cookie(&resp, SESSION_COOKIE).unwrap().value(), "");
+        assert_eq!(cookie(&resp, CSRF_COOKIE).unwrap().value(), "");
+
+        // A copy of the cookie kept from before the logout holds a revoked access token
+        assert_eq!(status(request(actix_web::http::Method::GET, "/api/mfa/", &session)).await, 401);
+    }
+}
@@ -0,0 +1,122 @@
+// Tests for logout and access token revocation
+mod tests {
+    use actix_web::{test, web, App, HttpResponse, Responder, http::header};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use rust_api::middleware::{validator, ReqDataCreator};
+    use rust_api::models::users::usecases::{insert_new_user, search_user};
+
+    async fn dummy() -> impl Responder {
+        HttpResponse::Ok().body("Hey there!")
+    }
+
+    fn create_user_token(pool: &rust_api::DbPool, username: &str) -> String {
+        let mut conn = pool.get().unwrap();
+        let user = match search_user(&mut conn, username).unwrap().into_iter().next() {
+            Some(user) => user,
+            None => insert_new_user(&mut conn, username.to_string(), None, None, None, None, None).unwrap(),
+        };
+        let config = rust_api::config::get_config().unwrap();
+        rust_api::jwt::issue_access_token(&config, user.id, &user.login_id, &[]).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_logout_revokes_access_token() {
+        let pool = rust_api::create_test_connection_pool();
+        let token = create_user_token(&pool, "logout_user");
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .configure(rust_api::services::auth::config)
+                .service(
+                    web::scope("/api")
+                        .wrap(ReqDataCreator)
+                        .wrap(HttpAuthentication::bearer(validator))
+                        .route("/protected", web::get().to(dummy))
+                )
+        ).await;
+
+        let protected = || test::TestRequest::get()
+            .uri("/api/protected")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+
+        let resp = test::call_service(&app, protected()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 204);
+
+        let resp = test::call_service(&app, protected()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_req_data_creator_rejects_revoked_token() {
+        use rust_api::middleware::UserClaims;
+        use rust_api::revocation::RevocationStore;
+
+        let pool = rust_api::create_test_connection_pool();
+        let token = create_user_token(&pool, "revoked_user");
+        {
+            let config = rust_api::config::get_config().unwrap();
+            let claims: UserClaims = rust_api::jwt::decode_access_token(&config, &token).unwrap().claims;
+            let mut conn = pool.get().unwrap();
+            RevocationStore::revoke(&mut conn, &claims).unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .wrap(ReqDataCreator)
+                .route("/test", web::get().to(dummy))
+        ).await;
+
+        let req = test::TestRequest::get()
+            .uri("/test")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let resp = test::try_call_service(&app, req).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_logout_without_token() {
+        let pool = rust_api::create_test_connection_pool();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .configure(rust_api::services::auth::config)
+        ).await;
+
+        let req = test::TestRequest::post().uri("/auth/logout").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_purge_does_not_depend_on_session_time_zone() {
+        use chrono::{TimeDelta, Utc};
+        use diesel::{Connection, RunQueryDsl};
+        use rust_api::models::revoked_tokens::usecases::{insert_revoked_token, is_token_revoked, purge_expired_revocations};
+
+        let pool = rust_api::create_test_connection_pool();
+        let user = {
+            let mut conn = pool.get().unwrap();
+            insert_new_user(&mut conn, format!("tzrevoked_{}", Utc::now().timestamp_nanos_opt().unwrap()), None, None, None, None, None).unwrap()
+        };
+        // A connection of its own, so the time zone does not leak into the pool
+        let config = rust_api::config::get_config().unwrap();
+        let mut conn = rust_api::DbConnection::establish(&config.test_database_url).unwrap();
+        diesel::sql_query("SET TIME ZONE 'Asia/Tokyo'").execute(&mut conn).unwrap();
+
+        let jti = uuid::Uuid::new_v4().to_string();
+        insert_revoked_token(&mut conn, &jti, user.id, Utc::now().naive_utc() + TimeDelta::hours(1)).unwrap();
+        purge_expired_revocations(&mut conn).unwrap();
+        assert!(is_token_revoked(&mut conn, &jti).unwrap(), "revocation purged before the token expired");
+    }
+}
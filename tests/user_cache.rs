@@ -0,0 +1,93 @@
+// Tests for resolving the current user through the user cache
+mod tests {
+    use actix_web::{web, App, HttpResponse, Responder, http::header};
+    use chrono::Utc;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use rust_api::DbConnection;
+    use rust_api::middleware::{ApiReqeustData, ReqDataCreator};
+    use rust_api::models::users::usecases::sync_directory_user;
+    use rust_api::revocation::RevocationStore;
+    use rust_api::user_cache::{UserCache, UserCachePolicy};
+    use std::time::Duration;
+
+    fn unique(prefix: &str) -> String {
+        format!("{}_{}", prefix, Utc::now().timestamp_nanos_opt().unwrap())
+    }
+
+    async fn whoami(req_data: web::ReqData<ApiReqeustData>) -> impl Responder {
+        HttpResponse::Ok().body(req_data.current_user().map(|user| user.login_id.clone()).unwrap_or_default())
+    }
+
+    #[actix_web::test]
+    async fn test_user_updates_invalidate_cache() {
+        let pool = rust_api::create_test_connection_pool();
+        let mut conn = pool.get().unwrap();
+        let username = unique("cacheuser");
+        let policy = UserCachePolicy { ttl_secs: 60, max_entries: 100 };
+
+        let user = sync_directory_user(&mut conn, username.clone(), None, None, None, Some("before@example.com".to_string()), None).unwrap().user;
+        let found = UserCache::find_login(&mut conn, user.id, &username, &policy).unwrap().unwrap();
+        assert_eq!(found.email.as_deref(), Some("before@example.com"));
+        assert_eq!(UserCache::cached(user.id).map(|user| user.login_id), Some(username.clone()));
+        // Entries answer only for the login id they were found for
+        assert!(UserCache::cached_login(user.id, "someone_else").is_none());
+
+        sync_directory_user(&mut conn, username.clone(), None, None, None, Some("after@example.com".to_string()), None).unwrap();
+        assert!(UserCache::cached(user.id).is_none());
+        let found = UserCache::find(&mut conn, user.id, &policy).unwrap().unwrap();
+        assert_eq!(found.email.as_deref(), Some("after@example.com"));
+
+        // Without a TTL nothing is cached
+        UserCache::invalidate(user.id);
+        UserCache::find(&mut conn, user.id, &UserCachePolicy { ttl_secs: 0, ..policy }).unwrap();
+        assert!(UserCache::cached(user.id).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_exhausted_pool_returns_service_unavailable() {
+        let config = rust_api::config::get_config().unwrap();
+        let username = unique("pooluser");
+        let user = {
+            let mut conn = rust_api::create_test_connection_pool().get().unwrap();
+            sync_directory_user(&mut conn, username.clone(), None, None, None, None, None).unwrap().user
+        };
+        let token = rust_api::jwt::issue_access_token(&config, user.id, &username, &[]).unwrap();
+        let claims = rust_api::jwt::decode_access_token(&config, &token).unwrap().claims;
+
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(200))
+            .build(ConnectionManager::<DbConnection>::new(&config.test_database_url))
+            .unwrap();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(web::scope("/whoami").wrap(ReqDataCreator).route("", web::get().to(whoami)))
+        ).await;
+        let request = || actix_web::test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+
+        // Hold the only connection, after using it to cache that the token is not revoked
+        let mut held = pool.get().unwrap();
+        assert!(!RevocationStore::is_revoked(&mut held, &claims, 60).unwrap());
+        let resp = actix_web::test::try_call_service(&app, request()).await;
+        assert_eq!(resp.map(|r| r.status()).unwrap_or_else(|e| e.as_response_error().status_code()).as_u16(), 503);
+
+        // Requests without a token do not need the database
+        let req = actix_web::test::TestRequest::get().uri("/whoami").to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status().as_u16(), 200);
+
+        drop(held);
+        let resp = actix_web::test::call_service(&app, request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(actix_web::test::read_body(resp).await, username.as_bytes());
+
+        // Once cached, the user is resolved without a connection
+        let _held = pool.get().unwrap();
+        let resp = actix_web::test::call_service(&app, request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(actix_web::test::read_body(resp).await, username.as_bytes());
+    }
+}